@@ -1,11 +1,16 @@
-use std::sync::Arc;
+use std::{ collections::HashSet, sync::Arc };
 
 use axum::extract::ws::Message;
 use tokio::sync::{ mpsc::{ self, Sender, UnboundedSender }, Mutex };
 use uuid::Uuid;
 
 use crate::{
-  database::connect_db,
+  backfill::backfill_metadata,
+  blobs::{ collect_orphaned_blobs, migrate_legacy_objects },
+  database::{ connect_db, migrate_database },
+  presigned::expire_staged_uploads,
+  resumable::expire_upload_sessions,
+  signed_urls::url_signing_key,
   storage::connect_s3,
   sync_engine::{ SyncEnvelope, SyncMessage },
 };
@@ -25,11 +30,17 @@ pub struct AppStateInner {
   pub sync_clients: Arc<Mutex<Vec<SyncClient>>>,
   pub notify_tx: Sender<SyncMessage>,
   pub server_id: Uuid,
+  /// Key for the signed `/kit` links handed out in CSS.
+  pub url_signing_key: [u8; 32],
+  /// Families waiting for their specimens to be regenerated.
+  pub specimen_jobs: Mutex<HashSet<(Uuid, String)>>,
 }
 
 pub async fn create_app_state() -> Result<Arc<AppStateInner>, Box<dyn std::error::Error>> {
+  let url_signing_key = url_signing_key()?;
   let s3_client = connect_s3().await?;
   let db_client = connect_db().await?;
+  migrate_database(&db_client).await?;
   let sync_clients: Arc<Mutex<Vec<SyncClient>>> = Arc::new(Mutex::new(Vec::new()));
   let (notify_tx, mut notify_rx) = mpsc::channel::<SyncMessage>(100);
   let sync_clients_clone = sync_clients.clone();
@@ -62,13 +73,21 @@ pub async fn create_app_state() -> Result<Arc<AppStateInner>, Box<dyn std::error
     }
   });
 
-  Ok(
-    Arc::new(AppStateInner {
-      s3_client,
-      db_client,
-      sync_clients,
-      notify_tx,
-      server_id,
-    })
-  )
+  let state = Arc::new(AppStateInner {
+    s3_client,
+    db_client,
+    sync_clients,
+    notify_tx,
+    server_id,
+    url_signing_key,
+    specimen_jobs: Mutex::new(HashSet::new()),
+  });
+
+  tokio::spawn(migrate_legacy_objects(state.clone()));
+  tokio::spawn(collect_orphaned_blobs(state.clone()));
+  tokio::spawn(backfill_metadata(state.clone()));
+  tokio::spawn(expire_upload_sessions(state.clone()));
+  tokio::spawn(expire_staged_uploads(state.clone()));
+
+  Ok(state)
 }
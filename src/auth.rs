@@ -17,12 +17,6 @@ pub struct LoginRequest {
   password: String,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct RegisterRequest {
-  email: String,
-  password: String,
-}
-
 #[derive(Serialize, Deserialize)]
 struct AuthResponse {
   access_token: Option<String>,
@@ -0,0 +1,113 @@
+//! Re-extracts metadata for fonts recorded by an older extractor. Columns
+//! added since a font was uploaded only hold their defaults until this reads
+//! the stored file once and upserts its rows. Progress is kept per row in
+//! `metadata_version`, so an interrupted run picks up where it stopped.
+
+use log::{ error, info, warn };
+use uuid::Uuid;
+
+use crate::{
+  app_state::AppState,
+  blobs::fetch_library_file,
+  database::{
+    advisory_unlock,
+    get_stale_metadata,
+    insert_metadata,
+    mark_metadata_current,
+    try_advisory_lock,
+  },
+  metadata::extract_metadata,
+  storage::font_records_for,
+};
+
+const BACKFILL_LOCK: i64 = 0x666f_6e74_0001;
+const BACKFILL_BATCH_SIZE: i64 = 100;
+
+enum Backfilled {
+  Updated(usize),
+  /// The file can't be read or parsed; retrying won't help.
+  Unreadable,
+}
+
+async fn backfill_file(
+  state: &AppState,
+  user_id: &Uuid,
+  object_path: &str,
+  checksum: String
+) -> Result<Backfilled, String> {
+  let data = fetch_library_file(state, user_id, object_path).await.map_err(|e| e.to_string())?;
+  let Some(data) = data else {
+    warn!("No stored file for {}, leaving its metadata as is", object_path);
+    return Ok(Backfilled::Unreadable);
+  };
+
+  let extracted = tokio::task
+    ::spawn_blocking(move || extract_metadata(&data, &checksum).map_err(|e| e.to_string())).await
+    .map_err(|e| e.to_string())?;
+  let faces = match extracted {
+    Ok(faces) => faces,
+    Err(e) => {
+      warn!("Failed to re-extract metadata for {}: {}", object_path, e);
+      return Ok(Backfilled::Unreadable);
+    }
+  };
+
+  let records = font_records_for(object_path, faces);
+  insert_metadata(&state.db_client, user_id, &records).await.map_err(|e| e.to_string())?;
+
+  Ok(Backfilled::Updated(records.len()))
+}
+
+/// Walks every out-of-date file once. Files that fail for a transient reason
+/// are skipped and tried again on the next start.
+pub async fn backfill_metadata(state: AppState) {
+  match try_advisory_lock(&state.db_client, BACKFILL_LOCK).await {
+    Ok(true) => {}
+    Ok(false) => {
+      info!("Metadata backfill is running on another instance.");
+      return;
+    }
+    Err(e) => {
+      error!("Failed to take the metadata backfill lock: {}", e);
+      return;
+    }
+  }
+
+  let mut cursor = (Uuid::nil(), String::new());
+  let mut updated = 0;
+  loop {
+    let stale = match get_stale_metadata(&state.db_client, &cursor, BACKFILL_BATCH_SIZE).await {
+      Ok(stale) => stale,
+      Err(e) => {
+        error!("Failed to look up fonts to backfill: {}", e);
+        break;
+      }
+    };
+    let Some((user_id, object_path, _)) = stale.last() else {
+      break;
+    };
+    cursor = (*user_id, object_path.clone());
+
+    for (user_id, object_path, checksum) in stale {
+      match backfill_file(&state, &user_id, &object_path, checksum).await {
+        Ok(Backfilled::Updated(faces)) => {
+          updated += 1;
+          info!("Backfilled metadata for {} ({} face(s))", object_path, faces);
+        }
+        Ok(Backfilled::Unreadable) => {
+          if let Err(e) = mark_metadata_current(&state.db_client, &user_id, &object_path).await {
+            error!("Failed to record backfill of {}: {}", object_path, e);
+          }
+        }
+        Err(e) => error!("Failed to backfill metadata for {}: {}", object_path, e),
+      }
+    }
+  }
+
+  if updated > 0 {
+    info!("Metadata backfill finished, {} file(s) updated.", updated);
+  }
+  if let Err(e) = advisory_unlock(&state.db_client, BACKFILL_LOCK).await {
+    error!("Failed to release the metadata backfill lock: {}", e);
+  }
+}
@@ -0,0 +1,21 @@
+//! Big-endian readers for the parts of the sfnt format that ttf-parser does
+//! not expose.
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+  let bytes = data.get(offset..offset + 2)?;
+  Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+  read_u16(data, offset).map(|v| v as i16)
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+  let bytes = data.get(offset..offset + 4)?;
+  Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads a 16.16 fixed-point number.
+pub fn read_fixed(data: &[u8], offset: usize) -> Option<f32> {
+  read_u32(data, offset).map(|v| (v as i32 as f32) / 65536.0)
+}
@@ -0,0 +1,471 @@
+//! Content-addressable storage for font files. Bytes are stored once under
+//! their blake3 checksum however many paths or users hold the same file; the
+//! `fonts` table maps library paths to blobs and a trigger keeps each blob's
+//! reference count. Blobs nothing refers to any more are collected, together
+//! with their cached renditions, after a grace period, which covers uploads
+//! that found a blob stored and are about to record a path for it. Files
+//! stored before blobs existed are moved in by a background job; until then
+//! readers fall back to their old objects.
+
+use std::time::Duration;
+
+use aws_sdk_s3::{
+  error::{ ProvideErrorMetadata, SdkError },
+  operation::head_object::{ HeadObjectError, HeadObjectOutput },
+};
+use axum::http::StatusCode;
+use log::{ error, info, warn };
+use percent_encoding::{ utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC };
+use uuid::Uuid;
+
+use crate::{
+  app_state::AppState,
+  database::{
+    advisory_unlock,
+    claim_blob,
+    delete_blob_record,
+    get_path_checksum,
+    get_unmoved_paths,
+    get_unstored_blobs,
+    mark_blob_missing,
+    mark_blob_stored,
+    mark_blob_unstored,
+    mark_orphaned_blobs,
+    try_advisory_lock,
+  },
+  storage::{ fetch_object, upload_failure, DERIVED_PREFIX, S3_BUCKET },
+};
+
+static BLOB_PREFIX: &str = "blobs";
+const GC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+const GC_BATCH_SIZE: i64 = 500;
+/// How long an upload waits for the collector to finish with a blob it needs.
+const CLAIM_ATTEMPTS: u32 = 10;
+const CLAIM_RETRY_DELAY: Duration = Duration::from_millis(200);
+const MIGRATION_LOCK: i64 = 0x666f_6e74_0002;
+const MIGRATION_BATCH_SIZE: i64 = 100;
+
+/// Everything but unreserved characters and `/` is escaped in a `CopySource`.
+const COPY_SOURCE_ESCAPED: &AsciiSet = &NON_ALPHANUMERIC
+  .remove(b'/')
+  .remove(b'-')
+  .remove(b'_')
+  .remove(b'.')
+  .remove(b'~');
+
+pub fn blob_key(checksum: &str) -> String {
+  format!("{}/{}", BLOB_PREFIX, checksum)
+}
+
+/// `CopySource` has to be URL-encoded, and library paths may hold anything.
+pub fn copy_source(key: &str) -> String {
+  format!("{}/{}", S3_BUCKET, utf8_percent_encode(key, COPY_SOURCE_ESCAPED))
+}
+
+fn blob_db_failure(checksum: &str, e: Box<dyn std::error::Error>) -> (StatusCode, String) {
+  error!("Failed to update blob {}: {}", checksum, e);
+  (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string())
+}
+
+/// Claims the blob for an accepted upload. Returns the key to write the bytes
+/// to, or `None` when an earlier upload already stored them; after writing,
+/// call [`blob_written`].
+pub async fn reserve_blob(
+  state: &AppState,
+  checksum: &str
+) -> Result<Option<String>, (StatusCode, String)> {
+  let mut attempts = 0;
+  loop {
+    let blob_state = claim_blob(&state.db_client, checksum).await.map_err(|e|
+      blob_db_failure(checksum, e)
+    )?;
+    match blob_state.as_str() {
+      "stored" => {
+        info!("Blob {} is already stored", checksum);
+        return Ok(None);
+      }
+      "deleting" if attempts < CLAIM_ATTEMPTS => {
+        attempts += 1;
+        tokio::time::sleep(CLAIM_RETRY_DELAY).await;
+      }
+      "deleting" => {
+        return Err((
+          StatusCode::SERVICE_UNAVAILABLE,
+          "Storage for this file is being cleaned up, please retry".to_string(),
+        ));
+      }
+      _ => {
+        return Ok(Some(blob_key(checksum)));
+      }
+    }
+  }
+}
+
+pub async fn blob_written(state: &AppState, checksum: &str) -> Result<(), (StatusCode, String)> {
+  mark_blob_stored(&state.db_client, checksum).await.map_err(|e| blob_db_failure(checksum, e))
+}
+
+/// Copies an object that is already in the bucket, such as a staged upload,
+/// into a blob.
+pub async fn copy_to_blob(
+  state: &AppState,
+  source_key: &str,
+  blob_key: &str,
+  content_type: &str,
+  file_name: &str
+) -> Result<(), (StatusCode, String)> {
+  state.s3_client
+    .copy_object()
+    .bucket(S3_BUCKET)
+    .copy_source(copy_source(source_key))
+    .key(blob_key)
+    .content_type(content_type)
+    .send().await
+    .map_err(|e| upload_failure(file_name, e))?;
+
+  Ok(())
+}
+
+/// Keys that may hold a library file, in the order to try them: its blob, the
+/// `{user_id}/{path}` object it had before the blob store existed, and the
+/// blob again in case the migration moved it in between.
+fn stored_file_keys(object_path: &str, checksum: &str) -> [String; 3] {
+  let key = blob_key(checksum);
+  [key.clone(), object_path.to_string(), key]
+}
+
+/// Reads a library file's bytes. Returns `None` when they are stored nowhere.
+pub async fn fetch_stored_file(
+  state: &AppState,
+  object_path: &str,
+  checksum: &str
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+  for key in stored_file_keys(object_path, checksum) {
+    if let Some(data) = fetch_object(state, &key).await? {
+      return Ok(Some(data));
+    }
+  }
+
+  Ok(None)
+}
+
+/// Finds the object holding a library file and returns its key and head, or
+/// `None` when the bytes are stored nowhere.
+pub async fn head_stored_file(
+  state: &AppState,
+  object_path: &str,
+  checksum: &str
+) -> Result<Option<(String, HeadObjectOutput)>, SdkError<HeadObjectError>> {
+  for key in stored_file_keys(object_path, checksum) {
+    match state.s3_client.head_object().bucket(S3_BUCKET).key(&key).send().await {
+      Ok(head) => {
+        return Ok(Some((key, head)));
+      }
+      Err(e) if e.as_service_error().map(|e| e.is_not_found()) == Some(true) => {}
+      Err(e) => {
+        return Err(e);
+      }
+    }
+  }
+
+  Ok(None)
+}
+
+/// Reads the file at a path in a user's library. Returns `None` when there is
+/// no such file.
+pub async fn fetch_library_file(
+  state: &AppState,
+  user_id: &Uuid,
+  object_path: &str
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+  let checksum = get_path_checksum(&state.db_client, user_id, object_path).await.map_err(|e|
+    e.to_string()
+  )?;
+
+  match checksum {
+    Some(checksum) => fetch_stored_file(state, object_path, &checksum).await,
+    None => Ok(None),
+  }
+}
+
+enum Migrated {
+  Moved,
+  /// None of the paths had an object left; the blob is flagged `missing`.
+  Missing,
+  /// Left pending and tried again on the next start.
+  Failed,
+}
+
+async fn migrate_blob(state: &AppState, checksum: &str, object_paths: &[String]) -> Migrated {
+  let key = blob_key(checksum);
+  let mut copied = false;
+  let mut all_missing = true;
+  for object_path in object_paths {
+    match
+      state.s3_client
+        .copy_object()
+        .bucket(S3_BUCKET)
+        .copy_source(copy_source(object_path))
+        .key(&key)
+        .send().await
+    {
+      Ok(_) => {
+        copied = true;
+        break;
+      }
+      Err(e) => {
+        all_missing &= e.code() == Some("NoSuchKey");
+        warn!("Failed to copy {} to {}: {}", object_path, key, e);
+      }
+    }
+  }
+
+  if !copied {
+    if !all_missing {
+      return Migrated::Failed;
+    }
+    error!("No stored object left for blob {} ({})", checksum, object_paths.join(", "));
+    if let Err(e) = mark_blob_missing(&state.db_client, checksum).await {
+      error!("Failed to flag blob {} as missing: {}", checksum, e);
+      return Migrated::Failed;
+    }
+    return Migrated::Missing;
+  }
+  if let Err(e) = mark_blob_stored(&state.db_client, checksum).await {
+    error!("Failed to mark blob {} as stored: {}", checksum, e);
+    return Migrated::Failed;
+  }
+
+  for object_path in object_paths {
+    if
+      let Err(e) = state.s3_client
+        .delete_object()
+        .bucket(S3_BUCKET)
+        .key(object_path)
+        .send().await
+    {
+      warn!("Failed to delete {} after moving it to {}: {}", object_path, key, e);
+    }
+  }
+
+  Migrated::Moved
+}
+
+/// Deletes objects under `{user_id}/` that no library path still needs: files
+/// deleted, replaced or overwritten before the migration moved their bytes.
+/// Objects of paths whose blob is not stored yet are kept for the next run.
+async fn remove_orphaned_legacy_objects(state: &AppState) {
+  let mut user_prefixes = Vec::new();
+  let mut pages = state.s3_client
+    .list_objects_v2()
+    .bucket(S3_BUCKET)
+    .delimiter("/")
+    .into_paginator()
+    .send();
+  while let Some(page) = pages.next().await {
+    let page = match page {
+      Ok(page) => page,
+      Err(e) => {
+        warn!("Failed to list the bucket for legacy objects: {}", e);
+        return;
+      }
+    };
+    user_prefixes.extend(
+      page
+        .common_prefixes()
+        .iter()
+        .filter_map(|prefix| prefix.prefix())
+        .filter(|prefix| Uuid::parse_str(prefix.trim_end_matches('/')).is_ok())
+        .map(str::to_string)
+    );
+  }
+
+  let mut removed = 0;
+  for prefix in user_prefixes {
+    let mut pages = state.s3_client
+      .list_objects_v2()
+      .bucket(S3_BUCKET)
+      .prefix(&prefix)
+      .into_paginator()
+      .send();
+    while let Some(page) = pages.next().await {
+      let page = match page {
+        Ok(page) => page,
+        Err(e) => {
+          warn!("Failed to list legacy objects under {}: {}", prefix, e);
+          break;
+        }
+      };
+      let keys: Vec<String> = page.contents()
+        .iter()
+        .filter_map(|object| object.key())
+        .map(str::to_string)
+        .collect();
+      let unmoved = match get_unmoved_paths(&state.db_client, &keys).await {
+        Ok(unmoved) => unmoved,
+        Err(e) => {
+          error!("Failed to look up paths for legacy objects under {}: {}", prefix, e);
+          return;
+        }
+      };
+      for key in keys.iter().filter(|key| !unmoved.contains(key)) {
+        match state.s3_client.delete_object().bucket(S3_BUCKET).key(key).send().await {
+          Ok(_) => {
+            removed += 1;
+          }
+          Err(e) => warn!("Failed to delete legacy object {}: {}", key, e),
+        }
+      }
+    }
+  }
+
+  if removed > 0 {
+    info!("Removed {} legacy object(s) no library path refers to.", removed);
+  }
+}
+
+/// Moves files stored under `{user_id}/{path}` before blobs existed into the
+/// blob store and removes the old objects, including those of files deleted
+/// or replaced before they were moved. Each blob's state records whether it
+/// was moved, so an interrupted run picks up where it stopped and files that
+/// fail to move are tried again on the next start.
+pub async fn migrate_legacy_objects(state: AppState) {
+  match try_advisory_lock(&state.db_client, MIGRATION_LOCK).await {
+    Ok(true) => {}
+    Ok(false) => {
+      info!("Blob store migration is running on another instance.");
+      return;
+    }
+    Err(e) => {
+      error!("Failed to take the blob store migration lock: {}", e);
+      return;
+    }
+  }
+
+  let mut cursor = String::new();
+  let (mut moved, mut missing) = (0, 0);
+  loop {
+    let unstored = match get_unstored_blobs(&state.db_client, &cursor, MIGRATION_BATCH_SIZE).await {
+      Ok(unstored) => unstored,
+      Err(e) => {
+        error!("Failed to look up files outside the blob store: {}", e);
+        break;
+      }
+    };
+    let Some((checksum, _)) = unstored.last() else {
+      break;
+    };
+    cursor = checksum.clone();
+
+    for (checksum, object_paths) in unstored {
+      match migrate_blob(&state, &checksum, &object_paths).await {
+        Migrated::Moved => {
+          moved += 1;
+        }
+        Migrated::Missing => {
+          missing += 1;
+        }
+        Migrated::Failed => {}
+      }
+    }
+  }
+
+  if moved + missing > 0 {
+    info!("Blob store migration finished, {} file(s) moved, {} missing.", moved, missing);
+  }
+  remove_orphaned_legacy_objects(&state).await;
+  if let Err(e) = advisory_unlock(&state.db_client, MIGRATION_LOCK).await {
+    error!("Failed to release the blob store migration lock: {}", e);
+  }
+}
+
+/// Deletes the renditions cached for a collected blob: format conversions
+/// under `derived/{checksum}.*` and previews under `derived/previews/{checksum}/`.
+/// Failures are only logged; renditions are only reached through a path that
+/// refers to their blob.
+async fn remove_derived_objects(state: &AppState, checksum: &str) {
+  let prefixes = [
+    format!("{}/{}.", DERIVED_PREFIX, checksum),
+    format!("{}/previews/{}/", DERIVED_PREFIX, checksum),
+  ];
+
+  for prefix in prefixes {
+    let mut pages = state.s3_client
+      .list_objects_v2()
+      .bucket(S3_BUCKET)
+      .prefix(&prefix)
+      .into_paginator()
+      .send();
+
+    while let Some(page) = pages.next().await {
+      let page = match page {
+        Ok(page) => page,
+        Err(e) => {
+          warn!("Failed to list derived objects under {}: {}", prefix, e);
+          break;
+        }
+      };
+      for key in page.contents().iter().filter_map(|object| object.key()) {
+        if let Err(e) = state.s3_client.delete_object().bucket(S3_BUCKET).key(key).send().await {
+          warn!("Failed to delete derived object {}: {}", key, e);
+        }
+      }
+    }
+  }
+}
+
+/// Deletes the object of a blob the collector claimed. When S3 refuses, the
+/// blob goes back up for collection on the next run.
+async fn remove_blob(state: &AppState, checksum: &str) {
+  let key = blob_key(checksum);
+
+  if let Err(e) = state.s3_client.delete_object().bucket(S3_BUCKET).key(&key).send().await {
+    warn!("Failed to delete orphaned blob {}: {}", key, e);
+    if let Err(e) = mark_blob_unstored(&state.db_client, checksum).await {
+      error!("Failed to release blob {}: {}", checksum, e);
+    }
+    return;
+  }
+
+  let deleted = delete_blob_record(&state.db_client, checksum).await.map_err(|e| e.to_string());
+  match deleted {
+    Ok(true) => {
+      info!("Deleted orphaned blob {}", key);
+      remove_derived_objects(state, checksum).await;
+    }
+    Ok(false) => {
+      // Only an upload that stalled for longer than the grace period gets
+      // here. Its bytes are gone; `finish_upload` sees the blob pending again
+      // and writes them back.
+      warn!("Blob {} was referenced again while being deleted", key);
+      if let Err(e) = mark_blob_unstored(&state.db_client, checksum).await {
+        error!("Failed to release blob {}: {}", checksum, e);
+      }
+    }
+    Err(e) => error!("Failed to delete blob record {}: {}", checksum, e),
+  }
+}
+
+/// Periodically deletes blobs no library path has referenced for the grace
+/// period.
+pub async fn collect_orphaned_blobs(state: AppState) {
+  let mut interval = tokio::time::interval(GC_INTERVAL);
+
+  loop {
+    interval.tick().await;
+
+    let orphaned = match
+      mark_orphaned_blobs(&state.db_client, GC_GRACE_PERIOD.as_secs_f64(), GC_BATCH_SIZE).await
+    {
+      Ok(orphaned) => orphaned,
+      Err(e) => {
+        error!("Failed to look up orphaned blobs: {}", e);
+        continue;
+      }
+    };
+    for checksum in orphaned {
+      remove_blob(&state, &checksum).await;
+    }
+  }
+}
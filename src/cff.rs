@@ -0,0 +1,787 @@
+//! Subsetting of `CFF ` and `CFF2` outline tables with stable glyph IDs, to
+//! match what `subset` does for TrueType. Charstrings of glyphs outside the
+//! subset are emptied and, in `CFF `, so are the subroutines no kept glyph
+//! calls, which keeps subroutine numbering and biases intact. `CFF2` keeps its
+//! subroutines, since following `blend` operands needs the variation store.
+//! The tables are laid out afresh with every offset rewritten.
+
+use std::collections::BTreeSet;
+
+use crate::binary::{ read_u16, read_u32 };
+
+type Error = Box<dyn std::error::Error>;
+
+const MALFORMED: &str = "Malformed CFF table";
+
+const OP_CHARSET: u16 = 15;
+const OP_ENCODING: u16 = 16;
+const OP_CHARSTRINGS: u16 = 17;
+const OP_PRIVATE: u16 = 18;
+const OP_SUBRS: u16 = 19;
+const OP_VSTORE: u16 = 24;
+const OP_FD_ARRAY: u16 = 1236;
+const OP_FD_SELECT: u16 = 1237;
+
+/// Type 2 `endchar`, the smallest valid charstring in a `CFF ` table.
+const EMPTY_CHARSTRING: &[u8] = &[14];
+/// Subroutine calls nest at most this deep.
+const MAX_SUBR_DEPTH: usize = 10;
+
+/// SIDs of the Standard Encoding for codes 161 to 255, which `seac`-style
+/// `endchar` uses to name its base and accent glyphs. Codes 32 to 126 map to
+/// SIDs 1 to 95; everything else is unencoded.
+const STANDARD_ENCODING_HIGH: [u16; 95] = [
+  96, 97, 98, 99, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 0, 111, 112, 113, 114, 0,
+  115, 116, 117, 118, 119, 120, 121, 122, 0, 123, 0, 124, 125, 126, 127, 128, 129, 130, 131, 0,
+  132, 133, 0, 134, 135, 136, 137, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 138, 0, 139, 0,
+  0, 0, 0, 140, 141, 142, 143, 0, 0, 0, 0, 0, 144, 0, 0, 0, 145, 0, 0, 146, 147, 148, 149, 0, 0,
+  0, 0,
+];
+
+#[derive(Clone)]
+enum Operand {
+  Int(i32),
+  /// A real number, kept in its encoded form.
+  Real(Vec<u8>),
+  /// An offset, always written in five bytes so a DICT's size is known before
+  /// the data it points to is laid out.
+  Offset(usize),
+}
+
+#[derive(Clone)]
+struct DictEntry {
+  /// Two-byte operators are stored as `1200 + second byte`.
+  op: u16,
+  operands: Vec<Operand>,
+}
+
+struct Private<'a> {
+  dict: Vec<DictEntry>,
+  subrs: Vec<&'a [u8]>,
+}
+
+struct Cff<'a> {
+  cff2: bool,
+  header: &'a [u8],
+  /// Name and String INDEXes of a `CFF ` table, copied as they are.
+  names: &'a [u8],
+  strings: &'a [u8],
+  top: Vec<DictEntry>,
+  global_subrs: Vec<&'a [u8]>,
+  charstrings: Vec<&'a [u8]>,
+  charset: Option<&'a [u8]>,
+  encoding: Option<&'a [u8]>,
+  fd_select: Option<&'a [u8]>,
+  vstore: Option<&'a [u8]>,
+  /// Font DICTs of CID-keyed and `CFF2` fonts, empty otherwise.
+  font_dicts: Vec<Vec<DictEntry>>,
+  /// One per Font DICT, or the single Private DICT of a name-keyed font.
+  privates: Vec<Private<'a>>,
+}
+
+fn read_index(data: &[u8], offset: usize, cff2: bool) -> Option<(Vec<&[u8]>, usize)> {
+  let (count, mut pos) = if cff2 {
+    (read_u32(data, offset)? as usize, offset + 4)
+  } else {
+    (usize::from(read_u16(data, offset)?), offset + 2)
+  };
+  if count == 0 {
+    return Some((Vec::new(), pos));
+  }
+
+  let off_size = usize::from(*data.get(pos)?);
+  pos += 1;
+  if !(1..=4).contains(&off_size) {
+    return None;
+  }
+  let read_offset = |index: usize| -> Option<usize> {
+    let start = pos + index * off_size;
+    let bytes = data.get(start..start + off_size)?;
+    Some(bytes.iter().fold(0, |value, &byte| (value << 8) | usize::from(byte)))
+  };
+  // Offsets count from the byte before the object data.
+  let base = (pos + (count + 1) * off_size).checked_sub(1)?;
+
+  let mut items = Vec::with_capacity(count);
+  for index in 0..count {
+    items.push(data.get(base + read_offset(index)?..base + read_offset(index + 1)?)?);
+  }
+  Some((items, base + read_offset(count)?))
+}
+
+fn write_index<T: AsRef<[u8]>>(items: &[T], cff2: bool) -> Vec<u8> {
+  let mut index = Vec::new();
+  if cff2 {
+    index.extend_from_slice(&(items.len() as u32).to_be_bytes());
+  } else {
+    index.extend_from_slice(&(items.len() as u16).to_be_bytes());
+  }
+  if items.is_empty() {
+    return index;
+  }
+
+  let data_len: usize = items.iter().map(|item| item.as_ref().len()).sum();
+  let off_size = match data_len + 1 {
+    len if len <= 0xff => 1,
+    len if len <= 0xffff => 2,
+    len if len <= 0xff_ffff => 3,
+    _ => 4,
+  };
+  index.push(off_size as u8);
+  let mut offset = 1usize;
+  for item in items.iter().map(AsRef::as_ref).chain([&[][..]]) {
+    index.extend_from_slice(&offset.to_be_bytes()[size_of::<usize>() - off_size..]);
+    offset += item.len();
+  }
+  for item in items {
+    index.extend_from_slice(item.as_ref());
+  }
+  index
+}
+
+fn index_len(item_lens: &[usize], cff2: bool) -> usize {
+  let count_len = if cff2 { 4 } else { 2 };
+  if item_lens.is_empty() {
+    return count_len;
+  }
+  let data_len: usize = item_lens.iter().sum();
+  let off_size = match data_len + 1 {
+    len if len <= 0xff => 1,
+    len if len <= 0xffff => 2,
+    len if len <= 0xff_ffff => 3,
+    _ => 4,
+  };
+  count_len + 1 + (item_lens.len() + 1) * off_size + data_len
+}
+
+fn parse_dict(data: &[u8]) -> Option<Vec<DictEntry>> {
+  let mut entries = Vec::new();
+  let mut operands = Vec::new();
+  let mut pos = 0;
+
+  while let Some(&b0) = data.get(pos) {
+    pos += 1;
+    match b0 {
+      28 => {
+        operands.push(Operand::Int(i32::from(read_u16(data, pos)? as i16)));
+        pos += 2;
+      }
+      29 => {
+        operands.push(Operand::Int(read_u32(data, pos)? as i32));
+        pos += 4;
+      }
+      30 => {
+        let start = pos - 1;
+        loop {
+          let byte = *data.get(pos)?;
+          pos += 1;
+          if byte & 0x0f == 0x0f || byte >> 4 == 0x0f {
+            break;
+          }
+        }
+        operands.push(Operand::Real(data[start..pos].to_vec()));
+      }
+      32..=246 => operands.push(Operand::Int(i32::from(b0) - 139)),
+      247..=250 => {
+        let b1 = *data.get(pos)?;
+        pos += 1;
+        operands.push(Operand::Int((i32::from(b0) - 247) * 256 + i32::from(b1) + 108));
+      }
+      251..=254 => {
+        let b1 = *data.get(pos)?;
+        pos += 1;
+        operands.push(Operand::Int(-(i32::from(b0) - 251) * 256 - i32::from(b1) - 108));
+      }
+      12 => {
+        let b1 = *data.get(pos)?;
+        pos += 1;
+        let op = 1200 + u16::from(b1);
+        entries.push(DictEntry { op, operands: std::mem::take(&mut operands) });
+      }
+      31 | 255 => {
+        return None;
+      }
+      _ => entries.push(DictEntry { op: u16::from(b0), operands: std::mem::take(&mut operands) }),
+    }
+  }
+
+  Some(entries)
+}
+
+fn write_dict(entries: &[DictEntry]) -> Vec<u8> {
+  let mut dict = Vec::new();
+  for entry in entries {
+    for operand in &entry.operands {
+      match operand {
+        Operand::Int(value @ -107..=107) => dict.push((value + 139) as u8),
+        Operand::Int(value @ 108..=1131) => {
+          let value = value - 108;
+          dict.extend_from_slice(&[(value >> 8) as u8 + 247, value as u8]);
+        }
+        Operand::Int(value @ -1131..=-108) => {
+          let value = -value - 108;
+          dict.extend_from_slice(&[(value >> 8) as u8 + 251, value as u8]);
+        }
+        Operand::Int(value @ -32768..=32767) => {
+          dict.push(28);
+          dict.extend_from_slice(&(*value as i16).to_be_bytes());
+        }
+        Operand::Int(value) => {
+          dict.push(29);
+          dict.extend_from_slice(&value.to_be_bytes());
+        }
+        Operand::Offset(value) => {
+          dict.push(29);
+          dict.extend_from_slice(&(*value as i32).to_be_bytes());
+        }
+        Operand::Real(encoded) => dict.extend_from_slice(encoded),
+      }
+    }
+    if entry.op >= 1200 {
+      dict.extend_from_slice(&[12, (entry.op - 1200) as u8]);
+    } else {
+      dict.push(entry.op as u8);
+    }
+  }
+  dict
+}
+
+fn dict_ints(entries: &[DictEntry], op: u16) -> Option<Vec<usize>> {
+  let entry = entries.iter().find(|entry| entry.op == op)?;
+  entry.operands
+    .iter()
+    .map(|operand| match operand {
+      Operand::Int(value) => usize::try_from(*value).ok(),
+      _ => None,
+    })
+    .collect()
+}
+
+fn set_operands(entries: &mut [DictEntry], op: u16, operands: Vec<Operand>) {
+  if let Some(entry) = entries.iter_mut().find(|entry| entry.op == op) {
+    entry.operands = operands;
+  }
+}
+
+fn charset_len(data: &[u8], offset: usize, glyph_count: usize) -> Option<usize> {
+  let format = *data.get(offset)?;
+  let mut pos = offset + 1;
+  if format == 0 {
+    return Some(1 + 2 * glyph_count.saturating_sub(1));
+  }
+
+  let mut covered = 1;
+  while covered < glyph_count {
+    let left = match format {
+      1 => usize::from(*data.get(pos + 2)?),
+      2 => usize::from(read_u16(data, pos + 2)?),
+      _ => {
+        return None;
+      }
+    };
+    pos += if format == 1 { 3 } else { 4 };
+    covered += left + 1;
+  }
+  Some(pos - offset)
+}
+
+fn encoding_len(data: &[u8], offset: usize) -> Option<usize> {
+  let format = *data.get(offset)?;
+  let count = usize::from(*data.get(offset + 1)?);
+  let mut len = match format & 0x7f {
+    0 => 2 + count,
+    1 => 2 + 2 * count,
+    _ => {
+      return None;
+    }
+  };
+  if format & 0x80 != 0 {
+    len += 1 + 3 * usize::from(*data.get(offset + len)?);
+  }
+  Some(len)
+}
+
+fn fd_select_len(data: &[u8], offset: usize, glyph_count: usize) -> Option<usize> {
+  match *data.get(offset)? {
+    0 => Some(1 + glyph_count),
+    3 => Some(5 + 3 * usize::from(read_u16(data, offset + 1)?)),
+    4 => Some(9 + 6 * (read_u32(data, offset + 1)? as usize)),
+    _ => None,
+  }
+}
+
+fn fd_index(fd_select: &[u8], glyph_id: u16) -> Option<usize> {
+  match *fd_select.first()? {
+    0 => fd_select.get(1 + usize::from(glyph_id)).map(|&fd| usize::from(fd)),
+    3 => {
+      let ranges = usize::from(read_u16(fd_select, 1)?);
+      (0..ranges).find_map(|range| {
+        let first = read_u16(fd_select, 3 + 3 * range)?;
+        let next = read_u16(fd_select, 6 + 3 * range)?;
+        (first..next).contains(&glyph_id).then(|| usize::from(fd_select[5 + 3 * range]))
+      })
+    }
+    4 => {
+      let ranges = read_u32(fd_select, 1)? as usize;
+      (0..ranges).find_map(|range| {
+        let first = read_u32(fd_select, 5 + 6 * range)?;
+        let next = read_u32(fd_select, 11 + 6 * range)?;
+        let fd = read_u16(fd_select, 9 + 6 * range)?;
+        (first..next).contains(&u32::from(glyph_id)).then_some(usize::from(fd))
+      })
+    }
+    _ => None,
+  }
+}
+
+fn parse_private<'a>(data: &'a [u8], dict: &[DictEntry], cff2: bool) -> Option<Private<'a>> {
+  let Some(private) = dict_ints(dict, OP_PRIVATE) else {
+    return Some(Private { dict: Vec::new(), subrs: Vec::new() });
+  };
+  let [size, offset] = private[..] else {
+    return None;
+  };
+  let entries = parse_dict(data.get(offset..offset + size)?)?;
+  let subrs = match dict_ints(&entries, OP_SUBRS).as_deref() {
+    Some(&[subrs_offset]) => read_index(data, offset + subrs_offset, cff2)?.0,
+    _ => Vec::new(),
+  };
+  Some(Private { dict: entries, subrs })
+}
+
+fn parse_cff(data: &[u8]) -> Option<Cff<'_>> {
+  let cff2 = *data.first()? == 2;
+  let header_size = usize::from(*data.get(2)?);
+  let header = data.get(..header_size)?;
+
+  let (names, strings, top, global_start) = if cff2 {
+    let top_len = usize::from(read_u16(data, 3)?);
+    let top = parse_dict(data.get(header_size..header_size + top_len)?)?;
+    (&[][..], &[][..], top, header_size + top_len)
+  } else {
+    let (_, names_end) = read_index(data, header_size, false)?;
+    let (top_dicts, top_end) = read_index(data, names_end, false)?;
+    let [top] = top_dicts[..] else {
+      return None;
+    };
+    let (_, strings_end) = read_index(data, top_end, false)?;
+    let names = &data[header_size..names_end];
+    (names, &data[top_end..strings_end], parse_dict(top)?, strings_end)
+  };
+  let (global_subrs, _) = read_index(data, global_start, cff2)?;
+
+  let charstrings_offset = *dict_ints(&top, OP_CHARSTRINGS)?.first()?;
+  let (charstrings, _) = read_index(data, charstrings_offset, cff2)?;
+  let glyph_count = charstrings.len();
+
+  let custom_offset = |op: u16, predefined: usize| {
+    dict_ints(&top, op)
+      .and_then(|values| values.first().copied())
+      .filter(|&offset| !cff2 && offset > predefined)
+  };
+  let charset = match custom_offset(OP_CHARSET, 2) {
+    Some(offset) => Some(data.get(offset..offset + charset_len(data, offset, glyph_count)?)?),
+    None => None,
+  };
+  let encoding = match custom_offset(OP_ENCODING, 1) {
+    Some(offset) => Some(data.get(offset..offset + encoding_len(data, offset)?)?),
+    None => None,
+  };
+  let fd_select = match dict_ints(&top, OP_FD_SELECT).as_deref() {
+    Some(&[offset]) => Some(data.get(offset..offset + fd_select_len(data, offset, glyph_count)?)?),
+    _ => None,
+  };
+  let vstore = match dict_ints(&top, OP_VSTORE).as_deref() {
+    Some(&[offset]) => Some(data.get(offset..offset + 2 + usize::from(read_u16(data, offset)?))?),
+    _ => None,
+  };
+
+  let font_dicts = match dict_ints(&top, OP_FD_ARRAY).as_deref() {
+    Some(&[offset]) => {
+      read_index(data, offset, cff2)?.0.into_iter().map(parse_dict).collect::<Option<Vec<_>>>()?
+    }
+    _ => Vec::new(),
+  };
+  let privates = if font_dicts.is_empty() {
+    vec![parse_private(data, &top, cff2)?]
+  } else {
+    font_dicts.iter().map(|dict| parse_private(data, dict, cff2)).collect::<Option<Vec<_>>>()?
+  };
+
+  Some(Cff {
+    cff2,
+    header,
+    names,
+    strings,
+    top,
+    global_subrs,
+    charstrings,
+    charset,
+    encoding,
+    fd_select,
+    vstore,
+    font_dicts,
+    privates,
+  })
+}
+
+fn subr_bias(count: usize) -> i32 {
+  if count < 1240 {
+    107
+  } else if count < 33900 {
+    1131
+  } else {
+    32768
+  }
+}
+
+enum Flow {
+  Continue,
+  Return,
+  End,
+}
+
+/// Follows a Type 2 charstring far enough to see which subroutines it calls
+/// and whether it ends in a `seac`-style accented character.
+struct CharstringWalker<'a> {
+  global_subrs: &'a [&'a [u8]],
+  local_subrs: &'a [&'a [u8]],
+  used_global: &'a mut BTreeSet<usize>,
+  used_local: &'a mut BTreeSet<usize>,
+  stack: Vec<i32>,
+  stems: usize,
+  /// Standard Encoding codes of the base and accent glyphs.
+  seac: Option<(i32, i32)>,
+}
+
+impl CharstringWalker<'_> {
+  /// Returns `None` for charstrings it can't follow, such as ones computing
+  /// subroutine numbers with the arithmetic operators.
+  fn walk(&mut self, charstring: &[u8], depth: usize) -> Option<Flow> {
+    if depth > MAX_SUBR_DEPTH {
+      return None;
+    }
+
+    let mut pos = 0;
+    while let Some(&b0) = charstring.get(pos) {
+      pos += 1;
+      match b0 {
+        28 => {
+          self.stack.push(i32::from(read_u16(charstring, pos)? as i16));
+          pos += 2;
+        }
+        32..=246 => self.stack.push(i32::from(b0) - 139),
+        247..=250 => {
+          let b1 = *charstring.get(pos)?;
+          pos += 1;
+          self.stack.push((i32::from(b0) - 247) * 256 + i32::from(b1) + 108);
+        }
+        251..=254 => {
+          let b1 = *charstring.get(pos)?;
+          pos += 1;
+          self.stack.push(-(i32::from(b0) - 251) * 256 - i32::from(b1) - 108);
+        }
+        // 16.16 fixed; only the integer part can name a subroutine.
+        255 => {
+          self.stack.push((read_u32(charstring, pos)? as i32) >> 16);
+          pos += 4;
+        }
+        // hstem, vstem, hstemhm, vstemhm; an odd count includes the width.
+        1 | 3 | 18 | 23 => {
+          self.stems += self.stack.len() / 2;
+          self.stack.clear();
+        }
+        // hintmask, cntrmask, with any implied vstem arguments before them.
+        19 | 20 => {
+          self.stems += self.stack.len() / 2;
+          self.stack.clear();
+          pos += self.stems.div_ceil(8);
+        }
+        10 | 29 => {
+          let (subrs, used) = if b0 == 10 {
+            (self.local_subrs, &mut *self.used_local)
+          } else {
+            (self.global_subrs, &mut *self.used_global)
+          };
+          let index = usize::try_from(self.stack.pop()? + subr_bias(subrs.len())).ok()?;
+          let subr = subrs.get(index)?;
+          used.insert(index);
+          if let Flow::End = self.walk(subr, depth + 1)? {
+            return Some(Flow::End);
+          }
+        }
+        11 => {
+          return Some(Flow::Return);
+        }
+        14 => {
+          if let [.., bchar, achar] = self.stack[..] && self.stack.len() >= 4 {
+            self.seac = Some((bchar, achar));
+          }
+          return Some(Flow::End);
+        }
+        12 => {
+          let b1 = *charstring.get(pos)?;
+          pos += 1;
+          // dotsection and the flex operators; everything else under the
+          // escape is arithmetic or storage.
+          if !matches!(b1, 0 | 34..=37) {
+            return None;
+          }
+          self.stack.clear();
+        }
+        _ => self.stack.clear(),
+      }
+    }
+
+    Some(Flow::Continue)
+  }
+}
+
+impl Cff<'_> {
+  fn private_for(&self, glyph_id: u16) -> Option<usize> {
+    match self.fd_select {
+      Some(fd_select) if !self.font_dicts.is_empty() => {
+        fd_index(fd_select, glyph_id).filter(|&fd| fd < self.privates.len())
+      }
+      _ => Some(0),
+    }
+  }
+
+  /// Records the subroutines a glyph calls. Returns the codes of its base and
+  /// accent glyphs when it is built from two others, or `None` when the
+  /// charstring can't be followed.
+  fn walk_glyph(
+    &self,
+    glyph_id: u16,
+    used_global: &mut BTreeSet<usize>,
+    used_local: &mut [BTreeSet<usize>]
+  ) -> Option<Option<(i32, i32)>> {
+    let charstring = self.charstrings.get(usize::from(glyph_id))?;
+    let fd = self.private_for(glyph_id)?;
+    let mut walker = CharstringWalker {
+      global_subrs: &self.global_subrs,
+      local_subrs: &self.privates[fd].subrs,
+      used_global,
+      used_local: used_local.get_mut(fd)?,
+      stack: Vec::new(),
+      stems: 0,
+      seac: None,
+    };
+    walker.walk(charstring, 0)?;
+    Some(walker.seac)
+  }
+
+  /// Glyph named by a Standard Encoding code through the charset.
+  fn glyph_for_code(&self, code: i32) -> Option<u16> {
+    let sid = match code {
+      32..=126 => (code - 31) as u16,
+      161..=255 => STANDARD_ENCODING_HIGH[(code - 161) as usize],
+      _ => 0,
+    };
+    if sid == 0 {
+      return None;
+    }
+
+    let Some(charset) = self.charset else {
+      // The predefined ISOAdobe charset maps glyph IDs to the same SIDs.
+      let predefined = dict_ints(&self.top, OP_CHARSET).and_then(|values| values.first().copied());
+      let iso_adobe = predefined.unwrap_or(0) == 0;
+      return (iso_adobe && usize::from(sid) < self.charstrings.len()).then_some(sid);
+    };
+    let format = *charset.first()?;
+    let mut glyph_id = 1u16;
+    let mut pos = 1;
+    while usize::from(glyph_id) < self.charstrings.len() {
+      let first = read_u16(charset, pos)?;
+      let left = match format {
+        0 => 0,
+        1 => u16::from(*charset.get(pos + 2)?),
+        _ => read_u16(charset, pos + 2)?,
+      };
+      if (first..=first.saturating_add(left)).contains(&sid) {
+        return glyph_id.checked_add(sid - first);
+      }
+      glyph_id = glyph_id.saturating_add(left + 1);
+      pos += [2, 3, 4][usize::from(format.min(2))];
+    }
+    None
+  }
+}
+
+/// Adds the base and accent glyphs of `seac`-style accented characters in a
+/// `CFF ` table to `glyphs`. When one can't be resolved, every glyph is kept.
+pub fn cff_closure(table: &[u8], glyphs: &mut BTreeSet<u16>) -> Result<(), Error> {
+  let cff = parse_cff(table).ok_or(MALFORMED)?;
+  if cff.cff2 {
+    return Ok(());
+  }
+
+  let mut used_global = BTreeSet::new();
+  let mut used_local = vec![BTreeSet::new(); cff.privates.len()];
+  let mut pending: Vec<u16> = glyphs.iter().copied().collect();
+  while let Some(glyph_id) = pending.pop() {
+    let Some(Some((bchar, achar))) = cff.walk_glyph(glyph_id, &mut used_global, &mut used_local)
+    else {
+      continue;
+    };
+    for code in [bchar, achar] {
+      let Some(component) = cff.glyph_for_code(code) else {
+        glyphs.extend(0..cff.charstrings.len() as u16);
+        return Ok(());
+      };
+      if glyphs.insert(component) {
+        pending.push(component);
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Rewrites a `CFF ` or `CFF2` table for a subset, emptying the charstrings of
+/// glyphs outside `glyphs` and, for `CFF `, every subroutine only they called.
+pub fn subset_cff(table: &[u8], glyphs: &BTreeSet<u16>) -> Result<Vec<u8>, Error> {
+  let cff = parse_cff(table).ok_or(MALFORMED)?;
+
+  let mut used_global = BTreeSet::new();
+  let mut used_local = vec![BTreeSet::new(); cff.privates.len()];
+  let mut keep_all_subrs = cff.cff2;
+  for &glyph_id in glyphs {
+    if
+      !keep_all_subrs &&
+      cff.walk_glyph(glyph_id, &mut used_global, &mut used_local).is_none()
+    {
+      keep_all_subrs = true;
+    }
+  }
+  let kept_subrs = |subrs: &[&[u8]], used: &BTreeSet<usize>| -> Vec<Vec<u8>> {
+    subrs
+      .iter()
+      .enumerate()
+      .map(|(index, subr)| {
+        if keep_all_subrs || used.contains(&index) { subr.to_vec() } else { Vec::new() }
+      })
+      .collect()
+  };
+
+  let empty: &[u8] = if cff.cff2 { &[] } else { EMPTY_CHARSTRING };
+  let charstrings: Vec<&[u8]> = cff.charstrings
+    .iter()
+    .enumerate()
+    .map(|(glyph_id, charstring)| {
+      if glyphs.contains(&(glyph_id as u16)) { *charstring } else { empty }
+    })
+    .collect();
+  let global_subrs = kept_subrs(&cff.global_subrs, &used_global);
+  let local_subrs: Vec<Vec<Vec<u8>>> = cff.privates
+    .iter()
+    .zip(&used_local)
+    .map(|(private, used)| kept_subrs(&private.subrs, used))
+    .collect();
+
+  Ok(write_cff(&cff, &charstrings, &global_subrs, &local_subrs))
+}
+
+/// Lays the table out as header, Top DICT, global subroutines, charset,
+/// encoding, variation store, FDSelect, charstrings, FDArray, then each
+/// Private DICT followed by its subroutines.
+fn write_cff(
+  cff: &Cff,
+  charstrings: &[&[u8]],
+  global_subrs: &[Vec<u8>],
+  local_subrs: &[Vec<Vec<u8>>]
+) -> Vec<u8> {
+  let cff2 = cff.cff2;
+
+  // Private DICTs point at their subroutines relative to themselves, right
+  // after the DICT.
+  let privates: Vec<Vec<u8>> = cff.privates
+    .iter()
+    .zip(local_subrs)
+    .map(|(private, subrs)| {
+      let mut dict = private.dict.clone();
+      if subrs.is_empty() {
+        dict.retain(|entry| entry.op != OP_SUBRS);
+      } else {
+        set_operands(&mut dict, OP_SUBRS, vec![Operand::Offset(0)]);
+        let len = write_dict(&dict).len();
+        set_operands(&mut dict, OP_SUBRS, vec![Operand::Offset(len)]);
+      }
+      write_dict(&dict)
+    })
+    .collect();
+  let private_subrs: Vec<Vec<u8>> = local_subrs
+    .iter()
+    .map(|subrs| if subrs.is_empty() { Vec::new() } else { write_index(subrs, cff2) })
+    .collect();
+
+  // Every offset is written in five bytes, so after a first pass with the
+  // original operands the DICT sizes no longer change.
+  let mut top = cff.top.clone();
+  let mut font_dicts = cff.font_dicts.clone();
+  for _ in 0..2 {
+    let top_len = write_dict(&top).len();
+    let mut offset = cff.header.len() +
+      cff.names.len() +
+      (if cff2 { top_len } else { index_len(&[top_len], false) }) +
+      cff.strings.len() +
+      index_len(&global_subrs.iter().map(Vec::len).collect::<Vec<_>>(), cff2);
+
+    let placed = [
+      (OP_CHARSET, cff.charset),
+      (OP_ENCODING, cff.encoding),
+      (OP_VSTORE, cff.vstore),
+      (OP_FD_SELECT, cff.fd_select),
+    ];
+    for (op, raw) in placed {
+      if let Some(raw) = raw {
+        set_operands(&mut top, op, vec![Operand::Offset(offset)]);
+        offset += raw.len();
+      }
+    }
+    set_operands(&mut top, OP_CHARSTRINGS, vec![Operand::Offset(offset)]);
+    offset += index_len(&charstrings.iter().map(|c| c.len()).collect::<Vec<_>>(), cff2);
+
+    if font_dicts.is_empty() {
+      let private = vec![Operand::Offset(privates[0].len()), Operand::Offset(offset)];
+      set_operands(&mut top, OP_PRIVATE, private);
+      continue;
+    }
+    set_operands(&mut top, OP_FD_ARRAY, vec![Operand::Offset(offset)]);
+    let dict_lens: Vec<usize> = font_dicts.iter().map(|dict| write_dict(dict).len()).collect();
+    offset += index_len(&dict_lens, cff2);
+    for (dict, (private, subrs)) in font_dicts.iter_mut().zip(privates.iter().zip(&private_subrs)) {
+      set_operands(dict, OP_PRIVATE, vec![Operand::Offset(private.len()), Operand::Offset(offset)]);
+      offset += private.len() + subrs.len();
+    }
+  }
+
+  let top_dict = write_dict(&top);
+  let mut out = cff.header.to_vec();
+  if cff2 {
+    out[3..5].copy_from_slice(&(top_dict.len() as u16).to_be_bytes());
+    out.extend_from_slice(&top_dict);
+  } else {
+    // The header's offSize only describes absolute offsets, which the DICTs
+    // now write in four bytes.
+    out[3] = 4;
+    out.extend_from_slice(cff.names);
+    out.extend_from_slice(&write_index(&[&top_dict], false));
+    out.extend_from_slice(cff.strings);
+  }
+  out.extend_from_slice(&write_index(global_subrs, cff2));
+  for raw in [cff.charset, cff.encoding, cff.vstore, cff.fd_select].into_iter().flatten() {
+    out.extend_from_slice(raw);
+  }
+  out.extend_from_slice(&write_index(charstrings, cff2));
+  if !font_dicts.is_empty() {
+    let dicts: Vec<Vec<u8>> = font_dicts.iter().map(|dict| write_dict(dict)).collect();
+    out.extend_from_slice(&write_index(&dicts, cff2));
+  }
+  for (private, subrs) in privates.iter().zip(&private_subrs) {
+    out.extend_from_slice(private);
+    out.extend_from_slice(subrs);
+  }
+
+  out
+}
@@ -0,0 +1,67 @@
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ Face, Tag };
+
+/// Byte offsets inside the OS/2 table, valid for every table version.
+const OS2_PANOSE_OFFSET: usize = 32;
+const OS2_VENDOR_ID_OFFSET: usize = 58;
+const PANOSE_LEN: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Classification {
+  /// OS/2 `usWeightClass`, 1 to 1000.
+  pub weight_class: i16,
+  /// OS/2 `usWidthClass`, 1 (ultra-condensed) to 9 (ultra-expanded).
+  pub width_class: i16,
+  pub is_italic: bool,
+  pub is_oblique: bool,
+  /// `post` isFixedPitch.
+  pub is_monospace: bool,
+  /// The ten PANOSE classification bytes, empty without an OS/2 table.
+  pub panose: Vec<u8>,
+  /// OS/2 `achVendID` with trailing padding removed.
+  pub vendor_id: String,
+}
+
+impl Default for Classification {
+  fn default() -> Self {
+    Classification {
+      weight_class: 400,
+      width_class: 5,
+      is_italic: false,
+      is_oblique: false,
+      is_monospace: false,
+      panose: Vec::new(),
+      vendor_id: String::new(),
+    }
+  }
+}
+
+pub fn extract_classification(face: &Face) -> Classification {
+  let os2 = face.raw_face().table(Tag::from_bytes(b"OS/2"));
+  let panose = os2
+    .and_then(|data| data.get(OS2_PANOSE_OFFSET..OS2_PANOSE_OFFSET + PANOSE_LEN))
+    .map(<[u8]>::to_vec)
+    .unwrap_or_default();
+  let vendor_id = os2
+    .and_then(|data| data.get(OS2_VENDOR_ID_OFFSET..OS2_VENDOR_ID_OFFSET + 4))
+    .map(|bytes| {
+      bytes
+        .iter()
+        .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+        .map(|&byte| byte as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+    })
+    .unwrap_or_default();
+
+  Classification {
+    weight_class: face.weight().to_number().min(1000) as i16,
+    width_class: face.width().to_number() as i16,
+    is_italic: face.is_italic(),
+    is_oblique: face.is_oblique(),
+    is_monospace: face.is_monospaced(),
+    panose,
+    vendor_id,
+  }
+}
@@ -0,0 +1,43 @@
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ Face, Tag };
+
+use crate::binary::read_u16;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ColorInfo {
+  /// Color glyph formats present: `COLRv0`, `COLRv1`, `CBDT`, `sbix`, `SVG`.
+  pub color_formats: Vec<String>,
+  /// Number of CPAL palettes, 0 without a CPAL table.
+  pub palette_count: i32,
+}
+
+pub fn extract_color_info(face: &Face) -> ColorInfo {
+  let raw_face = face.raw_face();
+  let table = |tag: &[u8; 4]| raw_face.table(Tag::from_bytes(tag));
+  let mut color_formats = Vec::new();
+
+  if let Some(colr) = table(b"COLR") {
+    match read_u16(colr, 0) {
+      Some(0) => color_formats.push("COLRv0"),
+      Some(1) => color_formats.push("COLRv1"),
+      _ => {}
+    }
+  }
+  // Color bitmaps are only usable with their location table.
+  if table(b"CBDT").is_some() && table(b"CBLC").is_some() {
+    color_formats.push("CBDT");
+  }
+  if table(b"sbix").is_some() {
+    color_formats.push("sbix");
+  }
+  if table(b"SVG ").is_some() {
+    color_formats.push("SVG");
+  }
+
+  let palette_count = table(b"CPAL").and_then(|cpal| read_u16(cpal, 4)).unwrap_or(0);
+
+  ColorInfo {
+    color_formats: color_formats.into_iter().map(str::to_string).collect(),
+    palette_count: i32::from(palette_count),
+  }
+}
@@ -0,0 +1,132 @@
+//! Conditional and partial GET handling for file downloads (RFC 9110 §13 and
+//! §14). Only single byte ranges are served; anything else falls back to the
+//! full representation, which the RFC allows.
+
+use aws_sdk_s3::primitives::{ DateTime, DateTimeFormat };
+use axum::http::{ header, HeaderMap };
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+  Full,
+  /// Inclusive byte offsets.
+  Partial {
+    start: u64,
+    end: u64,
+  },
+  Unsatisfiable,
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+  headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime> {
+  DateTime::from_str(value.trim(), DateTimeFormat::HttpDate).ok()
+}
+
+fn opaque_tag(tag: &str) -> &str {
+  tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// Strong ETag for a stored object, derived from its blake3 checksum.
+pub fn checksum_etag(checksum: &str) -> String {
+  format!("\"{}\"", checksum)
+}
+
+pub fn http_date(time: &DateTime) -> Option<String> {
+  time.fmt(DateTimeFormat::HttpDate).ok()
+}
+
+/// Whether the client's cached copy is still current. `If-Modified-Since` is
+/// only consulted when the request carries no `If-None-Match`.
+pub fn is_not_modified(
+  headers: &HeaderMap,
+  etag: Option<&str>,
+  last_modified: Option<&DateTime>
+) -> bool {
+  if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+    let Some(etag) = etag else {
+      return false;
+    };
+    return if_none_match.trim() == "*" ||
+      if_none_match
+        .split(',')
+        .any(|candidate| opaque_tag(candidate.trim()) == opaque_tag(etag));
+  }
+
+  match (header_str(headers, header::IF_MODIFIED_SINCE).and_then(parse_http_date), last_modified) {
+    (Some(since), Some(modified)) => modified.secs() <= since.secs(),
+    _ => false,
+  }
+}
+
+/// Whether a `Range` header may be honoured: without `If-Range` always, with
+/// it only if the validator still matches exactly. Weak tags never match.
+pub fn range_applies(
+  headers: &HeaderMap,
+  etag: Option<&str>,
+  last_modified: Option<&DateTime>
+) -> bool {
+  let Some(if_range) = header_str(headers, header::IF_RANGE).map(str::trim) else {
+    return true;
+  };
+
+  if if_range.starts_with('"') {
+    return etag == Some(if_range);
+  }
+  match (parse_http_date(if_range), last_modified) {
+    (Some(date), Some(modified)) => date.secs() == modified.secs(),
+    _ => false,
+  }
+}
+
+/// Interprets a `Range` header against an object of `len` bytes.
+pub fn parse_range(headers: &HeaderMap, len: u64) -> RangeRequest {
+  let Some(value) = header_str(headers, header::RANGE) else {
+    return RangeRequest::Full;
+  };
+  let Some(spec) = value
+    .trim()
+    .split_once('=')
+    .filter(|(unit, _)| unit.trim().eq_ignore_ascii_case("bytes"))
+    .map(|(_, spec)| spec.trim()) else {
+    return RangeRequest::Full;
+  };
+  if spec.contains(',') {
+    return RangeRequest::Full;
+  }
+  let Some((start, end)) = spec.split_once('-') else {
+    return RangeRequest::Full;
+  };
+
+  match (start.trim(), end.trim()) {
+    ("", suffix) => {
+      let Ok(suffix) = suffix.parse::<u64>() else {
+        return RangeRequest::Full;
+      };
+      if suffix == 0 || len == 0 {
+        return RangeRequest::Unsatisfiable;
+      }
+      RangeRequest::Partial { start: len.saturating_sub(suffix), end: len - 1 }
+    }
+    (start, end) => {
+      let Ok(start) = start.parse::<u64>() else {
+        return RangeRequest::Full;
+      };
+      let end = match end {
+        "" => u64::MAX,
+        end =>
+          match end.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => {
+              return RangeRequest::Full;
+            }
+          }
+      };
+      if start >= len {
+        return RangeRequest::Unsatisfiable;
+      }
+      RangeRequest::Partial { start, end: end.min(len - 1) }
+    }
+  }
+}
@@ -0,0 +1,159 @@
+use std::cmp::Ordering;
+
+use axum::http::StatusCode;
+use serde::Serialize;
+
+use crate::{ database::FontRecord, metadata::FontMetadata };
+
+/// What to do when an upload claims a PostScript name that another file in the
+/// library already uses. Both files are kept unless the client asks otherwise,
+/// so uploads keep working for clients that predate conflict detection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+  Reject,
+  Replace,
+  #[default]
+  KeepBoth,
+}
+
+impl ConflictPolicy {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.trim().to_ascii_lowercase().as_str() {
+      "reject" => Some(ConflictPolicy::Reject),
+      "replace" => Some(ConflictPolicy::Replace),
+      "keep_both" | "keep-both" => Some(ConflictPolicy::KeepBoth),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ConflictPolicy::Reject => "reject",
+      ConflictPolicy::Replace => "replace",
+      ConflictPolicy::KeepBoth => "keep_both",
+    }
+  }
+}
+
+/// Parses an `on_conflict` value sent by a client.
+pub fn parse_conflict_policy(value: &str) -> Result<ConflictPolicy, (StatusCode, String)> {
+  ConflictPolicy::from_name(value).ok_or_else(|| (
+    StatusCode::BAD_REQUEST,
+    format!("Unsupported on_conflict '{}', expected keep_both, replace or reject", value),
+  ))
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionComparison {
+  Newer,
+  Older,
+  Same,
+}
+
+#[derive(Serialize)]
+pub struct ConflictingFace {
+  pub object_path: String,
+  pub face_index: i32,
+  pub checksum: String,
+  pub version_string: String,
+  pub font_revision: f32,
+}
+
+#[derive(Serialize)]
+pub struct FontConflict {
+  pub postscript_name: String,
+  pub uploaded: ConflictingFace,
+  pub existing: ConflictingFace,
+  /// How the uploaded face's version relates to the existing one.
+  pub uploaded_is: VersionComparison,
+}
+
+#[derive(Serialize)]
+pub struct ConflictReport<'a> {
+  pub status: &'static str,
+  pub options: [&'static str; 3],
+  pub conflicts: &'a [FontConflict],
+}
+
+impl<'a> ConflictReport<'a> {
+  /// `status` is `conflict` when the upload was rejected, otherwise what
+  /// `policy` did about the conflicts: `kept_both` or `replaced`.
+  pub fn new(policy: ConflictPolicy, conflicts: &'a [FontConflict]) -> Self {
+    let status = match policy {
+      ConflictPolicy::Reject => "conflict",
+      ConflictPolicy::Replace => "replaced",
+      ConflictPolicy::KeepBoth => "kept_both",
+    };
+    ConflictReport { status, options: ["keep_both", "replace", "reject"], conflicts }
+  }
+}
+
+/// First dotted number in a name-table version string, e.g. `2.003` from
+/// "Version 2.003; ttfautohint (v1.8)".
+fn parse_version_string(version: &str) -> Option<f64> {
+  let start = version.find(|c: char| c.is_ascii_digit())?;
+  let number: String = version[start..]
+    .chars()
+    .take_while(|c| c.is_ascii_digit() || *c == '.')
+    .collect();
+
+  number.trim_end_matches('.').parse().ok()
+}
+
+/// Compares `head` fontRevision first and falls back to the name-table version
+/// when the revisions agree, since many fonts never bump the former.
+pub fn compare_versions(uploaded: &FontMetadata, existing: &FontMetadata) -> VersionComparison {
+  let by_revision = uploaded.font_revision
+    .partial_cmp(&existing.font_revision)
+    .unwrap_or(Ordering::Equal);
+  let by_name = match
+    (parse_version_string(&uploaded.version_string), parse_version_string(&existing.version_string))
+  {
+    (Some(uploaded), Some(existing)) => uploaded.partial_cmp(&existing).unwrap_or(Ordering::Equal),
+    _ => Ordering::Equal,
+  };
+
+  match by_revision.then(by_name) {
+    Ordering::Greater => VersionComparison::Newer,
+    Ordering::Less => VersionComparison::Older,
+    Ordering::Equal => VersionComparison::Same,
+  }
+}
+
+/// Pairs every uploaded face with the existing faces that share its PostScript
+/// name.
+pub fn describe_conflicts(
+  object_path: &str,
+  faces: &[FontMetadata],
+  existing: &[FontRecord]
+) -> Vec<FontConflict> {
+  faces
+    .iter()
+    .enumerate()
+    .filter(|(_, face)| !face.postscript_name.is_empty())
+    .flat_map(|(face_index, face)| {
+      existing
+        .iter()
+        .filter(move |record| record.metadata.postscript_name == face.postscript_name)
+        .map(move |record| FontConflict {
+          postscript_name: face.postscript_name.clone(),
+          uploaded: ConflictingFace {
+            object_path: object_path.to_string(),
+            face_index: face_index as i32,
+            checksum: face.checksum.clone(),
+            version_string: face.version_string.clone(),
+            font_revision: face.font_revision,
+          },
+          existing: ConflictingFace {
+            object_path: record.object_path.clone(),
+            face_index: record.face_index,
+            checksum: record.metadata.checksum.clone(),
+            version_string: record.metadata.version_string.clone(),
+            font_revision: record.metadata.font_revision,
+          },
+          uploaded_is: compare_versions(face, &record.metadata),
+        })
+    })
+    .collect()
+}
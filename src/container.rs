@@ -0,0 +1,828 @@
+use std::{ borrow::Cow, io::{ Read, Write } };
+
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ fonts_in_collection, Face, Tag };
+
+use crate::{
+  binary::{ read_u16, read_u32 },
+  sfnt::{
+    build_sfnt,
+    component_args_len,
+    read_tables,
+    sfnt_size,
+    table_checksum,
+    SfntTable,
+    MORE_COMPONENTS,
+    TTCF_TAG,
+    WE_HAVE_INSTRUCTIONS,
+  },
+};
+
+/// Upper bound for a decompressed font, so a hostile WOFF header cannot make
+/// us allocate arbitrary amounts of memory.
+const MAX_SFNT_SIZE: usize = 256 * 1024 * 1024;
+
+const WOFF_SIGNATURE: u32 = u32::from_be_bytes(*b"wOFF");
+const WOFF2_SIGNATURE: u32 = u32::from_be_bytes(*b"wOF2");
+const OTTO_TAG: u32 = u32::from_be_bytes(*b"OTTO");
+const TRUE_TAG: u32 = u32::from_be_bytes(*b"true");
+
+const GLYF_TAG: Tag = Tag::from_bytes(b"glyf");
+const LOCA_TAG: Tag = Tag::from_bytes(b"loca");
+const HMTX_TAG: Tag = Tag::from_bytes(b"hmtx");
+const HHEA_TAG: Tag = Tag::from_bytes(b"hhea");
+const MAXP_TAG: Tag = Tag::from_bytes(b"maxp");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontFormat {
+  Ttf,
+  Otf,
+  Ttc,
+  Woff,
+  Woff2,
+}
+
+impl FontFormat {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      FontFormat::Ttf => "ttf",
+      FontFormat::Otf => "otf",
+      FontFormat::Ttc => "ttc",
+      FontFormat::Woff => "woff",
+      FontFormat::Woff2 => "woff2",
+    }
+  }
+
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      FontFormat::Ttf => "font/ttf",
+      FontFormat::Otf => "font/otf",
+      FontFormat::Ttc => "font/collection",
+      FontFormat::Woff => "font/woff",
+      FontFormat::Woff2 => "font/woff2",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "ttf" => Some(FontFormat::Ttf),
+      "otf" => Some(FontFormat::Otf),
+      "ttc" => Some(FontFormat::Ttc),
+      "woff" => Some(FontFormat::Woff),
+      "woff2" => Some(FontFormat::Woff2),
+      _ => None,
+    }
+  }
+}
+
+/// The outline flavor of a face. Only TrueType outlines can be subset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutlineFormat {
+  TrueType,
+  Cff,
+  Cff2,
+}
+
+impl OutlineFormat {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      OutlineFormat::TrueType => "truetype",
+      OutlineFormat::Cff => "cff",
+      OutlineFormat::Cff2 => "cff2",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "truetype" => Some(OutlineFormat::TrueType),
+      "cff" => Some(OutlineFormat::Cff),
+      "cff2" => Some(OutlineFormat::Cff2),
+      _ => None,
+    }
+  }
+
+  /// Reads the flavor from the outline table the face carries, whatever
+  /// container or sfnt version it came in.
+  pub fn of_face(face: &Face) -> Option<Self> {
+    let tables = face.tables();
+    if tables.cff.is_some() {
+      Some(OutlineFormat::Cff)
+    } else if tables.cff2.is_some() {
+      Some(OutlineFormat::Cff2)
+    } else if tables.glyf.is_some() {
+      Some(OutlineFormat::TrueType)
+    } else {
+      None
+    }
+  }
+}
+
+pub fn detect_format(data: &[u8]) -> Option<FontFormat> {
+  match read_u32(data, 0)? {
+    0x00010000 | TRUE_TAG => Some(FontFormat::Ttf),
+    OTTO_TAG => Some(FontFormat::Otf),
+    TTCF_TAG => Some(FontFormat::Ttc),
+    WOFF_SIGNATURE => Some(FontFormat::Woff),
+    WOFF2_SIGNATURE => Some(FontFormat::Woff2),
+    _ => None,
+  }
+}
+
+/// The detected container format alongside the sfnt data ttf-parser can read.
+pub type DecodedFont<'a> = (FontFormat, Cow<'a, [u8]>);
+
+/// Unwraps web font containers so the result can be handed to ttf-parser.
+/// Plain sfnt data is borrowed as-is.
+pub fn decode_font(data: &[u8]) -> Result<DecodedFont<'_>, Box<dyn std::error::Error>> {
+  let format = detect_format(data).ok_or("Unrecognized font container")?;
+
+  let sfnt = match format {
+    FontFormat::Woff => Cow::Owned(decode_woff(data)?),
+    FontFormat::Woff2 => Cow::Owned(decode_woff2(data)?),
+    _ => Cow::Borrowed(data),
+  };
+
+  Ok((format, sfnt))
+}
+
+fn inflate_zlib(data: &[u8], expected_len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let mut out = Vec::with_capacity(expected_len);
+  flate2::read::ZlibDecoder
+    ::new(data)
+    .take(expected_len as u64 + 1)
+    .read_to_end(&mut out)?;
+
+  if out.len() != expected_len {
+    return Err("Decompressed table length does not match the WOFF directory".into());
+  }
+
+  Ok(out)
+}
+
+pub fn decode_woff(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let header_err = "Truncated WOFF header";
+  let flavor = read_u32(data, 4).ok_or(header_err)?;
+  let num_tables = read_u16(data, 12).ok_or(header_err)? as usize;
+  let total_sfnt_size = read_u32(data, 16).ok_or(header_err)? as usize;
+
+  if total_sfnt_size > MAX_SFNT_SIZE {
+    return Err("WOFF declares an sfnt size beyond the supported limit".into());
+  }
+
+  let mut tables = Vec::with_capacity(num_tables);
+  for i in 0..num_tables {
+    let entry = 44 + i * 20;
+    let dir_err = "Truncated WOFF table directory";
+    let tag = Tag(read_u32(data, entry).ok_or(dir_err)?);
+    let offset = read_u32(data, entry + 4).ok_or(dir_err)? as usize;
+    let comp_length = read_u32(data, entry + 8).ok_or(dir_err)? as usize;
+    let orig_length = read_u32(data, entry + 12).ok_or(dir_err)? as usize;
+
+    let compressed = offset
+      .checked_add(comp_length)
+      .and_then(|end| data.get(offset..end))
+      .ok_or_else(|| format!("WOFF table '{}' lies outside of the file", tag))?;
+
+    let table = if comp_length < orig_length {
+      inflate_zlib(compressed, orig_length)?
+    } else if comp_length == orig_length {
+      compressed.to_vec()
+    } else {
+      return Err(format!("WOFF table '{}' is larger compressed than uncompressed", tag).into());
+    };
+
+    tables.push(SfntTable { tag, data: table });
+  }
+
+  Ok(build_sfnt(flavor, tables))
+}
+
+/// Wraps a single-face sfnt in WOFF 1.0, zlib-compressing every table that
+/// gets smaller.
+pub fn encode_woff(sfnt: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let (flavor, tables) = read_tables(sfnt, 0)?;
+  let header_size = 44 + 20 * tables.len();
+
+  let mut directory = Vec::with_capacity(header_size);
+  let mut body = Vec::new();
+  for table in &tables {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&table.data)?;
+    let compressed = encoder.finish()?;
+    let stored = if compressed.len() < table.data.len() { &compressed } else { &table.data };
+
+    directory.extend_from_slice(&table.tag.to_bytes());
+    directory.extend_from_slice(&((header_size + body.len()) as u32).to_be_bytes());
+    directory.extend_from_slice(&(stored.len() as u32).to_be_bytes());
+    directory.extend_from_slice(&(table.data.len() as u32).to_be_bytes());
+    directory.extend_from_slice(&table_checksum(&table.data).to_be_bytes());
+
+    body.extend_from_slice(stored);
+    body.resize((body.len() + 3) & !3, 0);
+  }
+
+  let mut woff = Vec::with_capacity(header_size + body.len());
+  woff.extend_from_slice(&WOFF_SIGNATURE.to_be_bytes());
+  woff.extend_from_slice(&flavor.to_be_bytes());
+  woff.extend_from_slice(&((header_size + body.len()) as u32).to_be_bytes());
+  woff.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+  woff.extend_from_slice(&0u16.to_be_bytes());
+  woff.extend_from_slice(&(sfnt_size(&tables) as u32).to_be_bytes());
+  woff.extend_from_slice(&[0; 24]);
+  woff.extend_from_slice(&directory);
+  woff.extend_from_slice(&body);
+
+  Ok(woff)
+}
+
+fn write_base128(out: &mut Vec<u8>, value: u32) {
+  let mut bytes = Vec::with_capacity(5);
+  let mut rest = value;
+  loop {
+    bytes.push((rest & 0x7f) as u8);
+    rest >>= 7;
+    if rest == 0 {
+      break;
+    }
+  }
+  for (i, byte) in bytes.iter().rev().enumerate() {
+    out.push(if i + 1 < bytes.len() { byte | 0x80 } else { *byte });
+  }
+}
+
+/// Wraps a single-face sfnt in WOFF2. Tables are stored untransformed (glyf
+/// and loca use the null transform), so the whole gain comes from Brotli.
+pub fn encode_woff2(sfnt: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let (flavor, mut tables) = read_tables(sfnt, 0)?;
+  let total_sfnt_size = sfnt_size(&tables);
+
+  // Decoders expect loca to directly follow glyf in the directory.
+  tables.sort_by_key(|table| {
+    if table.tag == LOCA_TAG { (GLYF_TAG, 1) } else { (table.tag, 0) }
+  });
+
+  let mut directory = Vec::new();
+  let mut stream = Vec::with_capacity(total_sfnt_size);
+  for table in &tables {
+    let known_index = WOFF2_KNOWN_TAGS.iter().position(|tag| **tag == table.tag.to_bytes());
+    let null_transform = if table.tag == GLYF_TAG || table.tag == LOCA_TAG { 0xc0 } else { 0 };
+    match known_index {
+      Some(index) => directory.push((index as u8) | null_transform),
+      None => {
+        directory.push(0x3f | null_transform);
+        directory.extend_from_slice(&table.tag.to_bytes());
+      }
+    }
+    write_base128(&mut directory, table.data.len() as u32);
+    stream.extend_from_slice(&table.data);
+  }
+
+  let params = brotli::enc::BrotliEncoderParams {
+    quality: 11,
+    lgwin: 22,
+    mode: brotli::enc::backward_references::BrotliEncoderMode::BROTLI_MODE_FONT,
+    size_hint: stream.len(),
+    ..Default::default()
+  };
+  let mut compressed = Vec::new();
+  brotli::BrotliCompress(&mut stream.as_slice(), &mut compressed, &params)?;
+
+  let length = 48 + directory.len() + compressed.len();
+  let mut woff2 = Vec::with_capacity(length);
+  woff2.extend_from_slice(&WOFF2_SIGNATURE.to_be_bytes());
+  woff2.extend_from_slice(&flavor.to_be_bytes());
+  woff2.extend_from_slice(&(length as u32).to_be_bytes());
+  woff2.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+  woff2.extend_from_slice(&0u16.to_be_bytes());
+  woff2.extend_from_slice(&(total_sfnt_size as u32).to_be_bytes());
+  woff2.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+  woff2.extend_from_slice(&[0; 24]);
+  woff2.extend_from_slice(&directory);
+  woff2.extend_from_slice(&compressed);
+
+  Ok(woff2)
+}
+
+/// Converts stored font data into a web font container. Collections are
+/// rejected because neither WOFF flavour we emit can hold more than one face.
+pub fn convert_font(
+  data: &[u8],
+  target: FontFormat
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let (_, sfnt) = decode_font(data)?;
+  if fonts_in_collection(&sfnt).is_some() {
+    return Err("Font collections cannot be converted to web font formats".into());
+  }
+
+  match target {
+    FontFormat::Woff => encode_woff(&sfnt),
+    FontFormat::Woff2 => encode_woff2(&sfnt),
+    _ => Err(format!("Conversion to '{}' is not supported", target.as_str()).into()),
+  }
+}
+
+static WOFF2_KNOWN_TAGS: [&[u8; 4]; 63] = [
+  b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm", b"glyf",
+  b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern", b"LTSH", b"PCLT",
+  b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC", b"JSTF", b"MATH", b"CBDT",
+  b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar", b"bdat", b"bloc", b"bsln", b"cvar",
+  b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty", b"just", b"lcar", b"mort", b"morx", b"opbd",
+  b"prop", b"trak", b"Zapf", b"Silf", b"Glat", b"Gloc", b"Feat", b"Sill",
+];
+
+/// Cursor over a byte slice for the variable-length encodings used by WOFF2.
+struct Reader<'a> {
+  data: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    Reader { data, offset: 0 }
+  }
+
+  fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+    let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+    self.offset += len;
+    Some(bytes)
+  }
+
+  fn u8(&mut self) -> Option<u8> {
+    self.bytes(1).map(|b| b[0])
+  }
+
+  fn u16(&mut self) -> Option<u16> {
+    self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+  }
+
+  fn i16(&mut self) -> Option<i16> {
+    self.u16().map(|v| v as i16)
+  }
+
+  fn u32(&mut self) -> Option<u32> {
+    self.bytes(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+  }
+
+  fn base128(&mut self) -> Option<u32> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+      let byte = self.u8()?;
+      if i == 0 && byte == 0x80 {
+        return None;
+      }
+      if value & 0xfe000000 != 0 {
+        return None;
+      }
+      value = (value << 7) | u32::from(byte & 0x7f);
+      if byte & 0x80 == 0 {
+        return Some(value);
+      }
+    }
+    None
+  }
+
+  fn u255(&mut self) -> Option<u16> {
+    match self.u8()? {
+      253 => self.u16(),
+      254 => self.u8().map(|v| u16::from(v) + 506),
+      255 => self.u8().map(|v| u16::from(v) + 253),
+      code => Some(u16::from(code)),
+    }
+  }
+
+  fn sub_reader(&mut self, len: usize) -> Option<Reader<'a>> {
+    self.bytes(len).map(Reader::new)
+  }
+}
+
+struct Woff2TableEntry {
+  tag: Tag,
+  orig_length: usize,
+  transform_length: usize,
+  transformed: bool,
+}
+
+pub fn decode_woff2(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let mut header = Reader::new(data);
+  let header_err = "Truncated WOFF2 header";
+  header.u32().ok_or(header_err)?;
+  let flavor = header.u32().ok_or(header_err)?;
+  header.u32().ok_or(header_err)?;
+  let num_tables = header.u16().ok_or(header_err)? as usize;
+  header.u16().ok_or(header_err)?;
+  let total_sfnt_size = header.u32().ok_or(header_err)? as usize;
+  let total_compressed_size = header.u32().ok_or(header_err)? as usize;
+  header.bytes(24).ok_or(header_err)?;
+
+  if flavor == TTCF_TAG {
+    return Err("WOFF2 font collections are not supported".into());
+  }
+  if total_sfnt_size > MAX_SFNT_SIZE {
+    return Err("WOFF2 declares an sfnt size beyond the supported limit".into());
+  }
+
+  let dir_err = "Malformed WOFF2 table directory";
+  let mut entries = Vec::with_capacity(num_tables);
+  for _ in 0..num_tables {
+    let flags = header.u8().ok_or(dir_err)?;
+    let tag = if flags & 0x3f == 0x3f {
+      Tag(header.u32().ok_or(dir_err)?)
+    } else {
+      Tag::from_bytes(WOFF2_KNOWN_TAGS[(flags & 0x3f) as usize])
+    };
+    let transform_version = flags >> 6;
+    let orig_length = header.base128().ok_or(dir_err)? as usize;
+
+    let transformed = if tag == GLYF_TAG || tag == LOCA_TAG {
+      transform_version == 0
+    } else {
+      transform_version != 0
+    };
+    let transform_length = if transformed {
+      header.base128().ok_or(dir_err)? as usize
+    } else {
+      orig_length
+    };
+
+    entries.push(Woff2TableEntry { tag, orig_length, transform_length, transformed });
+  }
+
+  let compressed = header
+    .bytes(total_compressed_size)
+    .ok_or("WOFF2 compressed stream lies outside of the file")?;
+  let mut stream = Vec::new();
+  brotli::Decompressor
+    ::new(compressed, 4096)
+    .take(MAX_SFNT_SIZE as u64)
+    .read_to_end(&mut stream)?;
+
+  let mut offset = 0usize;
+  let mut raw_tables = Vec::with_capacity(entries.len());
+  for entry in &entries {
+    let table = offset
+      .checked_add(entry.transform_length)
+      .and_then(|end| stream.get(offset..end))
+      .ok_or_else(|| format!("WOFF2 table '{}' lies outside of the decompressed stream", entry.tag))?;
+    raw_tables.push(table);
+    offset += entry.transform_length;
+  }
+
+  let find = |tag: Tag| entries.iter().position(|e| e.tag == tag);
+  let mut tables: Vec<SfntTable> = Vec::with_capacity(entries.len());
+  let mut glyph_x_mins: Option<Vec<i16>> = None;
+
+  if
+    let Some(glyf_index) = find(GLYF_TAG) &&
+    entries[glyf_index].transformed
+  {
+    let loca_index = find(LOCA_TAG).ok_or("Transformed glyf table without loca")?;
+    let reconstructed = reconstruct_glyf(raw_tables[glyf_index])?;
+
+    if reconstructed.loca.len() != entries[loca_index].orig_length {
+      return Err("Reconstructed loca length does not match the WOFF2 directory".into());
+    }
+
+    tables.push(SfntTable { tag: GLYF_TAG, data: reconstructed.glyf });
+    tables.push(SfntTable { tag: LOCA_TAG, data: reconstructed.loca });
+    glyph_x_mins = Some(reconstructed.x_mins);
+  }
+
+  for (entry, raw) in entries.iter().zip(&raw_tables) {
+    if tables.iter().any(|t| t.tag == entry.tag) {
+      continue;
+    }
+
+    let data = if entry.tag == HMTX_TAG && entry.transformed {
+      let x_mins = glyph_x_mins.as_deref().ok_or("Transformed hmtx requires a transformed glyf")?;
+      let num_h_metrics = find(HHEA_TAG)
+        .and_then(|i| read_u16(raw_tables[i], 34))
+        .ok_or("Missing hhea table")?;
+      let num_glyphs = find(MAXP_TAG)
+        .and_then(|i| read_u16(raw_tables[i], 4))
+        .ok_or("Missing maxp table")?;
+      reconstruct_hmtx(raw, num_h_metrics as usize, num_glyphs as usize, x_mins)?
+    } else if entry.transformed {
+      return Err(format!("Unsupported WOFF2 transform for table '{}'", entry.tag).into());
+    } else {
+      raw.to_vec()
+    };
+
+    tables.push(SfntTable { tag: entry.tag, data });
+  }
+
+  Ok(build_sfnt(flavor, tables))
+}
+
+const ON_CURVE_POINT: u8 = 0x01;
+const X_SHORT_VECTOR: u8 = 0x02;
+const Y_SHORT_VECTOR: u8 = 0x04;
+const X_IS_SAME_OR_POSITIVE: u8 = 0x10;
+const Y_IS_SAME_OR_POSITIVE: u8 = 0x20;
+const OVERLAP_SIMPLE: u8 = 0x40;
+
+fn with_sign(flag: u8, value: i32) -> i32 {
+  if flag & 1 != 0 { value } else { -value }
+}
+
+/// Decodes one point of the WOFF2 triplet encoding into a coordinate delta.
+fn decode_triplet(flag: u8, glyph_stream: &mut Reader) -> Option<(i32, i32)> {
+  let flag = flag & 0x7f;
+  let delta = if flag < 10 {
+    let b0 = i32::from(glyph_stream.u8()?);
+    (0, with_sign(flag, (i32::from(flag & 14) << 7) + b0))
+  } else if flag < 20 {
+    let b0 = i32::from(glyph_stream.u8()?);
+    (with_sign(flag, (i32::from((flag - 10) & 14) << 7) + b0), 0)
+  } else if flag < 84 {
+    let b0 = i32::from(flag - 20);
+    let b1 = i32::from(glyph_stream.u8()?);
+    (
+      with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+      with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f)),
+    )
+  } else if flag < 120 {
+    let b0 = i32::from(flag - 84);
+    let bytes = glyph_stream.bytes(2)?;
+    (
+      with_sign(flag, 1 + ((b0 / 12) << 8) + i32::from(bytes[0])),
+      with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + i32::from(bytes[1])),
+    )
+  } else if flag < 124 {
+    let bytes = glyph_stream.bytes(3)?;
+    let b2 = i32::from(bytes[1]);
+    (
+      with_sign(flag, (i32::from(bytes[0]) << 4) + (b2 >> 4)),
+      with_sign(flag >> 1, ((b2 & 0x0f) << 8) + i32::from(bytes[2])),
+    )
+  } else {
+    let bytes = glyph_stream.bytes(4)?;
+    (
+      with_sign(flag, (i32::from(bytes[0]) << 8) + i32::from(bytes[1])),
+      with_sign(flag >> 1, (i32::from(bytes[2]) << 8) + i32::from(bytes[3])),
+    )
+  };
+
+  Some(delta)
+}
+
+fn push_coordinate(flag: &mut u8, out: &mut Vec<u8>, delta: i32, short: u8, same: u8) {
+  if delta == 0 {
+    *flag |= same;
+  } else if (-255..=255).contains(&delta) {
+    *flag |= short;
+    if delta > 0 {
+      *flag |= same;
+    }
+    out.push(delta.unsigned_abs() as u8);
+  } else {
+    out.extend_from_slice(&(delta as i16).to_be_bytes());
+  }
+}
+
+/// Serializes a simple glyph in the regular `glyf` encoding.
+fn encode_simple_glyph(
+  end_points: &[u16],
+  points: &[(i32, i32, bool)],
+  instructions: &[u8],
+  bbox: [i16; 4],
+  overlap: bool
+) -> Vec<u8> {
+  let mut glyph = Vec::new();
+  glyph.extend_from_slice(&(end_points.len() as i16).to_be_bytes());
+  for value in bbox {
+    glyph.extend_from_slice(&value.to_be_bytes());
+  }
+  for end_point in end_points {
+    glyph.extend_from_slice(&end_point.to_be_bytes());
+  }
+  glyph.extend_from_slice(&(instructions.len() as u16).to_be_bytes());
+  glyph.extend_from_slice(instructions);
+
+  let mut flags = Vec::with_capacity(points.len());
+  let mut xs = Vec::new();
+  let mut ys = Vec::new();
+  let (mut last_x, mut last_y) = (0, 0);
+  for (i, &(x, y, on_curve)) in points.iter().enumerate() {
+    let mut flag = if on_curve { ON_CURVE_POINT } else { 0 };
+    if i == 0 && overlap {
+      flag |= OVERLAP_SIMPLE;
+    }
+    push_coordinate(&mut flag, &mut xs, x - last_x, X_SHORT_VECTOR, X_IS_SAME_OR_POSITIVE);
+    push_coordinate(&mut flag, &mut ys, y - last_y, Y_SHORT_VECTOR, Y_IS_SAME_OR_POSITIVE);
+    flags.push(flag);
+    last_x = x;
+    last_y = y;
+  }
+
+  glyph.extend_from_slice(&flags);
+  glyph.extend_from_slice(&xs);
+  glyph.extend_from_slice(&ys);
+  glyph
+}
+
+/// Reads the component records of a composite glyph, returning their raw
+/// bytes and whether any component carries instructions.
+fn read_composite(composite_stream: &mut Reader) -> Option<(Vec<u8>, bool)> {
+  let start = composite_stream.offset;
+  let mut has_instructions = false;
+
+  loop {
+    let flags = composite_stream.u16()?;
+    composite_stream.u16()?;
+    composite_stream.bytes(component_args_len(flags))?;
+    has_instructions |= flags & WE_HAVE_INSTRUCTIONS != 0;
+
+    if flags & MORE_COMPONENTS == 0 {
+      break;
+    }
+  }
+
+  Some((composite_stream.data[start..composite_stream.offset].to_vec(), has_instructions))
+}
+
+struct ReconstructedGlyf {
+  glyf: Vec<u8>,
+  loca: Vec<u8>,
+  /// Every glyph's xMin, needed to rebuild a transformed hmtx.
+  x_mins: Vec<i16>,
+}
+
+/// Rebuilds `glyf` and `loca` from the WOFF2 transformed glyf stream.
+fn reconstruct_glyf(data: &[u8]) -> Result<ReconstructedGlyf, Box<dyn std::error::Error>> {
+  let err = "Malformed WOFF2 glyf transform";
+  let mut header = Reader::new(data);
+  header.u16().ok_or(err)?;
+  let option_flags = header.u16().ok_or(err)?;
+  let num_glyphs = header.u16().ok_or(err)? as usize;
+  let index_format = header.u16().ok_or(err)?;
+  let mut stream_sizes = [0usize; 7];
+  for size in &mut stream_sizes {
+    *size = header.u32().ok_or(err)? as usize;
+  }
+
+  let mut n_contour_stream = header.sub_reader(stream_sizes[0]).ok_or(err)?;
+  let mut n_points_stream = header.sub_reader(stream_sizes[1]).ok_or(err)?;
+  let mut flag_stream = header.sub_reader(stream_sizes[2]).ok_or(err)?;
+  let mut glyph_stream = header.sub_reader(stream_sizes[3]).ok_or(err)?;
+  let mut composite_stream = header.sub_reader(stream_sizes[4]).ok_or(err)?;
+  let mut bbox_stream = header.sub_reader(stream_sizes[5]).ok_or(err)?;
+  let mut instruction_stream = header.sub_reader(stream_sizes[6]).ok_or(err)?;
+  let overlap_bitmap = if option_flags & 1 != 0 {
+    header.bytes(num_glyphs.div_ceil(8)).ok_or(err)?
+  } else {
+    &[]
+  };
+
+  let bbox_bitmap = bbox_stream.bytes(4 * num_glyphs.div_ceil(32)).ok_or(err)?;
+  let has_bit = |bitmap: &[u8], index: usize| {
+    bitmap.get(index >> 3).is_some_and(|byte| byte & (0x80 >> (index & 7)) != 0)
+  };
+
+  let mut glyf = Vec::new();
+  let mut offsets = Vec::with_capacity(num_glyphs + 1);
+  let mut x_mins = Vec::with_capacity(num_glyphs);
+
+  for glyph_id in 0..num_glyphs {
+    offsets.push(glyf.len());
+    let n_contours = n_contour_stream.i16().ok_or(err)?;
+    let explicit_bbox = if has_bit(bbox_bitmap, glyph_id) {
+      Some([
+        bbox_stream.i16().ok_or(err)?,
+        bbox_stream.i16().ok_or(err)?,
+        bbox_stream.i16().ok_or(err)?,
+        bbox_stream.i16().ok_or(err)?,
+      ])
+    } else {
+      None
+    };
+
+    if n_contours == 0 {
+      if explicit_bbox.is_some() {
+        return Err("WOFF2 empty glyph carries a bounding box".into());
+      }
+      x_mins.push(0);
+      continue;
+    }
+
+    if n_contours < 0 {
+      let bbox = explicit_bbox.ok_or("WOFF2 composite glyph is missing its bounding box")?;
+      let (components, has_instructions) = read_composite(&mut composite_stream).ok_or(err)?;
+
+      glyf.extend_from_slice(&(-1i16).to_be_bytes());
+      for value in bbox {
+        glyf.extend_from_slice(&value.to_be_bytes());
+      }
+      glyf.extend_from_slice(&components);
+      if has_instructions {
+        let len = glyph_stream.u255().ok_or(err)?;
+        glyf.extend_from_slice(&len.to_be_bytes());
+        glyf.extend_from_slice(instruction_stream.bytes(len as usize).ok_or(err)?);
+      }
+      x_mins.push(bbox[0]);
+    } else {
+      let mut end_points = Vec::with_capacity(n_contours as usize);
+      let mut total_points = 0usize;
+      for _ in 0..n_contours {
+        total_points += n_points_stream.u255().ok_or(err)? as usize;
+        end_points.push(
+          u16::try_from(total_points.checked_sub(1).ok_or(err)?).map_err(|_| err)?
+        );
+      }
+
+      let flags = flag_stream.bytes(total_points).ok_or(err)?;
+      let mut points = Vec::with_capacity(total_points);
+      let (mut x, mut y) = (0i32, 0i32);
+      for &flag in flags {
+        let (dx, dy) = decode_triplet(flag, &mut glyph_stream).ok_or(err)?;
+        x += dx;
+        y += dy;
+        points.push((x, y, flag & 0x80 == 0));
+      }
+
+      let instruction_len = glyph_stream.u255().ok_or(err)? as usize;
+      let instructions = instruction_stream.bytes(instruction_len).ok_or(err)?;
+
+      let bbox = explicit_bbox.unwrap_or_else(|| {
+        let clamp = |v: i32| v.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        let x_min = points.iter().map(|p| p.0).min().unwrap_or(0);
+        let y_min = points.iter().map(|p| p.1).min().unwrap_or(0);
+        let x_max = points.iter().map(|p| p.0).max().unwrap_or(0);
+        let y_max = points.iter().map(|p| p.1).max().unwrap_or(0);
+        [clamp(x_min), clamp(y_min), clamp(x_max), clamp(y_max)]
+      });
+
+      glyf.extend_from_slice(
+        &encode_simple_glyph(
+          &end_points,
+          &points,
+          instructions,
+          bbox,
+          has_bit(overlap_bitmap, glyph_id)
+        )
+      );
+      x_mins.push(bbox[0]);
+    }
+
+    glyf.resize((glyf.len() + 3) & !3, 0);
+  }
+  offsets.push(glyf.len());
+
+  let mut loca = Vec::with_capacity(offsets.len() * 4);
+  for offset in offsets {
+    if index_format == 0 {
+      loca.extend_from_slice(&((offset / 2) as u16).to_be_bytes());
+    } else {
+      loca.extend_from_slice(&(offset as u32).to_be_bytes());
+    }
+  }
+
+  Ok(ReconstructedGlyf { glyf, loca, x_mins })
+}
+
+fn reconstruct_hmtx(
+  data: &[u8],
+  num_h_metrics: usize,
+  num_glyphs: usize,
+  x_mins: &[i16]
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let err = "Malformed WOFF2 hmtx transform";
+  if num_h_metrics == 0 || num_h_metrics > num_glyphs || x_mins.len() < num_glyphs {
+    return Err(err.into());
+  }
+
+  let mut reader = Reader::new(data);
+  let flags = reader.u8().ok_or(err)?;
+  let advances = (0..num_h_metrics)
+    .map(|_| reader.u16())
+    .collect::<Option<Vec<u16>>>()
+    .ok_or(err)?;
+  let proportional_lsbs = if flags & 1 == 0 {
+    (0..num_h_metrics)
+      .map(|_| reader.i16())
+      .collect::<Option<Vec<i16>>>()
+      .ok_or(err)?
+  } else {
+    x_mins[..num_h_metrics].to_vec()
+  };
+  let monospaced_lsbs = if flags & 2 == 0 {
+    (num_h_metrics..num_glyphs)
+      .map(|_| reader.i16())
+      .collect::<Option<Vec<i16>>>()
+      .ok_or(err)?
+  } else {
+    x_mins[num_h_metrics..num_glyphs].to_vec()
+  };
+
+  let mut hmtx = Vec::with_capacity(num_h_metrics * 4 + (num_glyphs - num_h_metrics) * 2);
+  for (advance, lsb) in advances.iter().zip(&proportional_lsbs) {
+    hmtx.extend_from_slice(&advance.to_be_bytes());
+    hmtx.extend_from_slice(&lsb.to_be_bytes());
+  }
+  for lsb in monospaced_lsbs {
+    hmtx.extend_from_slice(&lsb.to_be_bytes());
+  }
+
+  Ok(hmtx)
+}
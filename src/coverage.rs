@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+
+use axum::{ extract::{ Query, State }, http::StatusCode, response::IntoResponse, Json };
+use log::{ error, info };
+use serde::{ Deserialize, Serialize };
+use ttf_parser::Face;
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  database::find_covering_fonts,
+  unicode::{ codepoint_ranges, requested_codepoints },
+};
+
+/// Limit on codepoints per coverage query; enough for every assigned codepoint
+/// in the BMP and the first supplementary plane.
+const MAX_QUERY_CODEPOINTS: usize = 0x20000;
+/// Missing codepoints listed per font in a coverage response.
+const MAX_REPORTED_MISSING: usize = 64;
+
+/// Inclusive codepoint range, serialized as `[start, end]`.
+pub type CodepointRange = (u32, u32);
+
+/// Returns every Unicode codepoint the font maps to a real glyph, merged into
+/// ascending ranges.
+pub fn extract_unicode_ranges(face: &Face) -> Vec<CodepointRange> {
+  let mut codepoints = BTreeSet::new();
+
+  if let Some(cmap) = face.tables().cmap {
+    for subtable in cmap.subtables.into_iter().filter(|subtable| subtable.is_unicode()) {
+      subtable.codepoints(|codepoint| {
+        if subtable.glyph_index(codepoint).is_some_and(|glyph_id| glyph_id.0 != 0) {
+          codepoints.insert(codepoint);
+        }
+      });
+    }
+  }
+
+  codepoint_ranges(codepoints)
+    .into_iter()
+    .map(|range| (*range.start(), *range.end()))
+    .collect()
+}
+
+pub fn covers(ranges: &[CodepointRange], codepoint: u32) -> bool {
+  let index = ranges.partition_point(|&(_, end)| end < codepoint);
+  ranges.get(index).is_some_and(|&(start, _)| start <= codepoint)
+}
+
+#[derive(Deserialize)]
+pub struct CoverageParams {
+  text: Option<String>,
+  unicodes: Option<String>,
+  /// Fraction of the requested codepoints a font must support, 1.0 by default.
+  min_coverage: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct FontCoverage {
+  object_path: String,
+  face_index: i32,
+  font_family: String,
+  font_subfamily: String,
+  supported: usize,
+  requested: usize,
+  coverage: f32,
+  missing: Vec<String>,
+}
+
+/// Answers which of the caller's fonts support the given characters, best
+/// coverage first. Matching happens in the database against the stored ranges.
+pub async fn coverage_handler(
+  user: AuthUser,
+  Query(params): Query<CoverageParams>,
+  State(state): State<AppState>
+) -> impl IntoResponse {
+  let codepoints = requested_codepoints(
+    params.text.as_deref(),
+    params.unicodes.as_deref(),
+    MAX_QUERY_CODEPOINTS
+  ).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+  let min_coverage = params.min_coverage.unwrap_or(1.0);
+  if !(0.0..=1.0).contains(&min_coverage) {
+    return Err((StatusCode::BAD_REQUEST, "'min_coverage' must be between 0 and 1".to_string()));
+  }
+
+  let ranges: Vec<CodepointRange> = codepoint_ranges(codepoints.iter().copied())
+    .into_iter()
+    .map(|range| (*range.start(), *range.end()))
+    .collect();
+  let min_supported = ((min_coverage * (codepoints.len() as f32)).ceil() as i64).max(1);
+  let fonts = find_covering_fonts(&state.db_client, &user.user_id, &ranges, min_supported).await
+    .map_err(|e| {
+      error!("Failed to run coverage query: {}", e);
+      (StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up font coverage".to_string())
+    })?;
+
+  let matches: Vec<FontCoverage> = fonts
+    .into_iter()
+    .map(|(font, supported)| {
+      let ranges = &font.metadata.unicode_ranges;
+      FontCoverage {
+        object_path: font.object_path,
+        face_index: font.face_index,
+        font_family: font.metadata.font_family,
+        font_subfamily: font.metadata.font_subfamily,
+        supported: supported as usize,
+        requested: codepoints.len(),
+        coverage: (supported as f32) / (codepoints.len() as f32),
+        missing: codepoints
+          .iter()
+          .filter(|&&codepoint| !covers(ranges, codepoint))
+          .take(MAX_REPORTED_MISSING)
+          .map(|codepoint| format!("U+{:04X}", codepoint))
+          .collect(),
+      }
+    })
+    .collect();
+
+  info!(
+    "User {} - Client {} coverage query for {} codepoint(s) matched {} font(s)",
+    user.email,
+    user.client_id,
+    codepoints.len(),
+    matches.len()
+  );
+
+  Ok(Json(matches))
+}
@@ -0,0 +1,383 @@
+use std::{ env, fmt::Write };
+
+use axum::{
+  extract::{ Query, State },
+  http::{ header, HeaderMap, StatusCode },
+  response::IntoResponse,
+};
+use log::{ error, info };
+use uuid::Uuid;
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  container::FontFormat,
+  database::{ get_metadata_by_family, FontRecord },
+  metadata::FontMetadata,
+  signed_urls::sign_font_url,
+  unicode::{ codepoint_ranges, format_unicode_ranges, merge_nearby_ranges },
+};
+
+const DEFAULT_WEIGHT: u16 = 400;
+/// Ranges per `unicode-range` descriptor. A font mapping thousands of scattered
+/// codepoints would otherwise add tens of kilobytes to every face rule.
+const MAX_UNICODE_RANGES: usize = 64;
+
+/// One `ital,wght` tuple of a CSS2 family spec. Weights are inclusive ranges;
+/// a single weight has equal bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct StyleRequest {
+  italic: bool,
+  weight: (u16, u16),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FaceStyle {
+  italic: bool,
+  weight: (u16, u16),
+}
+
+fn parse_weight(value: &str) -> Result<(u16, u16), String> {
+  let parse = |weight: &str| {
+    weight
+      .parse::<u16>()
+      .ok()
+      .filter(|weight| (1..=1000).contains(weight))
+      .ok_or_else(|| format!("Invalid weight '{}'", weight))
+  };
+
+  match value.split_once("..") {
+    Some((min, max)) => {
+      let (min, max) = (parse(min)?, parse(max)?);
+      if min > max {
+        return Err(format!("Invalid weight range '{}'", value));
+      }
+      Ok((min, max))
+    }
+    None => parse(value).map(|weight| (weight, weight)),
+  }
+}
+
+/// Parses a Google Fonts CSS2 `family` value such as `Inter`,
+/// `Inter:wght@400;700` or `Inter:ital,wght@0,400;1,700..900`.
+fn parse_family_spec(spec: &str) -> Result<(String, Vec<StyleRequest>), String> {
+  let (family, axes) = match spec.split_once(':') {
+    Some((family, axes)) => (family.trim(), Some(axes)),
+    None => (spec.trim(), None),
+  };
+  if family.is_empty() {
+    return Err("Missing family name".to_string());
+  }
+
+  let Some(axes) = axes else {
+    return Ok((
+      family.to_string(),
+      vec![StyleRequest { italic: false, weight: (DEFAULT_WEIGHT, DEFAULT_WEIGHT) }],
+    ));
+  };
+
+  let (tags, tuples) = axes
+    .split_once('@')
+    .ok_or_else(|| format!("Invalid axis specification '{}'", axes))?;
+  let tags: Vec<&str> = tags.split(',').collect();
+  if let Some(tag) = tags.iter().find(|tag| !matches!(**tag, "ital" | "wght")) {
+    return Err(format!("Unsupported axis '{}'", tag));
+  }
+
+  let mut styles = Vec::new();
+  for tuple in tuples.split(';').filter(|tuple| !tuple.is_empty()) {
+    let values: Vec<&str> = tuple.split(',').collect();
+    if values.len() != tags.len() {
+      return Err(format!("Axis values '{}' do not match axes '{}'", tuple, tags.join(",")));
+    }
+
+    let mut style = StyleRequest { italic: false, weight: (DEFAULT_WEIGHT, DEFAULT_WEIGHT) };
+    for (tag, value) in tags.iter().zip(values) {
+      match (*tag, value) {
+        ("ital", "0") => {
+          style.italic = false;
+        }
+        ("ital", "1") => {
+          style.italic = true;
+        }
+        ("ital", _) => {
+          return Err(format!("Invalid ital value '{}'", value));
+        }
+        _ => {
+          style.weight = parse_weight(value)?;
+        }
+      }
+    }
+    styles.push(style);
+  }
+
+  if styles.is_empty() {
+    return Err(format!("No styles requested for family '{}'", family));
+  }
+  styles.sort();
+  styles.dedup();
+
+  Ok((family.to_string(), styles))
+}
+
+fn face_style(record: &FontRecord) -> FaceStyle {
+  let metadata = &record.metadata;
+  let classification = &metadata.classification;
+  let italic = classification.is_italic || classification.is_oblique;
+
+  let weight_axis = metadata.variations
+    .as_ref()
+    .and_then(|variations| variations.axes.iter().find(|axis| axis.tag == "wght"));
+  let weight = match weight_axis {
+    Some(axis) => (axis.min_value.round() as u16, axis.max_value.round() as u16),
+    None => {
+      let weight = classification.weight_class.max(1) as u16;
+      (weight, weight)
+    }
+  };
+
+  FaceStyle { italic, weight }
+}
+
+/// Percent-encodes a value for use inside a URL, leaving `/` intact when
+/// `keep_slashes` is set so object paths stay readable.
+fn encode_url_component(value: &str, keep_slashes: bool) -> String {
+  let mut encoded = String::with_capacity(value.len());
+  for byte in value.bytes() {
+    if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) || (keep_slashes && byte == b'/') {
+      encoded.push(byte as char);
+    } else {
+      let _ = write!(encoded, "%{:02X}", byte);
+    }
+  }
+  encoded
+}
+
+fn css_format(format: FontFormat) -> &'static str {
+  match format {
+    FontFormat::Ttf => "truetype",
+    FontFormat::Otf => "opentype",
+    FontFormat::Ttc => "collection",
+    FontFormat::Woff => "woff",
+    FontFormat::Woff2 => "woff2",
+  }
+}
+
+/// Quotes `value` as a CSS string. Control characters cannot appear in a
+/// string literally, so they become hex escapes.
+fn css_string(value: &str) -> String {
+  let mut quoted = String::with_capacity(value.len() + 2);
+  quoted.push('\'');
+  for c in value.chars() {
+    match c {
+      '\\' | '\'' => {
+        quoted.push('\\');
+        quoted.push(c);
+      }
+      c if c.is_control() => {
+        let _ = write!(quoted, "\\{:x} ", u32::from(c));
+      }
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('\'');
+  quoted
+}
+
+/// Builds a signed link to `path` in the caller's library. `@font-face`
+/// sources are fetched without credentials, so they go through `/kit`.
+fn signed_url(
+  signing_key: &[u8; 32],
+  base_url: &str,
+  user_id: &Uuid,
+  path: &str,
+  format: Option<FontFormat>,
+  text: Option<&str>
+) -> String {
+  let format = format.map(|format| format.as_str());
+  let (expires, signature) = sign_font_url(signing_key, user_id, path, format, text);
+
+  let mut url = format!("{}/kit/{}/{}?", base_url, user_id, encode_url_component(path, true));
+  if let Some(format) = format {
+    let _ = write!(url, "format={}&", format);
+  }
+  if let Some(text) = text {
+    let _ = write!(url, "text={}&", encode_url_component(text, false));
+  }
+  let _ = write!(url, "expires={}&signature={}", expires, signature);
+  url
+}
+
+/// Builds the `src` descriptor. A WOFF2 rendition is listed first and the
+/// stored file second, or a WOFF2 subset when the request was limited to `text`.
+fn font_src(
+  signing_key: &[u8; 32],
+  base_url: &str,
+  user_id: &Uuid,
+  path: &str,
+  metadata: &FontMetadata,
+  text: Option<&str>
+) -> String {
+  let format = metadata.container_format.unwrap_or(FontFormat::Ttf);
+  let url = |format, text| signed_url(signing_key, base_url, user_id, path, format, text);
+
+  // Fonts without a known outline format are served whole.
+  if let Some(text) = text && metadata.outline_format.is_some() {
+    return format!("url({}) format('woff2')", url(Some(FontFormat::Woff2), Some(text)));
+  }
+
+  match format {
+    FontFormat::Woff2 => format!("url({}) format('woff2')", url(None, None)),
+    _ =>
+      format!(
+        "url({}) format('woff2'), url({}) format('{}')",
+        url(Some(FontFormat::Woff2), None),
+        url(None, None),
+        css_format(format)
+      ),
+  }
+}
+
+fn base_url(headers: &HeaderMap) -> String {
+  if let Ok(url) = env::var("PUBLIC_URL") {
+    return url.trim_end_matches('/').to_string();
+  }
+
+  let host = headers
+    .get(header::HOST)
+    .and_then(|host| host.to_str().ok())
+    .unwrap_or("localhost:3000");
+  format!("http://{}", host)
+}
+
+/// Serves `@font-face` rules in the shape of the Google Fonts CSS2 API, e.g.
+/// `/css2?family=Inter:ital,wght@0,400;1,700&display=swap`, for the caller's
+/// stored fonts. Sources are signed `/kit` links that stay valid for a few
+/// hours, so the stylesheet itself should not be cached longer than that.
+pub async fn css2_handler(
+  user: AuthUser,
+  headers: HeaderMap,
+  Query(params): Query<Vec<(String, String)>>,
+  State(state): State<AppState>
+) -> impl IntoResponse {
+  let mut families = Vec::new();
+  let mut display = None;
+  let mut text = None;
+  for (name, value) in &params {
+    match name.as_str() {
+      "family" => {
+        // Google also accepts several families separated by `|`.
+        for spec in value.split('|') {
+          families.push(parse_family_spec(spec).map_err(|e| (StatusCode::BAD_REQUEST, e))?);
+        }
+      }
+      "display" => {
+        if !matches!(value.as_str(), "auto" | "block" | "swap" | "fallback" | "optional") {
+          return Err((StatusCode::BAD_REQUEST, format!("Invalid display value '{}'", value)));
+        }
+        display = Some(value.as_str());
+      }
+      "text" if !value.is_empty() => {
+        text = Some(value.as_str());
+      }
+      _ => {}
+    }
+  }
+  if families.is_empty() {
+    return Err((StatusCode::BAD_REQUEST, "Missing 'family' parameter".to_string()));
+  }
+
+  let unicode_range = text.map(|text| {
+    let mut codepoints: Vec<u32> = text.chars().map(u32::from).collect();
+    codepoints.sort_unstable();
+    format_unicode_ranges(&merge_nearby_ranges(&codepoint_ranges(codepoints), MAX_UNICODE_RANGES))
+  });
+  let base_url = base_url(&headers);
+  let user_prefix = format!("{}/", user.user_id);
+
+  let mut css = String::new();
+  for (family, styles) in &families {
+    let records = get_metadata_by_family(&state.db_client, &user.user_id, family).await.map_err(
+      |e| {
+        error!("Failed to look up family {}: {}", family, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up font family".to_string())
+      }
+    )?;
+
+    // Browsers cannot pick a face out of a collection through `src`.
+    let faces: Vec<(&FontRecord, FaceStyle)> = records
+      .iter()
+      .filter(|record| record.metadata.container_format != Some(FontFormat::Ttc))
+      .map(|record| (record, face_style(record)))
+      .collect();
+    if faces.is_empty() {
+      return Err((StatusCode::NOT_FOUND, format!("Font family '{}' not found", family)));
+    }
+
+    let mut emitted: Vec<(&str, (u16, u16), bool)> = Vec::new();
+    for style in styles {
+      for (record, face) in &faces {
+        let weight = (style.weight.0.max(face.weight.0), style.weight.1.min(face.weight.1));
+        if face.italic != style.italic || weight.0 > weight.1 {
+          continue;
+        }
+        if emitted.contains(&(record.object_path.as_str(), weight, face.italic)) {
+          continue;
+        }
+        emitted.push((&record.object_path, weight, face.italic));
+
+        let path = record.object_path.strip_prefix(&user_prefix).unwrap_or(&record.object_path);
+
+        let _ = writeln!(css, "@font-face {{");
+        let _ = writeln!(css, "  font-family: {};", css_string(&record.metadata.font_family));
+        let _ = writeln!(css, "  font-style: {};", if face.italic { "italic" } else { "normal" });
+        if weight.0 == weight.1 {
+          let _ = writeln!(css, "  font-weight: {};", weight.0);
+        } else {
+          let _ = writeln!(css, "  font-weight: {} {};", weight.0, weight.1);
+        }
+        if let Some(display) = display {
+          let _ = writeln!(css, "  font-display: {};", display);
+        }
+        let src = font_src(
+          &state.url_signing_key,
+          &base_url,
+          &user.user_id,
+          path,
+          &record.metadata,
+          text
+        );
+        let _ = writeln!(css, "  src: {};", src);
+        let stored_range = || {
+          let ranges: Vec<_> = record.metadata.unicode_ranges
+            .iter()
+            .map(|&(start, end)| start..=end)
+            .collect();
+          (!ranges.is_empty()).then(|| {
+            format_unicode_ranges(&merge_nearby_ranges(&ranges, MAX_UNICODE_RANGES))
+          })
+        };
+        if let Some(unicode_range) = unicode_range.clone().or_else(stored_range) {
+          let _ = writeln!(css, "  unicode-range: {};", unicode_range);
+        }
+        let _ = writeln!(css, "}}");
+      }
+    }
+  }
+
+  info!(
+    "User {} - Client {} requested CSS for {} family(ies)",
+    user.email,
+    user.client_id,
+    families.len()
+  );
+
+  Ok((
+    StatusCode::OK,
+    [
+      (header::CONTENT_TYPE, "text/css; charset=utf-8"),
+      (header::CACHE_CONTROL, "private, max-age=3600"),
+    ],
+    css,
+  ))
+}
@@ -1,16 +1,219 @@
-use std::env;
+use std::{ collections::HashMap, env };
 use log::{ error, info };
-use serde::Serialize;
-use tokio_postgres::{ NoTls };
+use serde::{ Deserialize, Serialize };
+use tokio_postgres::{ types::{ Json, ToSql }, NoTls, Row };
+
+use crate::{
+  classification::Classification,
+  color::ColorInfo,
+  container::{ FontFormat, OutlineFormat },
+  languages::LanguageSupport,
+  licensing::{ EmbeddingLevel, EmbeddingPermissions },
+  metadata::{ FontMetadata, METADATA_VERSION },
+};
 
 #[derive(Serialize)]
 pub struct FontRecord {
-  pub font_family: String,
-  pub font_subfamily: String,
   pub object_path: String,
-  pub checksum: String,
+  pub face_index: i32,
+  #[serde(flatten)]
+  pub metadata: FontMetadata,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadedPart {
+  pub part_number: i32,
+  pub e_tag: String,
 }
 
+/// A resumable upload in progress. Bytes that do not yet fill an S3 part are
+/// kept in `pending` until more arrive.
+#[derive(Serialize)]
+pub struct UploadSession {
+  pub id: uuid::Uuid,
+  #[serde(skip)]
+  pub user_id: uuid::Uuid,
+  pub path: String,
+  pub file_name: String,
+  pub content_type: String,
+  pub on_conflict: String,
+  pub upload_length: i64,
+  pub upload_offset: i64,
+  #[serde(skip)]
+  pub s3_upload_id: String,
+  #[serde(skip)]
+  pub parts: Vec<UploadedPart>,
+  #[serde(skip)]
+  pub pending: Vec<u8>,
+  pub completed: bool,
+  /// Last progress, in seconds since the epoch; sessions expire after a
+  /// period without any.
+  #[serde(skip)]
+  pub updated_at: i64,
+}
+
+static SCHEMA_MIGRATIONS: &[&str] = &[
+  "ALTER TABLE fonts
+     ADD COLUMN IF NOT EXISTS font_foundry TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS font_designer TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS font_license TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS font_copyright TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS postscript_name TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS full_name TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS version_string TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS trademark TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS vendor_url TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS designer_url TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS license_url TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS sample_text TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS description TEXT NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS localized_names JSONB NOT NULL DEFAULT '[]'",
+  // Rows start at version 0, so everything stored before this migration is
+  // picked up by the metadata backfill.
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS metadata_version INTEGER NOT NULL DEFAULT 0;
+   CREATE INDEX IF NOT EXISTS fonts_metadata_version_idx ON fonts (metadata_version)",
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS variations JSONB NULL",
+  // The old one-row-per-file uniqueness may have any name, and may be a bare
+  // unique index rather than a constraint, so it is found by its columns.
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS face_index INTEGER NOT NULL DEFAULT 0;
+   DO $$
+   DECLARE
+     legacy RECORD;
+   BEGIN
+     FOR legacy IN
+       SELECT idx.indexrelid::regclass::text AS index_name, con.conname
+       FROM pg_index idx
+       LEFT JOIN pg_constraint con
+         ON con.conindid = idx.indexrelid AND con.conrelid = idx.indrelid
+       WHERE idx.indrelid = 'fonts'::regclass AND idx.indisunique AND idx.indnatts = 2
+         AND ARRAY(
+           SELECT att.attname::text FROM pg_attribute att
+           WHERE att.attrelid = idx.indrelid AND att.attnum = ANY(idx.indkey)
+           ORDER BY att.attname
+         ) = ARRAY['object_path', 'user_id']
+     LOOP
+       IF legacy.conname IS NOT NULL THEN
+         EXECUTE format('ALTER TABLE fonts DROP CONSTRAINT %I', legacy.conname);
+       ELSE
+         EXECUTE format('DROP INDEX %s', legacy.index_name);
+       END IF;
+     END LOOP;
+   END
+   $$;
+   CREATE UNIQUE INDEX IF NOT EXISTS fonts_user_id_object_path_face_index_key
+     ON fonts (user_id, object_path, face_index)",
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS container_format TEXT NULL",
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS outline_format TEXT NULL",
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS unicode_ranges JSONB NOT NULL DEFAULT '[]'",
+  "ALTER TABLE fonts
+     ADD COLUMN IF NOT EXISTS languages TEXT[] NOT NULL DEFAULT '{}',
+     ADD COLUMN IF NOT EXISTS partial_languages TEXT[] NOT NULL DEFAULT '{}',
+     ADD COLUMN IF NOT EXISTS scripts TEXT[] NOT NULL DEFAULT '{}',
+     ADD COLUMN IF NOT EXISTS partial_scripts TEXT[] NOT NULL DEFAULT '{}';
+   CREATE INDEX IF NOT EXISTS fonts_languages_idx ON fonts USING GIN (languages);
+   CREATE INDEX IF NOT EXISTS fonts_scripts_idx ON fonts USING GIN (scripts)",
+  "ALTER TABLE fonts
+     ADD COLUMN IF NOT EXISTS weight_class SMALLINT NOT NULL DEFAULT 400,
+     ADD COLUMN IF NOT EXISTS width_class SMALLINT NOT NULL DEFAULT 5,
+     ADD COLUMN IF NOT EXISTS is_italic BOOLEAN NOT NULL DEFAULT FALSE,
+     ADD COLUMN IF NOT EXISTS is_oblique BOOLEAN NOT NULL DEFAULT FALSE,
+     ADD COLUMN IF NOT EXISTS is_monospace BOOLEAN NOT NULL DEFAULT FALSE,
+     ADD COLUMN IF NOT EXISTS panose BYTEA NOT NULL DEFAULT '',
+     ADD COLUMN IF NOT EXISTS vendor_id TEXT NOT NULL DEFAULT '';
+   CREATE INDEX IF NOT EXISTS fonts_weight_width_idx ON fonts (user_id, weight_class, width_class)",
+  "ALTER TABLE fonts
+     ADD COLUMN IF NOT EXISTS fs_type SMALLINT NULL,
+     ADD COLUMN IF NOT EXISTS embedding_level TEXT NULL,
+     ADD COLUMN IF NOT EXISTS allows_subsetting BOOLEAN NOT NULL DEFAULT TRUE,
+     ADD COLUMN IF NOT EXISTS allows_outline_embedding BOOLEAN NOT NULL DEFAULT TRUE",
+  "ALTER TABLE fonts
+     ADD COLUMN IF NOT EXISTS layout JSONB NOT NULL DEFAULT '{}',
+     ADD COLUMN IF NOT EXISTS layout_features TEXT[] NOT NULL DEFAULT '{}',
+     ADD COLUMN IF NOT EXISTS layout_scripts TEXT[] NOT NULL DEFAULT '{}';
+   CREATE INDEX IF NOT EXISTS fonts_layout_features_idx ON fonts USING GIN (layout_features)",
+  "ALTER TABLE fonts
+     ADD COLUMN IF NOT EXISTS color_formats TEXT[] NOT NULL DEFAULT '{}',
+     ADD COLUMN IF NOT EXISTS palette_count INTEGER NOT NULL DEFAULT 0",
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS qa_report JSONB NOT NULL DEFAULT '{}'",
+  "ALTER TABLE fonts ADD COLUMN IF NOT EXISTS font_revision REAL NOT NULL DEFAULT 0;
+   CREATE INDEX IF NOT EXISTS fonts_postscript_name_idx ON fonts (user_id, postscript_name)",
+  "CREATE TABLE IF NOT EXISTS upload_sessions (
+     id UUID PRIMARY KEY,
+     user_id UUID NOT NULL,
+     path TEXT NOT NULL,
+     file_name TEXT NOT NULL,
+     content_type TEXT NOT NULL,
+     on_conflict TEXT NOT NULL,
+     upload_length BIGINT NOT NULL,
+     upload_offset BIGINT NOT NULL DEFAULT 0,
+     s3_upload_id TEXT NOT NULL,
+     parts JSONB NOT NULL DEFAULT '[]',
+     pending BYTEA NOT NULL DEFAULT '',
+     completed BOOLEAN NOT NULL DEFAULT FALSE,
+     created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+     updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+   );
+   CREATE INDEX IF NOT EXISTS upload_sessions_user_id_idx ON upload_sessions (user_id)",
+  "CREATE INDEX IF NOT EXISTS upload_sessions_updated_at_idx ON upload_sessions (updated_at)",
+  // A PATCH holds the lease while it writes parts, so two requests at the same
+  // offset cannot both upload the same part number.
+  "ALTER TABLE upload_sessions
+     ADD COLUMN IF NOT EXISTS lease_id UUID NULL,
+     ADD COLUMN IF NOT EXISTS leased_until TIMESTAMPTZ NULL",
+  // Font files are stored once per checksum. `ref_count` counts the library
+  // paths (face 0 rows) pointing at a blob and is kept current by a trigger,
+  // so every statement that touches `fonts` keeps it right.
+  "CREATE TABLE IF NOT EXISTS blobs (
+     checksum TEXT PRIMARY KEY,
+     state TEXT NOT NULL DEFAULT 'pending',
+     ref_count INTEGER NOT NULL DEFAULT 0,
+     created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+     touched_at TIMESTAMPTZ NOT NULL DEFAULT now()
+   );
+   CREATE INDEX IF NOT EXISTS blobs_orphaned_idx ON blobs (touched_at) WHERE ref_count <= 0;
+   INSERT INTO blobs (checksum, ref_count)
+     SELECT checksum, COUNT(*) FROM fonts
+     WHERE face_index = 0 AND checksum IS NOT NULL
+     GROUP BY checksum
+   ON CONFLICT (checksum) DO NOTHING;
+   CREATE OR REPLACE FUNCTION count_blob_references() RETURNS trigger AS $$
+   BEGIN
+     IF TG_OP <> 'INSERT' AND OLD.face_index = 0 THEN
+       UPDATE blobs SET ref_count = ref_count - 1, touched_at = now()
+       WHERE checksum = OLD.checksum;
+     END IF;
+     IF TG_OP <> 'DELETE' AND NEW.face_index = 0 AND NEW.checksum IS NOT NULL THEN
+       INSERT INTO blobs (checksum, ref_count) VALUES (NEW.checksum, 1)
+       ON CONFLICT (checksum)
+       DO UPDATE SET ref_count = blobs.ref_count + 1, touched_at = now();
+     END IF;
+     RETURN NULL;
+   END
+   $$ LANGUAGE plpgsql;
+   DROP TRIGGER IF EXISTS fonts_blob_references ON fonts;
+   CREATE TRIGGER fonts_blob_references
+     AFTER INSERT OR DELETE OR UPDATE OF checksum, face_index ON fonts
+     FOR EACH ROW EXECUTE FUNCTION count_blob_references()",
+];
+
+/// Condition on `fonts` rows that leaves out files whose bytes were already
+/// gone before the blob store migration. Every query serving library files or
+/// their metadata to users includes it.
+static STORED_FONTS: &str =
+  "NOT EXISTS (
+     SELECT 1 FROM blobs WHERE blobs.checksum = fonts.checksum AND blobs.state = 'missing'
+   )";
+
+static FONT_COLUMNS: &str =
+  "font_family, font_subfamily, object_path, face_index, checksum, container_format,
+   outline_format, font_foundry, font_designer, font_license, font_copyright,
+   postscript_name, full_name, version_string, trademark, vendor_url,
+   designer_url, license_url, sample_text, description, localized_names, variations,
+   unicode_ranges, languages, partial_languages, scripts, partial_scripts, weight_class,
+   width_class, is_italic, is_oblique, is_monospace, panose, vendor_id, fs_type, embedding_level,
+   allows_subsetting, allows_outline_embedding, layout, color_formats, palette_count, qa_report,
+   font_revision";
+
 pub async fn connect_db() -> Result<tokio_postgres::Client, Box<dyn std::error::Error>> {
   info!("Connecting to database...");
   let db_url = env::var("DATABASE_URL").expect("invalid database url");
@@ -27,6 +230,84 @@ pub async fn connect_db() -> Result<tokio_postgres::Client, Box<dyn std::error::
   Ok(client)
 }
 
+pub async fn migrate_database(
+  client: &tokio_postgres::Client
+) -> Result<(), Box<dyn std::error::Error>> {
+  for migration in SCHEMA_MIGRATIONS {
+    client.batch_execute(migration).await?;
+  }
+  info!("Applied {} schema migration(s).", SCHEMA_MIGRATIONS.len());
+
+  Ok(())
+}
+
+fn font_record_from_row(row: &Row) -> FontRecord {
+  let Json(localized_names) = row.get("localized_names");
+  let variations: Option<Json<_>> = row.get("variations");
+  let Json(unicode_ranges) = row.get("unicode_ranges");
+  let Json(layout) = row.get("layout");
+  let Json(qa_report) = row.get("qa_report");
+  let container_format: Option<&str> = row.get("container_format");
+  let outline_format: Option<&str> = row.get("outline_format");
+  let embedding_level: Option<&str> = row.get("embedding_level");
+
+  FontRecord {
+    object_path: row.get("object_path"),
+    face_index: row.get("face_index"),
+    metadata: FontMetadata {
+      font_family: row.get("font_family"),
+      font_subfamily: row.get("font_subfamily"),
+      checksum: row.get("checksum"),
+      container_format: container_format.and_then(FontFormat::from_name),
+      outline_format: outline_format.and_then(OutlineFormat::from_name),
+      font_foundry: row.get("font_foundry"),
+      font_designer: row.get("font_designer"),
+      font_license: row.get("font_license"),
+      font_copyright: row.get("font_copyright"),
+      postscript_name: row.get("postscript_name"),
+      full_name: row.get("full_name"),
+      version_string: row.get("version_string"),
+      font_revision: row.get("font_revision"),
+      trademark: row.get("trademark"),
+      vendor_url: row.get("vendor_url"),
+      designer_url: row.get("designer_url"),
+      license_url: row.get("license_url"),
+      sample_text: row.get("sample_text"),
+      description: row.get("description"),
+      localized_names,
+      variations: variations.map(|Json(v)| v),
+      unicode_ranges,
+      language_support: LanguageSupport {
+        languages: row.get("languages"),
+        partial_languages: row.get("partial_languages"),
+        scripts: row.get("scripts"),
+        partial_scripts: row.get("partial_scripts"),
+      },
+      classification: Classification {
+        weight_class: row.get("weight_class"),
+        width_class: row.get("width_class"),
+        is_italic: row.get("is_italic"),
+        is_oblique: row.get("is_oblique"),
+        is_monospace: row.get("is_monospace"),
+        panose: row.get("panose"),
+        vendor_id: row.get("vendor_id"),
+      },
+      embedding: EmbeddingPermissions {
+        fs_type: row.get("fs_type"),
+        embedding_level: embedding_level.and_then(EmbeddingLevel::from_name),
+        allows_subsetting: row.get("allows_subsetting"),
+        allows_outline_embedding: row.get("allows_outline_embedding"),
+      },
+      layout,
+      color: ColorInfo {
+        color_formats: row.get("color_formats"),
+        palette_count: row.get("palette_count"),
+      },
+      qa_report,
+    },
+  }
+}
+
 pub async fn insert_metadata(
   client: &tokio_postgres::Client,
   user_id: &uuid::Uuid,
@@ -37,41 +318,150 @@ pub async fn insert_metadata(
   }
 
   let stmt = client.prepare(
-    "INSERT INTO fonts (user_id, font_family, font_subfamily, object_path, checksum) 
-     VALUES ($1, $2, $3, $4, $5)
-     ON CONFLICT (user_id, object_path)
+    "INSERT INTO fonts (user_id, font_family, font_subfamily, object_path, face_index, checksum,
+       container_format, font_foundry, font_designer, font_license, font_copyright,
+       postscript_name, full_name, version_string, trademark, vendor_url, designer_url,
+       license_url, sample_text, description, localized_names, metadata_version, variations,
+       outline_format, unicode_ranges, languages, partial_languages, scripts, partial_scripts,
+       weight_class, width_class, is_italic, is_oblique, is_monospace, panose, vendor_id, fs_type,
+       embedding_level, allows_subsetting, allows_outline_embedding, layout, layout_features,
+       layout_scripts, color_formats, palette_count, qa_report, font_revision)
+     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19,
+       $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32, $33, $34, $35, $36, $37,
+       $38, $39, $40, $41, $42, $43, $44, $45, $46, $47)
+     ON CONFLICT (user_id, object_path, face_index)
      DO UPDATE SET 
        font_family = EXCLUDED.font_family,
        font_subfamily = EXCLUDED.font_subfamily,
-       checksum = EXCLUDED.checksum"
+       checksum = EXCLUDED.checksum,
+       container_format = EXCLUDED.container_format,
+       font_foundry = EXCLUDED.font_foundry,
+       font_designer = EXCLUDED.font_designer,
+       font_license = EXCLUDED.font_license,
+       font_copyright = EXCLUDED.font_copyright,
+       postscript_name = EXCLUDED.postscript_name,
+       full_name = EXCLUDED.full_name,
+       version_string = EXCLUDED.version_string,
+       trademark = EXCLUDED.trademark,
+       vendor_url = EXCLUDED.vendor_url,
+       designer_url = EXCLUDED.designer_url,
+       license_url = EXCLUDED.license_url,
+       sample_text = EXCLUDED.sample_text,
+       description = EXCLUDED.description,
+       localized_names = EXCLUDED.localized_names,
+       metadata_version = EXCLUDED.metadata_version,
+       variations = EXCLUDED.variations,
+       outline_format = EXCLUDED.outline_format,
+       unicode_ranges = EXCLUDED.unicode_ranges,
+       languages = EXCLUDED.languages,
+       partial_languages = EXCLUDED.partial_languages,
+       scripts = EXCLUDED.scripts,
+       partial_scripts = EXCLUDED.partial_scripts,
+       weight_class = EXCLUDED.weight_class,
+       width_class = EXCLUDED.width_class,
+       is_italic = EXCLUDED.is_italic,
+       is_oblique = EXCLUDED.is_oblique,
+       is_monospace = EXCLUDED.is_monospace,
+       panose = EXCLUDED.panose,
+       vendor_id = EXCLUDED.vendor_id,
+       fs_type = EXCLUDED.fs_type,
+       embedding_level = EXCLUDED.embedding_level,
+       allows_subsetting = EXCLUDED.allows_subsetting,
+       allows_outline_embedding = EXCLUDED.allows_outline_embedding,
+       layout = EXCLUDED.layout,
+       layout_features = EXCLUDED.layout_features,
+       layout_scripts = EXCLUDED.layout_scripts,
+       color_formats = EXCLUDED.color_formats,
+       palette_count = EXCLUDED.palette_count,
+       qa_report = EXCLUDED.qa_report,
+       font_revision = EXCLUDED.font_revision"
   ).await?;
 
   for record in records {
+    let metadata = &record.metadata;
     client.execute(
       &stmt,
       &[
         &user_id,
-        &record.font_family,
-        &record.font_subfamily,
+        &metadata.font_family,
+        &metadata.font_subfamily,
         &record.object_path,
-        &record.checksum,
+        &record.face_index,
+        &metadata.checksum,
+        &metadata.container_format.map(|format| format.as_str()),
+        &metadata.font_foundry,
+        &metadata.font_designer,
+        &metadata.font_license,
+        &metadata.font_copyright,
+        &metadata.postscript_name,
+        &metadata.full_name,
+        &metadata.version_string,
+        &metadata.trademark,
+        &metadata.vendor_url,
+        &metadata.designer_url,
+        &metadata.license_url,
+        &metadata.sample_text,
+        &metadata.description,
+        &Json(&metadata.localized_names),
+        &METADATA_VERSION,
+        &metadata.variations.as_ref().map(Json),
+        &metadata.outline_format.map(|format| format.as_str()),
+        &Json(&metadata.unicode_ranges),
+        &metadata.language_support.languages,
+        &metadata.language_support.partial_languages,
+        &metadata.language_support.scripts,
+        &metadata.language_support.partial_scripts,
+        &metadata.classification.weight_class,
+        &metadata.classification.width_class,
+        &metadata.classification.is_italic,
+        &metadata.classification.is_oblique,
+        &metadata.classification.is_monospace,
+        &metadata.classification.panose,
+        &metadata.classification.vendor_id,
+        &metadata.embedding.fs_type,
+        &metadata.embedding.embedding_level.map(|level| level.as_str()),
+        &metadata.embedding.allows_subsetting,
+        &metadata.embedding.allows_outline_embedding,
+        &Json(&metadata.layout),
+        &metadata.layout.feature_tags(),
+        &metadata.layout.script_tags(),
+        &metadata.color.color_formats,
+        &metadata.color.palette_count,
+        &Json(&metadata.qa_report),
+        &metadata.font_revision,
       ]
     ).await?;
   }
 
+  // A re-uploaded collection may hold fewer faces than the one it replaced.
+  let prune_stmt = client.prepare(
+    "DELETE FROM fonts WHERE user_id = $1 AND object_path = $2 AND face_index > $3"
+  ).await?;
+  let mut last_faces: HashMap<&str, i32> = HashMap::new();
+  for record in records {
+    let last_face = last_faces.entry(&record.object_path).or_insert(record.face_index);
+    *last_face = (*last_face).max(record.face_index);
+  }
+  for (object_path, last_face) in last_faces {
+    client.execute(&prune_stmt, &[&user_id, &object_path, &last_face]).await?;
+  }
+
   Ok(())
 }
 
+/// Deletes every face of a file, returning the family of each deleted row.
 pub async fn delete_metadata(
   client: &tokio_postgres::Client,
   user_id: &uuid::Uuid,
   object_path: &str
-) -> Result<u64, Box<dyn std::error::Error>> {
-  let stmt = client.prepare("DELETE FROM fonts WHERE user_id = $1 AND object_path = $2").await?;
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+  let stmt = client.prepare(
+    "DELETE FROM fonts WHERE user_id = $1 AND object_path = $2 RETURNING font_family"
+  ).await?;
 
-  let row_affected = client.execute(&stmt, &[user_id, &object_path]).await?;
+  let rows = client.query(&stmt, &[user_id, &object_path]).await?;
 
-  Ok(row_affected)
+  Ok(rows.iter().map(|row| row.get("font_family")).collect())
 }
 
 pub async fn check_duplicate(
@@ -81,7 +471,8 @@ pub async fn check_duplicate(
   object_path: &str
 ) -> Result<Option<String>, Box<dyn std::error::Error>> {
   let query =
-    "SELECT object_path FROM fonts WHERE user_id = $1 AND checksum = $2 AND object_path = $3";
+    "SELECT object_path FROM fonts WHERE user_id = $1 AND checksum = $2 AND object_path = $3
+     LIMIT 1";
 
   match client.query_opt(query, &[&user_id, &checksum, &object_path]).await? {
     Some(row) => Ok(Some(row.get("object_path"))),
@@ -89,25 +480,620 @@ pub async fn check_duplicate(
   }
 }
 
+/// Checksum of the file stored at a library path, which is also its blob key.
+pub async fn get_path_checksum(
+  client: &tokio_postgres::Client,
+  user_id: &uuid::Uuid,
+  object_path: &str
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+  let query = format!(
+    "SELECT checksum FROM fonts
+     WHERE user_id = $1 AND object_path = $2 AND face_index = 0 AND {}",
+    STORED_FONTS
+  );
+  let row = client.query_opt(&query, &[&user_id, &object_path]).await?;
+
+  Ok(row.and_then(|row| row.get("checksum")))
+}
+
+/// Faces elsewhere in the user's library that claim one of the given PostScript
+/// names but hold different data.
+pub async fn find_postscript_conflicts(
+  client: &tokio_postgres::Client,
+  user_id: &uuid::Uuid,
+  postscript_names: &[String],
+  checksum: &str,
+  object_path: &str
+) -> Result<Vec<FontRecord>, Box<dyn std::error::Error>> {
+  let query = format!(
+    "SELECT {} FROM fonts
+     WHERE user_id = $1 AND postscript_name = ANY($2) AND checksum <> $3 AND object_path <> $4
+     ORDER BY object_path, face_index",
+    FONT_COLUMNS
+  );
+  let rows = client.query(&query, &[&user_id, &postscript_names, &checksum, &object_path]).await?;
+
+  Ok(rows.iter().map(font_record_from_row).collect())
+}
+
+pub async fn create_upload_session(
+  client: &tokio_postgres::Client,
+  session: &UploadSession
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute(
+    "INSERT INTO upload_sessions
+       (id, user_id, path, file_name, content_type, on_conflict, upload_length, s3_upload_id)
+     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    &[
+      &session.id,
+      &session.user_id,
+      &session.path,
+      &session.file_name,
+      &session.content_type,
+      &session.on_conflict,
+      &session.upload_length,
+      &session.s3_upload_id,
+    ]
+  ).await?;
+
+  Ok(())
+}
+
+static UPLOAD_SESSION_COLUMNS: &str =
+  "id, user_id, path, file_name, content_type, on_conflict, upload_length, upload_offset,
+   s3_upload_id, parts, pending, completed, EXTRACT(EPOCH FROM updated_at)::int8 AS updated_at";
+
+fn upload_session_from_row(row: &Row) -> UploadSession {
+  let Json(parts) = row.get("parts");
+  UploadSession {
+    id: row.get("id"),
+    user_id: row.get("user_id"),
+    path: row.get("path"),
+    file_name: row.get("file_name"),
+    content_type: row.get("content_type"),
+    on_conflict: row.get("on_conflict"),
+    upload_length: row.get("upload_length"),
+    upload_offset: row.get("upload_offset"),
+    s3_upload_id: row.get("s3_upload_id"),
+    parts,
+    pending: row.get("pending"),
+    completed: row.get("completed"),
+    updated_at: row.get("updated_at"),
+  }
+}
+
+pub async fn get_upload_session(
+  client: &tokio_postgres::Client,
+  user_id: &uuid::Uuid,
+  id: &uuid::Uuid
+) -> Result<Option<UploadSession>, Box<dyn std::error::Error>> {
+  let query = format!(
+    "SELECT {} FROM upload_sessions WHERE id = $1 AND user_id = $2",
+    UPLOAD_SESSION_COLUMNS
+  );
+  let row = client.query_opt(&query, &[&id, &user_id]).await?;
+
+  Ok(row.as_ref().map(upload_session_from_row))
+}
+
+/// Sessions of any user that made no progress for `max_idle_secs`, oldest first.
+pub async fn get_expired_upload_sessions(
+  client: &tokio_postgres::Client,
+  max_idle_secs: f64,
+  limit: i64
+) -> Result<Vec<UploadSession>, Box<dyn std::error::Error>> {
+  let query = format!(
+    "SELECT {} FROM upload_sessions
+     WHERE updated_at < now() - make_interval(secs => $1)
+     ORDER BY updated_at
+     LIMIT $2",
+    UPLOAD_SESSION_COLUMNS
+  );
+  let rows = client.query(&query, &[&max_idle_secs, &limit]).await?;
+
+  Ok(rows.iter().map(upload_session_from_row).collect())
+}
+
+/// Takes the write lease on a session still at `offset`, unless another
+/// request holds an unexpired one. Returns false when the lease was not taken.
+pub async fn claim_upload_lease(
+  client: &tokio_postgres::Client,
+  id: &uuid::Uuid,
+  offset: i64,
+  lease_id: &uuid::Uuid,
+  lease_secs: f64
+) -> Result<bool, Box<dyn std::error::Error>> {
+  let updated = client.execute(
+    "UPDATE upload_sessions
+     SET lease_id = $3, leased_until = now() + make_interval(secs => $4), updated_at = now()
+     WHERE id = $1 AND upload_offset = $2 AND NOT completed
+       AND (leased_until IS NULL OR leased_until < now())",
+    &[&id, &offset, &lease_id, &lease_secs]
+  ).await?;
+
+  Ok(updated == 1)
+}
+
+/// Extends a lease the caller still holds. Returns false when another request
+/// took it over after it expired.
+pub async fn renew_upload_lease(
+  client: &tokio_postgres::Client,
+  id: &uuid::Uuid,
+  lease_id: &uuid::Uuid,
+  lease_secs: f64
+) -> Result<bool, Box<dyn std::error::Error>> {
+  let updated = client.execute(
+    "UPDATE upload_sessions
+     SET leased_until = now() + make_interval(secs => $3), updated_at = now()
+     WHERE id = $1 AND lease_id = $2",
+    &[&id, &lease_id, &lease_secs]
+  ).await?;
+
+  Ok(updated == 1)
+}
+
+pub async fn release_upload_lease(
+  client: &tokio_postgres::Client,
+  id: &uuid::Uuid,
+  lease_id: &uuid::Uuid
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute(
+    "UPDATE upload_sessions SET lease_id = NULL, leased_until = NULL
+     WHERE id = $1 AND lease_id = $2",
+    &[&id, &lease_id]
+  ).await?;
+
+  Ok(())
+}
+
+/// Records progress only if the caller still holds the lease and no other
+/// request moved the session past `expected_offset`. Returns false otherwise.
+pub async fn update_upload_progress(
+  client: &tokio_postgres::Client,
+  id: &uuid::Uuid,
+  lease_id: &uuid::Uuid,
+  expected_offset: i64,
+  upload_offset: i64,
+  parts: &[UploadedPart],
+  pending: &[u8]
+) -> Result<bool, Box<dyn std::error::Error>> {
+  let updated = client.execute(
+    "UPDATE upload_sessions
+     SET upload_offset = $4, parts = $5, pending = $6, updated_at = now()
+     WHERE id = $1 AND lease_id = $2 AND upload_offset = $3 AND NOT completed",
+    &[&id, &lease_id, &expected_offset, &upload_offset, &Json(parts), &pending]
+  ).await?;
+
+  Ok(updated == 1)
+}
+
+pub async fn mark_upload_completed(
+  client: &tokio_postgres::Client,
+  id: &uuid::Uuid
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute(
+    "UPDATE upload_sessions
+     SET completed = TRUE, parts = '[]', pending = '', updated_at = now()
+     WHERE id = $1",
+    &[&id]
+  ).await?;
+
+  Ok(())
+}
+
+pub async fn delete_upload_session(
+  client: &tokio_postgres::Client,
+  id: &uuid::Uuid
+) -> Result<u64, Box<dyn std::error::Error>> {
+  Ok(client.execute("DELETE FROM upload_sessions WHERE id = $1", &[&id]).await?)
+}
+
+/// Registers an upload's interest in a blob and returns its state: `stored`,
+/// `pending` while its bytes still have to be written, `missing` when the
+/// blob store migration found no object to move, or `deleting` while the
+/// garbage collector removes it. Claiming restarts the grace period orphaned
+/// blobs get before they are collected.
+pub async fn claim_blob(
+  client: &tokio_postgres::Client,
+  checksum: &str
+) -> Result<String, Box<dyn std::error::Error>> {
+  let row = client.query_one(
+    "INSERT INTO blobs (checksum) VALUES ($1)
+     ON CONFLICT (checksum) DO UPDATE SET touched_at = now()
+     RETURNING state",
+    &[&checksum]
+  ).await?;
+
+  Ok(row.get("state"))
+}
+
+pub async fn mark_blob_stored(
+  client: &tokio_postgres::Client,
+  checksum: &str
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute(
+    "UPDATE blobs SET state = 'stored', touched_at = now()
+     WHERE checksum = $1 AND state IN ('pending', 'missing')",
+    &[&checksum]
+  ).await?;
+
+  Ok(())
+}
+
+/// Blobs recorded before the blob store existed, after the checksum given as
+/// `after`, with the library paths whose objects still hold their bytes.
+pub async fn get_unstored_blobs(
+  client: &tokio_postgres::Client,
+  after: &str,
+  limit: i64
+) -> Result<Vec<(String, Vec<String>)>, Box<dyn std::error::Error>> {
+  let rows = client.query(
+    "SELECT blobs.checksum, array_agg(fonts.object_path ORDER BY fonts.object_path) AS object_paths
+     FROM blobs JOIN fonts ON fonts.checksum = blobs.checksum AND fonts.face_index = 0
+     WHERE blobs.state = 'pending' AND blobs.checksum > $1
+     GROUP BY blobs.checksum
+     ORDER BY blobs.checksum
+     LIMIT $2",
+    &[&after, &limit]
+  ).await?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|row| (row.get("checksum"), row.get("object_paths")))
+      .collect()
+  )
+}
+
+/// The given library paths whose bytes have not been moved into the blob store,
+/// so their objects under `{user_id}/{path}` are still needed.
+pub async fn get_unmoved_paths(
+  client: &tokio_postgres::Client,
+  object_paths: &[String]
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+  let rows = client.query(
+    "SELECT DISTINCT fonts.object_path
+     FROM fonts LEFT JOIN blobs ON blobs.checksum = fonts.checksum
+     WHERE fonts.object_path = ANY($1) AND blobs.state IS DISTINCT FROM 'stored'",
+    &[&object_paths]
+  ).await?;
+
+  Ok(rows.iter().map(|row| row.get("object_path")).collect())
+}
+
+/// Records that none of a blob's library paths had an object left to move, so
+/// listings hide them and the migration stops retrying.
+pub async fn mark_blob_missing(
+  client: &tokio_postgres::Client,
+  checksum: &str
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute(
+    "UPDATE blobs SET state = 'missing' WHERE checksum = $1 AND state = 'pending'",
+    &[&checksum]
+  ).await?;
+
+  Ok(())
+}
+
+/// Marks up to `limit` blobs that no path has referenced for `grace_secs` as
+/// being deleted and returns them. Blobs left in that state by an earlier run
+/// are returned again.
+pub async fn mark_orphaned_blobs(
+  client: &tokio_postgres::Client,
+  grace_secs: f64,
+  limit: i64
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+  let rows = client.query(
+    "UPDATE blobs SET state = 'deleting'
+     WHERE checksum IN (
+       SELECT checksum FROM blobs
+       WHERE ref_count <= 0
+         AND (state = 'deleting' OR touched_at < now() - make_interval(secs => $1))
+       ORDER BY touched_at
+       LIMIT $2
+     )
+       AND ref_count <= 0
+       AND (state = 'deleting' OR touched_at < now() - make_interval(secs => $1))
+     RETURNING checksum",
+    &[&grace_secs, &limit]
+  ).await?;
+
+  Ok(rows.iter().map(|row| row.get("checksum")).collect())
+}
+
+/// Drops a blob's row once its object is gone. Returns `false` if a path took
+/// a reference in the meantime.
+pub async fn delete_blob_record(
+  client: &tokio_postgres::Client,
+  checksum: &str
+) -> Result<bool, Box<dyn std::error::Error>> {
+  let deleted = client.execute(
+    "DELETE FROM blobs WHERE checksum = $1 AND state = 'deleting' AND ref_count <= 0",
+    &[&checksum]
+  ).await?;
+
+  Ok(deleted == 1)
+}
+
+/// Records that a blob's object may be gone, so the next upload of the same
+/// bytes writes them again.
+pub async fn mark_blob_unstored(
+  client: &tokio_postgres::Client,
+  checksum: &str
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute("UPDATE blobs SET state = 'pending' WHERE checksum = $1", &[&checksum]).await?;
+
+  Ok(())
+}
+
+/// Optional filters for listing fonts. List values are comma-separated and a
+/// font has to match all of them.
+#[derive(Debug, Default, Deserialize)]
+pub struct FontFilter {
+  pub language: Option<String>,
+  pub script: Option<String>,
+  /// Also match languages and scripts that are only partially covered.
+  #[serde(default)]
+  pub include_partial: bool,
+  pub min_weight: Option<i16>,
+  pub max_weight: Option<i16>,
+  pub min_width: Option<i16>,
+  pub max_width: Option<i16>,
+  pub italic: Option<bool>,
+  pub monospace: Option<bool>,
+  pub vendor: Option<String>,
+  /// OpenType feature tags from GSUB/GPOS, e.g. `smcp,tnum`.
+  pub feature: Option<String>,
+  /// OpenType layout script tags, e.g. `cyrl`.
+  pub layout_script: Option<String>,
+  /// Color glyph formats, e.g. `COLRv1`.
+  pub color_format: Option<String>,
+  /// Only fonts with (or without) any color glyph format.
+  pub color: Option<bool>,
+  /// Comma-separated sort keys, descending when prefixed with `-`, e.g.
+  /// `family,-weight`.
+  pub sort: Option<String>,
+}
+
+static SORT_COLUMNS: &[(&str, &str)] = &[
+  ("family", "font_family"),
+  ("subfamily", "font_subfamily"),
+  ("path", "object_path"),
+  ("weight", "weight_class"),
+  ("width", "width_class"),
+];
+
+impl FontFilter {
+  /// Builds the ORDER BY clause, rejecting unknown sort keys.
+  pub fn order_by(&self) -> Result<String, String> {
+    let mut terms = Vec::new();
+    for key in split_list(self.sort.as_deref()).unwrap_or_default() {
+      let (name, direction) = match key.strip_prefix('-') {
+        Some(name) => (name, "DESC"),
+        None => (key.as_str(), "ASC"),
+      };
+      let column = SORT_COLUMNS.iter()
+        .find(|(sort_key, _)| *sort_key == name)
+        .map(|(_, column)| *column)
+        .ok_or_else(|| format!("Unknown sort key '{}'", name))?;
+      terms.push(format!("{} {}", column, direction));
+    }
+    terms.push("object_path ASC, face_index ASC".to_string());
+
+    Ok(terms.join(", "))
+  }
+}
+
+fn split_list(value: Option<&str>) -> Option<Vec<String>> {
+  let items: Vec<String> = value?
+    .split(',')
+    .map(str::trim)
+    .filter(|item| !item.is_empty())
+    .map(str::to_string)
+    .collect();
+  (!items.is_empty()).then_some(items)
+}
+
 pub async fn get_metadata(
   client: &tokio_postgres::Client,
-  user_id: &uuid::Uuid
+  user_id: &uuid::Uuid,
+  filter: &FontFilter
 ) -> Result<Vec<FontRecord>, Box<dyn std::error::Error>> {
-  let query =
-    "SELECT font_family, font_subfamily, object_path, checksum FROM fonts WHERE user_id = $1";
-  let rows = client.query(query, &[&user_id]).await?;
+  let mut conditions = vec!["user_id = $1".to_string(), STORED_FONTS.to_string()];
+  let mut params: Vec<Box<dyn ToSql + Sync + Send>> = vec![Box::new(*user_id)];
 
-  let mut font_records = Vec::new();
-  for row in rows {
-    font_records.push(FontRecord {
-      font_family: row.get("font_family"),
-      font_subfamily: row.get("font_subfamily"),
-      object_path: row.get("object_path"),
-      checksum: row.get("checksum"),
-    });
+  let array_filters = [
+    (filter.language.as_deref(), "languages", Some("partial_languages")),
+    (filter.script.as_deref(), "scripts", Some("partial_scripts")),
+    (filter.feature.as_deref(), "layout_features", None),
+    (filter.layout_script.as_deref(), "layout_scripts", None),
+    (filter.color_format.as_deref(), "color_formats", None),
+  ];
+  for (value, column, partial_column) in array_filters {
+    if let Some(items) = split_list(value) {
+      params.push(Box::new(items));
+      match partial_column {
+        Some(partial_column) if filter.include_partial => {
+          conditions.push(format!("({} || {}) @> ${}", column, partial_column, params.len()));
+        }
+        _ => {
+          conditions.push(format!("{} @> ${}", column, params.len()));
+        }
+      }
+    }
   }
 
-  Ok(font_records)
+  let range_filters = [
+    (filter.min_weight, "weight_class >="),
+    (filter.max_weight, "weight_class <="),
+    (filter.min_width, "width_class >="),
+    (filter.max_width, "width_class <="),
+  ];
+  for (value, condition) in range_filters {
+    if let Some(value) = value {
+      params.push(Box::new(value));
+      conditions.push(format!("{} ${}", condition, params.len()));
+    }
+  }
+
+  let flag_filters = [(filter.italic, "is_italic"), (filter.monospace, "is_monospace")];
+  for (value, column) in flag_filters {
+    if let Some(value) = value {
+      params.push(Box::new(value));
+      conditions.push(format!("{} = ${}", column, params.len()));
+    }
+  }
+
+  if let Some(color) = filter.color {
+    conditions.push(format!("(cardinality(color_formats) > 0) = {}", color));
+  }
+
+  if let Some(vendor) = filter.vendor.as_deref() {
+    params.push(Box::new(vendor.to_string()));
+    conditions.push(format!("upper(vendor_id) = upper(${})", params.len()));
+  }
+
+  let query = format!(
+    "SELECT {} FROM fonts WHERE {} ORDER BY {}",
+    FONT_COLUMNS,
+    conditions.join(" AND "),
+    filter.order_by()?
+  );
+  let param_refs: Vec<&(dyn ToSql + Sync)> = params
+    .iter()
+    .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+    .collect();
+  let rows = client.query(&query, &param_refs).await?;
+
+  Ok(rows.iter().map(font_record_from_row).collect())
+}
+
+/// Returns the fonts supporting at least `min_supported` of the codepoints in
+/// `ranges`, with how many they support, best coverage first. Both sides are
+/// disjoint ranges, so summing their pairwise overlaps counts each codepoint once.
+pub async fn find_covering_fonts(
+  client: &tokio_postgres::Client,
+  user_id: &uuid::Uuid,
+  ranges: &[(u32, u32)],
+  min_supported: i64
+) -> Result<Vec<(FontRecord, i64)>, Box<dyn std::error::Error>> {
+  let starts: Vec<i64> = ranges.iter().map(|&(start, _)| start.into()).collect();
+  let ends: Vec<i64> = ranges.iter().map(|&(_, end)| end.into()).collect();
+  let query = format!(
+    "SELECT {}, covered.supported FROM fonts
+     CROSS JOIN LATERAL (
+       SELECT COALESCE(SUM(
+         LEAST((stored.range->>1)::int8, requested.range_end) -
+           GREATEST((stored.range->>0)::int8, requested.range_start) + 1
+       ), 0)::int8 AS supported
+       FROM jsonb_array_elements(fonts.unicode_ranges) AS stored(range)
+       JOIN unnest($2::int8[], $3::int8[]) AS requested(range_start, range_end)
+         ON (stored.range->>0)::int8 <= requested.range_end
+         AND (stored.range->>1)::int8 >= requested.range_start
+     ) AS covered
+     WHERE user_id = $1 AND covered.supported >= $4 AND {}
+     ORDER BY covered.supported DESC, font_family, font_subfamily, object_path, face_index",
+    FONT_COLUMNS,
+    STORED_FONTS
+  );
+  let rows = client.query(&query, &[&user_id, &starts, &ends, &min_supported]).await?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|row| (font_record_from_row(row), row.get("supported")))
+      .collect()
+  )
+}
+
+pub async fn get_metadata_by_path(
+  client: &tokio_postgres::Client,
+  user_id: &uuid::Uuid,
+  object_path: &str
+) -> Result<Vec<FontRecord>, Box<dyn std::error::Error>> {
+  let query = format!(
+    "SELECT {} FROM fonts WHERE user_id = $1 AND object_path = $2 AND {} ORDER BY face_index",
+    FONT_COLUMNS,
+    STORED_FONTS
+  );
+  let rows = client.query(&query, &[&user_id, &object_path]).await?;
+
+  Ok(rows.iter().map(font_record_from_row).collect())
+}
+
+pub async fn get_metadata_by_family(
+  client: &tokio_postgres::Client,
+  user_id: &uuid::Uuid,
+  font_family: &str
+) -> Result<Vec<FontRecord>, Box<dyn std::error::Error>> {
+  let query = format!(
+    "SELECT {} FROM fonts WHERE user_id = $1 AND lower(font_family) = lower($2) AND {}
+     ORDER BY object_path, face_index",
+    FONT_COLUMNS,
+    STORED_FONTS
+  );
+  let rows = client.query(&query, &[&user_id, &font_family]).await?;
+
+  Ok(rows.iter().map(font_record_from_row).collect())
+}
+
+/// Library files whose metadata was extracted by an older version of the
+/// extractor, after the `(user_id, object_path)` given as `after`.
+pub async fn get_stale_metadata(
+  client: &tokio_postgres::Client,
+  after: &(uuid::Uuid, String),
+  limit: i64
+) -> Result<Vec<(uuid::Uuid, String, String)>, Box<dyn std::error::Error>> {
+  let rows = client.query(
+    "SELECT user_id, object_path, checksum FROM fonts
+     WHERE face_index = 0 AND metadata_version < $1 AND checksum IS NOT NULL
+       AND (user_id, object_path) > ($2, $3)
+     ORDER BY user_id, object_path
+     LIMIT $4",
+    &[&METADATA_VERSION, &after.0, &after.1, &limit]
+  ).await?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|row| (row.get("user_id"), row.get("object_path"), row.get("checksum")))
+      .collect()
+  )
+}
+
+/// Records that a file's metadata can't be extracted again, so the backfill
+/// stops retrying it.
+pub async fn mark_metadata_current(
+  client: &tokio_postgres::Client,
+  user_id: &uuid::Uuid,
+  object_path: &str
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute(
+    "UPDATE fonts SET metadata_version = $3 WHERE user_id = $1 AND object_path = $2",
+    &[&user_id, &object_path, &METADATA_VERSION]
+  ).await?;
+
+  Ok(())
+}
+
+/// Takes a session-level advisory lock without waiting, so only one server
+/// instance runs a given background job. Returns false if another holds it.
+pub async fn try_advisory_lock(
+  client: &tokio_postgres::Client,
+  key: i64
+) -> Result<bool, Box<dyn std::error::Error>> {
+  let row = client.query_one("SELECT pg_try_advisory_lock($1) AS locked", &[&key]).await?;
+
+  Ok(row.get("locked"))
+}
+
+pub async fn advisory_unlock(
+  client: &tokio_postgres::Client,
+  key: i64
+) -> Result<(), Box<dyn std::error::Error>> {
+  client.execute("SELECT pg_advisory_unlock($1)", &[&key]).await?;
+
+  Ok(())
 }
 
 // static CREATE_FONT_TABLE_SQL: &str =
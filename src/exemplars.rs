@@ -0,0 +1,106 @@
+//! Main exemplar character sets in CLDR `UnicodeSet` notation, keyed by BCP 47
+//! tag with their ISO 15924 script. Sets are lowercase; uppercase forms are
+//! derived when the sets are loaded. The CJK entries list a core set of common
+//! characters rather than the full CLDR inventory.
+
+pub struct Exemplar {
+  pub tag: &'static str,
+  pub script: &'static str,
+  pub characters: &'static str,
+}
+
+pub static EXEMPLARS: &[Exemplar] = &[
+  // Latin
+  Exemplar { tag: "af", script: "Latn", characters: "[a á â b-e é è ê ë f-i î ï j-n ŉ o ô p-u û v-z]" },
+  Exemplar { tag: "az", script: "Latn", characters: "[a b c ç d e ə f g ğ h x ı i İ j k q l m n o ö p r s ş t u ü v y z]" },
+  Exemplar { tag: "ca", script: "Latn", characters: "[a à b c ç d e é è f-i í ï j-o ó ò p-u ú ü v-z]" },
+  Exemplar { tag: "cs", script: "Latn", characters: "[a á b c č d ď e é ě f-i í j-n ň o ó p-r ř s š t ť u ú ů v-y ý z ž]" },
+  Exemplar { tag: "cy", script: "Latn", characters: "[a â b-e ê f-i î l-o ô p r-u û w ŵ y ŷ]" },
+  Exemplar { tag: "da", script: "Latn", characters: "[a-z æ ø å]" },
+  Exemplar { tag: "de", script: "Latn", characters: "[a ä b-o ö p-s ß t u ü v-z]" },
+  Exemplar { tag: "en", script: "Latn", characters: "[a-z]" },
+  Exemplar { tag: "es", script: "Latn", characters: "[a á b-e é f-i í j-n ñ o ó p-u ú ü v-z]" },
+  Exemplar { tag: "et", script: "Latn", characters: "[a-s š z ž t-w õ ä ö ü x y]" },
+  Exemplar { tag: "eu", script: "Latn", characters: "[a-n ñ o-z]" },
+  Exemplar { tag: "fi", script: "Latn", characters: "[a-s š t-z ž å ä ö]" },
+  Exemplar { tag: "fr", script: "Latn", characters: "[a à â æ b c ç d e é è ê ë f-i î ï j-o ô œ p-u ù û ü v-y ÿ z]" },
+  Exemplar { tag: "ga", script: "Latn", characters: "[a á b-e é f-i í l-o ó p r-u ú]" },
+  Exemplar { tag: "gl", script: "Latn", characters: "[a á b-e é f-i í j-n ñ o ó p-u ú ü v-z]" },
+  Exemplar { tag: "hr", script: "Latn", characters: "[a b c č ć d đ e-p r s š t-v z ž]" },
+  Exemplar { tag: "hu", script: "Latn", characters: "[a á b-e é f-i í j-o ó ö ő p-u ú ü ű v-z]" },
+  Exemplar { tag: "id", script: "Latn", characters: "[a-z]" },
+  Exemplar { tag: "is", script: "Latn", characters: "[a á b d ð e é f-i í j-o ó p r-u ú v x y ý þ æ ö]" },
+  Exemplar { tag: "it", script: "Latn", characters: "[a à b-e é è f-i ì j-o ó ò p-u ù v-z]" },
+  Exemplar { tag: "lt", script: "Latn", characters: "[a ą b c č d e ę ė f-i į y j-p r s š t u ų ū v z ž]" },
+  Exemplar { tag: "lv", script: "Latn", characters: "[a ā b c č d e ē f g ģ h i ī j k ķ l ļ m n ņ o-s š t u ū v z ž]" },
+  Exemplar { tag: "ms", script: "Latn", characters: "[a-z]" },
+  Exemplar { tag: "mt", script: "Latn", characters: "[a à b ċ d e è f ġ g {għ} h ħ i ì j-o ò p-u ù v w x ż z]" },
+  Exemplar { tag: "nb", script: "Latn", characters: "[a à b-e é f-o ó ò ô p-z æ ø å]" },
+  Exemplar { tag: "nl", script: "Latn", characters: "[a á ä b-e é ë f-i í ï j-o ó ö p-u ú ü v-z]" },
+  Exemplar { tag: "pl", script: "Latn", characters: "[a ą b c ć d e ę f-l ł m n ń o ó p r s ś t u w y z ź ż]" },
+  Exemplar { tag: "pt", script: "Latn", characters: "[a á â ã à b c ç d e é ê f-i í j-o ó ô õ p-u ú v-z]" },
+  Exemplar { tag: "ro", script: "Latn", characters: "[a ă â b-i î j-s ș t ț u-z]" },
+  Exemplar { tag: "sk", script: "Latn", characters: "[a á ä b c č d ď e é f-i í j-l ĺ ľ m n ň o ó ô p-r ŕ s š t ť u ú v-y ý z ž]" },
+  Exemplar { tag: "sl", script: "Latn", characters: "[a-c č d-s š t-v z ž]" },
+  Exemplar { tag: "sq", script: "Latn", characters: "[a-c ç d e ë f-z]" },
+  Exemplar { tag: "sv", script: "Latn", characters: "[a à b-e é f-z å ä ö]" },
+  Exemplar { tag: "sw", script: "Latn", characters: "[a-p r-w y z]" },
+  Exemplar { tag: "tr", script: "Latn", characters: "[a-c ç d-g ğ h ı i İ j-o ö p r s ş t u ü v y z]" },
+  Exemplar {
+    tag: "vi",
+    script: "Latn",
+    characters: "[a à ả ã á ạ ă ằ ẳ ẵ ắ ặ â ầ ẩ ẫ ấ ậ b-d đ e è ẻ ẽ é ẹ ê ề ể ễ ế ệ g h i ì ỉ ĩ í ị k-o ò ỏ õ ó ọ ô ồ ổ ỗ ố ộ ơ ờ ở ỡ ớ ợ p-u ù ủ ũ ú ụ ư ừ ử ữ ứ ự v x y ỳ ỷ ỹ ý ỵ]",
+  },
+  Exemplar { tag: "yo", script: "Latn", characters: "[a á à b d e é è ẹ f g {gb} h i í ì j-o ó ò ọ p r s ṣ t u ú ù w y]" },
+  Exemplar { tag: "zu", script: "Latn", characters: "[a-z]" },
+  // Cyrillic
+  Exemplar { tag: "be", script: "Cyrl", characters: "[а-е ё ж з і й-у ў ф-ш ы-я]" },
+  Exemplar { tag: "bg", script: "Cyrl", characters: "[а-ъ ь ю я]" },
+  Exemplar { tag: "kk", script: "Cyrl", characters: "[а ә б-г ғ д-к қ л-н ң о ө п-у ұ ү ф х һ ц-я і]" },
+  Exemplar { tag: "mk", script: "Cyrl", characters: "[а-г ѓ д ѕ е ж з и ј к ќ л љ м н њ о-ц ч џ ш]" },
+  Exemplar { tag: "mn", script: "Cyrl", characters: "[а-е ё ж-о ө п-у ү ф-я]" },
+  Exemplar { tag: "ru", script: "Cyrl", characters: "[а-е ё ж-я]" },
+  Exemplar { tag: "sr", script: "Cyrl", characters: "[а-д ђ е-и ј к л љ м н њ о-т ћ у-ч џ ш]" },
+  Exemplar { tag: "uk", script: "Cyrl", characters: "[ʼ а-г ґ д е є ж-и і ї й-щ ь ю я]" },
+  // Greek, Armenian, Georgian
+  Exemplar { tag: "el", script: "Grek", characters: "[α ά β-ε έ ζ η ή θ ι ί ϊ ΐ κ-ο ό π ρ σ ς τ υ ύ ϋ ΰ φ-ω ώ]" },
+  Exemplar { tag: "hy", script: "Armn", characters: "[ա-ֆ]" },
+  Exemplar { tag: "ka", script: "Geor", characters: "[ა-ჰ]" },
+  // Right-to-left
+  Exemplar { tag: "he", script: "Hebr", characters: "[א-ת]" },
+  Exemplar { tag: "ar", script: "Arab", characters: "[ً-ْ ء-غ ف-ي]" },
+  Exemplar { tag: "fa", script: "Arab", characters: "[ً ٌ ٍ ّ ٔ آ ا ء أ ؤ ئ ب پ ت-ج چ ح-ز ژ س-غ ف ق ک گ ل-ن و ه ی]" },
+  Exemplar { tag: "ur", script: "Arab", characters: "[ا آ ب پ ت ٹ ث ج چ ح خ د ڈ ذ ر ڑ ز ژ س ش ص ض ط ظ ع غ ف ق ک گ ل م ن ں و ہ ھ ء ی ے]" },
+  // Indic
+  Exemplar { tag: "bn", script: "Beng", characters: "[় ঁ-ঃ অ-ঋ এ ঐ ও-ন প-র ল শ-হ ঽ-ৃ ে ৈ ো ৌ ্ ৎ ড় ঢ় য়]" },
+  Exemplar { tag: "gu", script: "Gujr", characters: "[઼ ૐ ં ઁ ઃ અ-ઋ ૠ એ ઐ ઓ-ન પ-ર લ ળ વ-હ ઽ ા-ૅ ે ૈ ૉ ો ૌ ્]" },
+  Exemplar { tag: "hi", script: "Deva", characters: "[़ ॐ ं ँ ः अ-ऋ ए ऐ ओ-न प-र ल ळ व-ह ऽ ा-ृ े ै ो ौ ्]" },
+  Exemplar { tag: "mr", script: "Deva", characters: "[़ ॐ ं ँ ः अ-ऋ ऍ ए ऐ ऑ ओ-न प-र ल ळ व-ह ऽ ा-ृ ॅ े ै ॉ ो ौ ्]" },
+  Exemplar { tag: "ne", script: "Deva", characters: "[़ ँ-ः ॐ अ-ऌ ए ऐ ओ-न प-र ल ळ व-ह ऽ ा-ॄ े ै ो ौ ्]" },
+  Exemplar { tag: "pa", script: "Guru", characters: "[ਂ ਁ ੰ ੱ ੴ ੳ ਉ ਊ ਓ ਅ ਆ ਐ ਔ ੲ ਇ ਈ ਏ ਸ ਹ ਕ-ਨ ਪ-ਰ ਲ ਵ ੜ ਼ ਾ-ੂ ੇ ੈ ੋ ੌ ੍]" },
+  Exemplar { tag: "ta", script: "Taml", characters: "[அ-ஊ எ-ஐ ஒ-க ங ச ஜ ஞ ட ண த ந-ப ம-வ ஷ-ஹ ா-ூ ெ-ை ொ-்]" },
+  Exemplar { tag: "te", script: "Telu", characters: "[ఁ-ః అ-ఌ ఎ-ఐ ఒ-న ప-ళ వ-హ ా-ౄ ె-ై ొ-్ ౕ ౖ ౠ ౡ]" },
+  Exemplar { tag: "kn", script: "Knda", characters: "[಼ ೦-೯ ಅ-ಌ ಎ-ಐ ಒ-ನ ಪ-ಳ ವ-ಹ ಽ-ೄ ೆ-ೈ ೊ-್ ೕ ೖ ೞ ೠ ೡ ಂ ಃ]" },
+  Exemplar { tag: "ml", script: "Mlym", characters: "[അ-ഋ എ-ഐ ഒ-ന പ-ഹ ാ-ൃ െ-ൈ ൊ-് ൗ ൺ-ൿ ം ഃ]" },
+  // Southeast Asian
+  Exemplar { tag: "th", script: "Thai", characters: "[ก-ฺ เ-ๅ ็-๎]" },
+  Exemplar { tag: "lo", script: "Laoo", characters: "[ກ ຂ ຄ ງ ຈ ຊ ຍ ດ-ທ ນ-ຟ ມ-ຣ ລ ວ ສ ຫ ອ-ູ ົ-ຽ ເ-ໄ ໆ ່-ໍ ໜ ໝ]" },
+  Exemplar { tag: "km", script: "Khmr", characters: "[៌ ៎ ៏ ៑ ័ ៈ ់ ៉ ៊ ៍ ក-ឡ អ-ឲ ា-ៅ ំ ះ ្]" },
+  // CJK
+  Exemplar {
+    tag: "ja",
+    script: "Jpan",
+    characters: "[ぁ-ゖ ゝ ゞ ァ-ヺ ー ヽ ヾ 一 二 三 四 五 六 七 八 九 十 百 千 万 円 年 月 日 時 分 人 大 小 中 上 下 本 語 国 学 生 先 何 私 見 行 来 出 入 山 川 水 火 木 金 土 子 女 男 手 目 口 名 前 後 午 今 週 毎 気 天 雨 車 電 話 食 飲 書 読 聞 言 高 安 新 古 長 白 赤 青 黒 東 西 南 北 左 右 外 間 会 社 員 店 駅 道 家 友 父 母 花 犬 半 休 校 買 売 思 知 作 使 同 持 仕 事 物 方 自 分 場 所 近 遠 早 多 少 明 春 夏 秋 冬]",
+  },
+  Exemplar { tag: "ko", script: "Kore", characters: "[ㄱ-ㅎ ㅏ-ㅣ 가-각 간 갈 감 강 개 거 건 것 게 고 공 과 관 교 구 국 그 기 나 남 내 너 네 노 는 니 다 대 더 도 동 되 된 두 들 등 때 또 라 람 래 로 를 리 마 만 말 면 모 무 문 물 미 바 반 방 보 부 분 사 상 생 서 선 성 세 소 수 스 시 신 아 안 않 알 야 어 없 에 여 연 오 요 우 원 위 은 을 의 이 인 일 있 자 작 장 저 전 정 제 조 주 중 지 진 하 한 할 함 해 했 현 화 회]" },
+  Exemplar {
+    tag: "zh",
+    script: "Hans",
+    characters: "[的 一 是 不 了 人 我 在 有 他 这 中 大 来 上 国 个 到 说 们 为 子 和 你 地 出 道 也 时 年 得 就 那 要 下 以 生 会 自 着 去 之 过 家 学 对 可 她 里 后 小 么 心 多 天 而 能 好 都 然 没 日 于 起 还 发 成 事 只 作 当 想 看 文 无 开 手 十 用 主 行 方 又 如 前 所 本 见 经 头 面 公 同 三 已 老 从 动 两 长 知 民 样 现 分 将 外 但 身 些 与 高 意 进 把 法 此 实 回 二 理 美 点 月 明 其 种 声 全 工 己 话 儿 者 向 情 部 正 名 定 女 问 力 机 给 等 几 很 业 最 间 新 什 打 便 位 因 重 被 走 电 四 第 门 相 次 东 政 海 口 使 教 西 再 平 真 听 世 气 信 北 少 关 并 内 加 化 由 却 代 军 产 入 先 山 五 太 水 万 市 眼 体 别 处 总 才 场 师 书 比 住 员 九 笑 性 通 目 华 报 立 马 命 张 活 难 神 数 件 安 表 原 车 白 应 路 期 叫 死 常 提 感 金 何 更 反 合 放 做 系 计 或 司 利 受 光 王 果 亲 界 及 今 京 务 制 解 各 任 至 清 物 台 象 记 边 共 风 战 干 接 它 许 八 特 觉 望 直 服 毛 林 题 建 南 度 统 色 字 请 交 爱 让 认 算 论 百 吃 义 科 怎 元 社 术 结 六 功 指 思 非 流 每 青 管 夫 连 远 资 队 跟 带 花 快 条 院 变 联 言 权 往 展 该 领 传 近 留 红 治 决 周 保 达 办 运 武 半 候 七 必 城 父 强 步 完 革 深 区 即 求 品 士 转 量 空 甚 众 技 轻 程 告 江 语 英 基 派 满 式 李 息 写 呢 识 极 令 黄 德 收 脸 钱 党 倒 未 持 取 设 始 版 双 历 越 史 商 千 片 容 研 像 找 友 孩 站 广 改 议 形 委 早 房 音 火 际 则 首 单 据 导 影 失 拿 网 香 似 专 石 兵 弟 谁 校 读 志 飞 观 争 究 包 组 造 落 视 济 喜 离 虽 坐 集 编 宝 谈 府 拉 黑 且 随 格 尽 讲 布 杀 微 怕 母 调 局 根 曾 准 团 段 终 乐 切 级 克 精 哪 官 示 冷 域]",
+  },
+  Exemplar {
+    tag: "zh-Hant",
+    script: "Hant",
+    characters: "[的 一 是 不 了 人 我 在 有 他 這 中 大 來 上 國 個 到 說 們 為 子 和 你 地 出 道 也 時 年 得 就 那 要 下 以 生 會 自 著 去 之 過 家 學 對 可 她 裡 後 小 麼 心 多 天 而 能 好 都 然 沒 日 於 起 還 發 成 事 只 作 當 想 看 文 無 開 手 十 用 主 行 方 又 如 前 所 本 見 經 頭 面 公 同 三 已 老 從 動 兩 長 知 民 樣 現 分 將 外 但 身 些 與 高 意 進 把 法 此 實 回 二 理 美 點 月 明 其 種 聲 全 工 己 話 兒 者 向 情 部 正 名 定 女 問 力 機 給 等 幾 很 業 最 間 新 什 打 便 位 因 重 被 走 電 四 第 門 相 次 東 政 海 口 使 教 西 再 平 真 聽 世 氣 信 北 少 關 並 內 加 化 由 卻 代 軍 產 入 先 山 五 太 水 萬 市 眼 體 別 處 總 才 場 師 書 比 住 員 九 笑 性 通 目 華 報 立 馬 命 張 活 難 神 數 件 安 表 原 車 白 應 路 期 叫 死 常 提 感 金 何 更 反 合 放 做 系 計 或 司 利 受 光 王 果 親 界 及 今 京 務 制 解 各 任 至 清 物 臺 象 記 邊 共 風 戰 乾 接 它 許 八 特 覺 望 直 服 毛 林 題 建 南 度 統 色 字 請 交 愛 讓 認 算 論 百 吃 義 科 怎 元 社 術 結 六 功 指 思 非 流 每 青 管 夫 連 遠 資 隊 跟 帶 花 快 條 院 變 聯 言 權 往 展 該 領 傳 近 留 紅 治 決 周 保 達 辦 運 武 半 候 七 必 城 父 強 步 完 革 深 區 即 求 品 士 轉 量 空 甚 眾 技 輕 程 告 江 語 英 基 派 滿 式 李 息 寫 呢 識 極 令 黃 德 收 臉 錢 黨 倒 未 持 取 設 始 版 雙 歷 越 史 商 千 片 容 研 像 找 友 孩 站 廣 改 議 形 委 早 房 音 火 際 則 首 單 據 導 影 失 拿 網 香 似 專 石 兵 弟 誰 校 讀 志 飛 觀 爭 究 包 組 造 落 視 濟 喜 離 雖 坐 集 編 寶 談 府 拉 黑 且 隨 格 盡 講 布 殺 微 怕 母 調 局 根 曾 準 團 段 終 樂 切 級 克 精 哪 官 示 冷 域]",
+  },
+];
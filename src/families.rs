@@ -0,0 +1,107 @@
+use std::collections::{ BTreeMap, BTreeSet };
+
+use axum::{ extract::{ Query, State }, http::StatusCode, response::IntoResponse, Json };
+use log::error;
+use serde::Serialize;
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  database::{ get_metadata, FontFilter, FontRecord },
+};
+
+#[derive(Serialize)]
+struct StyleRef {
+  object_path: String,
+  face_index: i32,
+  font_subfamily: String,
+}
+
+#[derive(Serialize)]
+struct FontFamily {
+  font_family: String,
+  /// The upright normal-width style closest to weight 400.
+  representative: StyleRef,
+  style_count: usize,
+  file_count: usize,
+  formats: Vec<&'static str>,
+  is_variable: bool,
+  styles: Vec<FontRecord>,
+}
+
+fn style_order(record: &FontRecord) -> (i16, i16, bool) {
+  let classification = &record.metadata.classification;
+  (
+    classification.weight_class,
+    classification.width_class,
+    classification.is_italic || classification.is_oblique,
+  )
+}
+
+/// Distance from a Regular style; italics only win when the family has no
+/// upright styles at all.
+fn regular_distance(record: &FontRecord) -> (bool, i16, i16) {
+  let (weight, width, slanted) = style_order(record);
+  (slanted, (width - 5).abs(), (weight - 400).abs())
+}
+
+fn group_families(records: Vec<FontRecord>) -> Vec<FontFamily> {
+  let mut grouped: BTreeMap<String, Vec<FontRecord>> = BTreeMap::new();
+  for record in records {
+    grouped.entry(record.metadata.font_family.to_lowercase()).or_default().push(record);
+  }
+
+  grouped
+    .into_values()
+    .filter_map(|mut styles| {
+      styles.sort_by_key(style_order);
+      let representative = styles.iter().min_by_key(|record| regular_distance(record))?;
+
+      let representative = StyleRef {
+        object_path: representative.object_path.clone(),
+        face_index: representative.face_index,
+        font_subfamily: representative.metadata.font_subfamily.clone(),
+      };
+      let files: BTreeSet<&str> = styles
+        .iter()
+        .map(|record| record.object_path.as_str())
+        .collect();
+      let formats: BTreeSet<&'static str> = styles
+        .iter()
+        .filter_map(|record| record.metadata.container_format)
+        .map(|format| format.as_str())
+        .collect();
+
+      Some(FontFamily {
+        font_family: styles[0].metadata.font_family.clone(),
+        representative,
+        style_count: styles.len(),
+        file_count: files.len(),
+        formats: formats.into_iter().collect(),
+        is_variable: styles.iter().any(|record| record.metadata.variations.is_some()),
+        styles,
+      })
+    })
+    .collect()
+}
+
+/// Groups the caller's fonts by typographic family. Accepts the same filters as
+/// `/files`; families are ordered by name and styles by weight, width and
+/// slope, so a valid `sort` has no effect here.
+pub async fn families_handler(
+  user: AuthUser,
+  Query(filter): Query<FontFilter>,
+  State(state): State<AppState>
+) -> impl IntoResponse {
+  if let Err(e) = filter.order_by() {
+    return (StatusCode::BAD_REQUEST, e).into_response();
+  }
+
+  match get_metadata(&state.db_client, &user.user_id, &filter).await {
+    Ok(records) => Json(group_families(records)).into_response(),
+    Err(db_err) => {
+      error!("Failed to fetch metadata for families: {}", db_err);
+      (StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up font metadata").into_response()
+    }
+  }
+}
@@ -0,0 +1,224 @@
+use std::{ collections::{ BTreeMap, BTreeSet }, fmt::Write };
+
+use axum::{
+  extract::{ Path, Query, State },
+  http::StatusCode,
+  response::{ IntoResponse, Response },
+  Json,
+};
+use log::error;
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ Face, GlyphId, OutlineBuilder };
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  blobs::fetch_library_file,
+  container::decode_font,
+};
+
+#[derive(Deserialize)]
+pub struct GlyphParams {
+  #[serde(default)]
+  face: u32,
+  /// Glyph ID to outline; the inventory is returned without one.
+  glyph: Option<u16>,
+}
+
+#[derive(Serialize)]
+struct GlyphEntry {
+  id: u16,
+  name: Option<String>,
+  codepoints: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct GlyphInventory {
+  glyph_count: u16,
+  units_per_em: u16,
+  glyphs: Vec<GlyphEntry>,
+  /// Codepoint to glyph ID, from the font's Unicode cmap subtables.
+  cmap: BTreeMap<u32, u16>,
+}
+
+#[derive(Serialize)]
+struct GlyphBounds {
+  x_min: i16,
+  y_min: i16,
+  x_max: i16,
+  y_max: i16,
+}
+
+#[derive(Serialize)]
+struct GlyphOutline {
+  id: u16,
+  name: Option<String>,
+  codepoints: Vec<u32>,
+  units_per_em: u16,
+  advance_width: Option<u16>,
+  left_side_bearing: Option<i16>,
+  bounds: Option<GlyphBounds>,
+  /// SVG path data in font units with the y axis flipped, so the origin sits
+  /// on the baseline and ascenders have negative y.
+  path: String,
+}
+
+struct SvgPath(String);
+
+/// Negates a font-space y coordinate without producing `-0` for the baseline.
+fn flip(y: f32) -> f32 {
+  0.0 - y
+}
+
+impl OutlineBuilder for SvgPath {
+  fn move_to(&mut self, x: f32, y: f32) {
+    let _ = write!(self.0, "M{} {}", x, flip(y));
+  }
+
+  fn line_to(&mut self, x: f32, y: f32) {
+    let _ = write!(self.0, "L{} {}", x, flip(y));
+  }
+
+  fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+    let _ = write!(self.0, "Q{} {} {} {}", x1, flip(y1), x, flip(y));
+  }
+
+  fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+    let _ = write!(self.0, "C{} {} {} {} {} {}", x1, flip(y1), x2, flip(y2), x, flip(y));
+  }
+
+  fn close(&mut self) {
+    self.0.push('Z');
+  }
+}
+
+fn unicode_map(face: &Face) -> BTreeMap<u32, u16> {
+  let mut map = BTreeMap::new();
+
+  if let Some(cmap) = face.tables().cmap {
+    for subtable in cmap.subtables.into_iter().filter(|subtable| subtable.is_unicode()) {
+      subtable.codepoints(|codepoint| {
+        if let Some(glyph_id) = subtable.glyph_index(codepoint) && glyph_id.0 != 0 {
+          map.entry(codepoint).or_insert(glyph_id.0);
+        }
+      });
+    }
+  }
+
+  map
+}
+
+/// Codepoints the Unicode cmap subtables map to `glyph_id`, without building
+/// the whole map.
+fn glyph_codepoints(face: &Face, glyph_id: GlyphId) -> Vec<u32> {
+  let mut codepoints = BTreeSet::new();
+
+  if let Some(cmap) = face.tables().cmap {
+    for subtable in cmap.subtables.into_iter().filter(|subtable| subtable.is_unicode()) {
+      subtable.codepoints(|codepoint| {
+        if subtable.glyph_index(codepoint) == Some(glyph_id) {
+          codepoints.insert(codepoint);
+        }
+      });
+    }
+  }
+
+  codepoints.into_iter().collect()
+}
+
+fn glyph_inventory(face: &Face) -> GlyphInventory {
+  let cmap = unicode_map(face);
+
+  let mut glyphs: Vec<GlyphEntry> = (0..face.number_of_glyphs())
+    .map(|id| GlyphEntry {
+      id,
+      name: face.glyph_name(GlyphId(id)).map(str::to_string),
+      codepoints: Vec::new(),
+    })
+    .collect();
+  for (&codepoint, &glyph_id) in &cmap {
+    if let Some(glyph) = glyphs.get_mut(glyph_id as usize) {
+      glyph.codepoints.push(codepoint);
+    }
+  }
+
+  GlyphInventory {
+    glyph_count: face.number_of_glyphs(),
+    units_per_em: face.units_per_em(),
+    glyphs,
+    cmap,
+  }
+}
+
+fn glyph_outline(face: &Face, id: u16) -> Option<GlyphOutline> {
+  if id >= face.number_of_glyphs() {
+    return None;
+  }
+
+  let glyph_id = GlyphId(id);
+  let mut path = SvgPath(String::new());
+  let bounds = face.outline_glyph(glyph_id, &mut path).map(|rect| GlyphBounds {
+    x_min: rect.x_min,
+    y_min: rect.y_min,
+    x_max: rect.x_max,
+    y_max: rect.y_max,
+  });
+
+  Some(GlyphOutline {
+    id,
+    name: face.glyph_name(glyph_id).map(str::to_string),
+    codepoints: glyph_codepoints(face, glyph_id),
+    units_per_em: face.units_per_em(),
+    advance_width: face.glyph_hor_advance(glyph_id),
+    left_side_bearing: face.glyph_hor_side_bearing(glyph_id),
+    bounds,
+    path: path.0,
+  })
+}
+
+/// Serves `GET /glyphs/{key}` with the glyph inventory and
+/// `GET /glyphs/{key}?glyph={id}` with a single glyph's outline. Keys may
+/// contain `/`, and axum only allows a wildcard as the last segment, so this
+/// replaces the `/fonts/{key}/glyphs` and `/fonts/{key}/glyphs/{id}` shape,
+/// which cannot tell a key ending in `/glyphs` from the glyph routes.
+pub async fn glyphs_handler(
+  user: AuthUser,
+  Path(key): Path<String>,
+  Query(params): Query<GlyphParams>,
+  State(state): State<AppState>
+) -> Result<Response, (StatusCode, String)> {
+  let user_key = format!("{}/{}", user.user_id, key);
+
+  let data = fetch_library_file(&state, &user.user_id, &user_key).await
+    .map_err(|e| {
+      error!("Failed to retrieve file {}: {}", key, e);
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Unable to retrieve file '{}': Server error", key),
+      )
+    })?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, format!("File '{}' does not exist", key)))?;
+
+  let face_index = params.face;
+  tokio::task
+    ::spawn_blocking(move || {
+      let (_, sfnt) = decode_font(&data).map_err(|e| {
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("Cannot read '{}': {}", key, e))
+      })?;
+      let face = Face::parse(&sfnt, face_index).map_err(|e| {
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("Error parsing font data: {:?}", e))
+      })?;
+
+      match params.glyph {
+        None => Ok(Json(glyph_inventory(&face)).into_response()),
+        Some(id) =>
+          glyph_outline(&face, id)
+            .map(|outline| Json(outline).into_response())
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Glyph {} does not exist", id))),
+      }
+    }).await
+    .map_err(|e| {
+      error!("Glyph task failed: {}", e);
+      (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read glyphs".to_string())
+    })?
+}
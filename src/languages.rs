@@ -0,0 +1,115 @@
+use std::{ collections::BTreeSet, sync::LazyLock };
+
+use serde::{ Deserialize, Serialize };
+
+use crate::{ coverage::{ covers, CodepointRange }, exemplars::{ Exemplar, EXEMPLARS } };
+
+/// Share of a language's exemplar characters a font needs before the language
+/// counts as partially supported.
+const PARTIAL_SUPPORT_THRESHOLD: f32 = 0.5;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LanguageSupport {
+  /// BCP 47 tags whose exemplar characters are all covered.
+  pub languages: Vec<String>,
+  pub partial_languages: Vec<String>,
+  /// ISO 15924 codes with at least one fully supported language.
+  pub scripts: Vec<String>,
+  pub partial_scripts: Vec<String>,
+}
+
+struct ExemplarSet {
+  tag: &'static str,
+  script: &'static str,
+  codepoints: BTreeSet<u32>,
+}
+
+/// Scripts whose exemplars need their capital forms as well. Georgian is left
+/// out on purpose: Mtavruli capitals are not used in running text.
+const BICAMERAL_SCRIPTS: &[&str] = &["Latn", "Cyrl", "Grek", "Armn"];
+
+/// Reads the characters of a CLDR `UnicodeSet` such as `[a à b-e {ch}]`.
+/// Multi-character sequences contribute each of their characters.
+fn parse_exemplar_set(set: &str, bicameral: bool) -> BTreeSet<u32> {
+  let mut codepoints = BTreeSet::new();
+  let chars: Vec<char> = set.trim().trim_start_matches('[').trim_end_matches(']').chars().collect();
+
+  let mut index = 0;
+  while index < chars.len() {
+    let ch = chars[index];
+    if ch.is_whitespace() || ch == '{' || ch == '}' {
+      index += 1;
+      continue;
+    }
+
+    if chars.get(index + 1) == Some(&'-') && let Some(&end) = chars.get(index + 2) {
+      codepoints.extend(u32::from(ch)..=u32::from(end));
+      index += 3;
+    } else {
+      codepoints.insert(u32::from(ch));
+      index += 1;
+    }
+  }
+
+  if !bicameral {
+    return codepoints;
+  }
+
+  // Exemplars are lowercase; fonts need the matching capitals as well.
+  let uppercase: Vec<u32> = codepoints
+    .iter()
+    .filter_map(|&codepoint| {
+      let mut upper = char::from_u32(codepoint)?.to_uppercase();
+      match (upper.next(), upper.next()) {
+        (Some(upper), None) if u32::from(upper) != codepoint => Some(u32::from(upper)),
+        _ => None,
+      }
+    })
+    .collect();
+  codepoints.extend(uppercase);
+
+  codepoints
+}
+
+static EXEMPLAR_SETS: LazyLock<Vec<ExemplarSet>> = LazyLock::new(|| {
+  EXEMPLARS.iter()
+    .map(|&Exemplar { tag, script, characters }| ExemplarSet {
+      tag,
+      script,
+      codepoints: parse_exemplar_set(characters, BICAMERAL_SCRIPTS.contains(&script)),
+    })
+    .collect()
+});
+
+/// Scores the font's codepoint coverage against every bundled exemplar set.
+pub fn detect_languages(ranges: &[CodepointRange]) -> LanguageSupport {
+  let mut support = LanguageSupport::default();
+  let mut scripts = BTreeSet::new();
+  let mut partial_scripts = BTreeSet::new();
+
+  for set in EXEMPLAR_SETS.iter() {
+    let covered = set.codepoints
+      .iter()
+      .filter(|&&codepoint| covers(ranges, codepoint))
+      .count();
+
+    if covered == set.codepoints.len() {
+      support.languages.push(set.tag.to_string());
+      scripts.insert(set.script);
+    } else if (covered as f32) / (set.codepoints.len() as f32) >= PARTIAL_SUPPORT_THRESHOLD {
+      support.partial_languages.push(set.tag.to_string());
+      partial_scripts.insert(set.script);
+    }
+  }
+
+  support.partial_scripts = partial_scripts
+    .difference(&scripts)
+    .map(|script| script.to_string())
+    .collect();
+  support.scripts = scripts
+    .into_iter()
+    .map(|script| script.to_string())
+    .collect();
+
+  support
+}
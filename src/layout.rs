@@ -0,0 +1,81 @@
+use std::collections::BTreeSet;
+
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ opentype_layout::LayoutTable, Face, Tag };
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayoutScript {
+  pub tag: String,
+  /// Language system tags besides the default one.
+  pub languages: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LayoutTableInfo {
+  pub scripts: Vec<LayoutScript>,
+  /// Distinct feature tags in the feature list, sorted.
+  pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutInfo {
+  pub gsub: Option<LayoutTableInfo>,
+  pub gpos: Option<LayoutTableInfo>,
+}
+
+impl LayoutInfo {
+  fn tables(&self) -> impl Iterator<Item = &LayoutTableInfo> {
+    self.gsub.iter().chain(self.gpos.iter())
+  }
+
+  /// Feature tags found in either table.
+  pub fn feature_tags(&self) -> Vec<String> {
+    let tags: BTreeSet<&String> = self.tables()
+      .flat_map(|table| table.features.iter())
+      .collect();
+    tags.into_iter().cloned().collect()
+  }
+
+  /// OpenType script tags found in either table.
+  pub fn script_tags(&self) -> Vec<String> {
+    let tags: BTreeSet<&String> = self.tables()
+      .flat_map(|table| table.scripts.iter().map(|script| &script.tag))
+      .collect();
+    tags.into_iter().cloned().collect()
+  }
+}
+
+/// Tags are space padded to four bytes, e.g. `TRK `.
+fn tag_name(tag: Tag) -> String {
+  tag.to_string().trim_end().to_string()
+}
+
+fn layout_table_info(table: &LayoutTable) -> LayoutTableInfo {
+  let scripts = table.scripts
+    .into_iter()
+    .map(|script| LayoutScript {
+      tag: tag_name(script.tag),
+      languages: script.languages
+        .into_iter()
+        .map(|language| tag_name(language.tag))
+        .collect(),
+    })
+    .collect();
+
+  let features: BTreeSet<String> = table.features
+    .into_iter()
+    .map(|feature| tag_name(feature.tag))
+    .collect();
+
+  LayoutTableInfo { scripts, features: features.into_iter().collect() }
+}
+
+pub fn extract_layout(face: &Face) -> LayoutInfo {
+  let tables = face.tables();
+
+  LayoutInfo {
+    gsub: tables.gsub.as_ref().map(layout_table_info),
+    gpos: tables.gpos.as_ref().map(layout_table_info),
+  }
+}
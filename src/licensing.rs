@@ -0,0 +1,243 @@
+use axum::{ extract::{ Query, State }, http::StatusCode, response::IntoResponse, Json };
+use log::{ error, info };
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ Face, Permissions, Tag };
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  binary::read_u16,
+  database::{ get_metadata, FontFilter, FontRecord },
+};
+
+const OS2_FS_TYPE_OFFSET: usize = 8;
+
+/// Usage permission from the low bits of OS/2 `fsType`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingLevel {
+  Installable,
+  Restricted,
+  PreviewPrint,
+  Editable,
+}
+
+impl EmbeddingLevel {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      EmbeddingLevel::Installable => "installable",
+      EmbeddingLevel::Restricted => "restricted",
+      EmbeddingLevel::PreviewPrint => "preview_print",
+      EmbeddingLevel::Editable => "editable",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "installable" => Some(EmbeddingLevel::Installable),
+      "restricted" => Some(EmbeddingLevel::Restricted),
+      "preview_print" => Some(EmbeddingLevel::PreviewPrint),
+      "editable" => Some(EmbeddingLevel::Editable),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingPermissions {
+  /// Raw OS/2 `fsType`, `None` without an OS/2 table.
+  pub fs_type: Option<i16>,
+  /// `None` when there is no OS/2 table or the usage bits contradict each other.
+  pub embedding_level: Option<EmbeddingLevel>,
+  pub allows_subsetting: bool,
+  pub allows_outline_embedding: bool,
+}
+
+impl Default for EmbeddingPermissions {
+  fn default() -> Self {
+    EmbeddingPermissions {
+      fs_type: None,
+      embedding_level: None,
+      allows_subsetting: true,
+      allows_outline_embedding: true,
+    }
+  }
+}
+
+pub fn extract_embedding_permissions(face: &Face) -> EmbeddingPermissions {
+  let Some(os2) = face.raw_face().table(Tag::from_bytes(b"OS/2")) else {
+    return EmbeddingPermissions::default();
+  };
+
+  EmbeddingPermissions {
+    fs_type: read_u16(os2, OS2_FS_TYPE_OFFSET).map(|fs_type| fs_type as i16),
+    embedding_level: face.permissions().map(|permissions| {
+      match permissions {
+        Permissions::Installable => EmbeddingLevel::Installable,
+        Permissions::Restricted => EmbeddingLevel::Restricted,
+        Permissions::PreviewAndPrint => EmbeddingLevel::PreviewPrint,
+        Permissions::Editable => EmbeddingLevel::Editable,
+      }
+    }),
+    allows_subsetting: face.is_subsetting_allowed(),
+    allows_outline_embedding: face.is_outline_embedding_allowed(),
+  }
+}
+
+/// Recognizes common font licenses from the name table license description
+/// and URL, returning an SPDX identifier.
+pub fn detect_license(license: &str, license_url: &str) -> Option<&'static str> {
+  let text = format!("{} {}", license, license_url).to_lowercase();
+  let licenses: &[(&[&str], &str)] = &[
+    (&["open font license", "scripts.sil.org/ofl", "openfontlicense.org"], "OFL-1.1"),
+    (&["apache license", "apache.org/licenses"], "Apache-2.0"),
+    (&["ubuntu font licence", "font.ubuntu.com/ufl"], "Ubuntu-font-1.0"),
+    (&["bitstream vera"], "Bitstream-Vera"),
+    (&["mit license", "opensource.org/licenses/mit"], "MIT"),
+    (&["creative commons zero", "publicdomain/zero", "cc0"], "CC0-1.0"),
+  ];
+
+  let gpl_markers = ["gnu general public license", "gnu.org/licenses/gpl"];
+  if gpl_markers.iter().any(|marker| text.contains(marker)) {
+    return Some(gpl_version(&text));
+  }
+
+  licenses
+    .iter()
+    .find(|(markers, _)| markers.iter().any(|marker| text.contains(marker)))
+    .map(|(_, spdx)| *spdx)
+}
+
+/// Picks the GPL identifier the license text supports. Plain `GPL` is not an
+/// SPDX identifier, but claiming a version the font never states would be worse.
+fn gpl_version(text: &str) -> &'static str {
+  let names = |markers: &[&str]| markers.iter().any(|marker| text.contains(marker));
+  let later = names(&["any later version", "or later", "or-later"]);
+
+  if names(&["version 3", "gpl-3", "gplv3"]) {
+    if later { "GPL-3.0-or-later" } else { "GPL-3.0-only" }
+  } else if names(&["version 2", "gpl-2", "gplv2"]) {
+    if later { "GPL-2.0-or-later" } else { "GPL-2.0-only" }
+  } else {
+    "GPL"
+  }
+}
+
+#[derive(Deserialize)]
+pub struct LicenseReportParams {
+  /// Only list fonts with at least one flag.
+  #[serde(default)]
+  flagged_only: bool,
+}
+
+#[derive(Serialize)]
+struct LicenseEntry {
+  object_path: String,
+  face_index: i32,
+  font_family: String,
+  font_subfamily: String,
+  embedding_level: Option<EmbeddingLevel>,
+  license: Option<&'static str>,
+  license_url: String,
+  web_embedding: bool,
+  document_embedding: bool,
+  app_embedding: bool,
+  flags: Vec<&'static str>,
+}
+
+#[derive(Serialize, Default)]
+struct LicenseSummary {
+  total: usize,
+  restricted: usize,
+  preview_only: usize,
+  missing_license: usize,
+  flagged: usize,
+}
+
+#[derive(Serialize)]
+struct LicenseReport {
+  summary: LicenseSummary,
+  fonts: Vec<LicenseEntry>,
+}
+
+fn license_entry(record: FontRecord) -> LicenseEntry {
+  let metadata = record.metadata;
+  let embedding = &metadata.embedding;
+  let license = detect_license(&metadata.font_license, &metadata.license_url);
+
+  let mut flags = Vec::new();
+  match embedding.embedding_level {
+    Some(EmbeddingLevel::Restricted) => flags.push("restricted"),
+    Some(EmbeddingLevel::PreviewPrint) => flags.push("preview_only"),
+    None => flags.push("unknown_embedding"),
+    _ => {}
+  }
+  if !embedding.allows_subsetting {
+    flags.push("no_subsetting");
+  }
+  if !embedding.allows_outline_embedding {
+    flags.push("bitmap_embedding_only");
+  }
+  if metadata.font_license.trim().is_empty() && metadata.license_url.trim().is_empty() {
+    flags.push("missing_license");
+  }
+
+  // Web fonts are served as subsets and PDFs embed them read-only, so each use
+  // needs a different combination of fsType bits.
+  let outline_embedding = embedding.allows_outline_embedding;
+  let level = embedding.embedding_level;
+  let installable = matches!(level, Some(EmbeddingLevel::Installable | EmbeddingLevel::Editable));
+  let document_embedding =
+    outline_embedding && (installable || level == Some(EmbeddingLevel::PreviewPrint));
+
+  LicenseEntry {
+    object_path: record.object_path,
+    face_index: record.face_index,
+    font_family: metadata.font_family,
+    font_subfamily: metadata.font_subfamily,
+    embedding_level: level,
+    license,
+    license_url: metadata.license_url,
+    web_embedding: installable && outline_embedding && embedding.allows_subsetting,
+    document_embedding,
+    app_embedding: installable && outline_embedding,
+    flags,
+  }
+}
+
+/// Summarizes embedding permissions and licenses across the caller's library,
+/// flagging restricted, preview-only and unlicensed fonts.
+pub async fn license_report_handler(
+  user: AuthUser,
+  Query(params): Query<LicenseReportParams>,
+  State(state): State<AppState>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+  let filter = FontFilter::default();
+  let fonts = get_metadata(&state.db_client, &user.user_id, &filter).await.map_err(|e| {
+    error!("Failed to fetch metadata for license report: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up font metadata".to_string())
+  })?;
+
+  let mut summary = LicenseSummary { total: fonts.len(), ..Default::default() };
+  let mut entries = Vec::with_capacity(fonts.len());
+  for entry in fonts.into_iter().map(license_entry) {
+    summary.restricted += entry.flags.contains(&"restricted") as usize;
+    summary.preview_only += entry.flags.contains(&"preview_only") as usize;
+    summary.missing_license += entry.flags.contains(&"missing_license") as usize;
+    summary.flagged += !entry.flags.is_empty() as usize;
+
+    if !params.flagged_only || !entry.flags.is_empty() {
+      entries.push(entry);
+    }
+  }
+
+  info!(
+    "User {} - Client {} requested license report: {} of {} font(s) flagged",
+    user.email,
+    user.client_id,
+    summary.flagged,
+    summary.total
+  );
+
+  Ok(Json(LicenseReport { summary, fonts: entries }))
+}
@@ -0,0 +1,161 @@
+//! Decoding for Macintosh-platform `name` records, which ttf-parser only
+//! exposes as raw bytes. Only the Roman script encoding is decoded; records in
+//! the Mac CJK and other script encodings are left out.
+
+/// Mac encoding ID of the Roman script.
+pub const MAC_ROMAN_ENCODING: u16 = 0;
+
+/// Mac OS Roman characters for bytes 0x80-0xFF; the lower half is ASCII.
+static MAC_ROMAN_HIGH: [char; 128] = [
+  '\u{00c4}', '\u{00c5}', '\u{00c7}', '\u{00c9}', '\u{00d1}', '\u{00d6}', '\u{00dc}', '\u{00e1}',
+  '\u{00e0}', '\u{00e2}', '\u{00e4}', '\u{00e3}', '\u{00e5}', '\u{00e7}', '\u{00e9}', '\u{00e8}',
+  '\u{00ea}', '\u{00eb}', '\u{00ed}', '\u{00ec}', '\u{00ee}', '\u{00ef}', '\u{00f1}', '\u{00f3}',
+  '\u{00f2}', '\u{00f4}', '\u{00f6}', '\u{00f5}', '\u{00fa}', '\u{00f9}', '\u{00fb}', '\u{00fc}',
+  '\u{2020}', '\u{00b0}', '\u{00a2}', '\u{00a3}', '\u{00a7}', '\u{2022}', '\u{00b6}', '\u{00df}',
+  '\u{00ae}', '\u{00a9}', '\u{2122}', '\u{00b4}', '\u{00a8}', '\u{2260}', '\u{00c6}', '\u{00d8}',
+  '\u{221e}', '\u{00b1}', '\u{2264}', '\u{2265}', '\u{00a5}', '\u{00b5}', '\u{2202}', '\u{2211}',
+  '\u{220f}', '\u{03c0}', '\u{222b}', '\u{00aa}', '\u{00ba}', '\u{03a9}', '\u{00e6}', '\u{00f8}',
+  '\u{00bf}', '\u{00a1}', '\u{00ac}', '\u{221a}', '\u{0192}', '\u{2248}', '\u{2206}', '\u{00ab}',
+  '\u{00bb}', '\u{2026}', '\u{00a0}', '\u{00c0}', '\u{00c3}', '\u{00d5}', '\u{0152}', '\u{0153}',
+  '\u{2013}', '\u{2014}', '\u{201c}', '\u{201d}', '\u{2018}', '\u{2019}', '\u{00f7}', '\u{25ca}',
+  '\u{00ff}', '\u{0178}', '\u{2044}', '\u{20ac}', '\u{2039}', '\u{203a}', '\u{fb01}', '\u{fb02}',
+  '\u{2021}', '\u{00b7}', '\u{201a}', '\u{201e}', '\u{2030}', '\u{00c2}', '\u{00ca}', '\u{00c1}',
+  '\u{00cb}', '\u{00c8}', '\u{00cd}', '\u{00ce}', '\u{00cf}', '\u{00cc}', '\u{00d3}', '\u{00d4}',
+  '\u{f8ff}', '\u{00d2}', '\u{00da}', '\u{00db}', '\u{00d9}', '\u{0131}', '\u{02c6}', '\u{02dc}',
+  '\u{00af}', '\u{02d8}', '\u{02d9}', '\u{02da}', '\u{00b8}', '\u{02dd}', '\u{02db}', '\u{02c7}',
+];
+
+/// Macintosh language IDs from the OpenType `name` table specification.
+static MAC_LANGUAGES: &[(u16, &str)] = &[
+  (0, "English"),
+  (1, "French"),
+  (2, "German"),
+  (3, "Italian"),
+  (4, "Dutch"),
+  (5, "Swedish"),
+  (6, "Spanish"),
+  (7, "Danish"),
+  (8, "Portuguese"),
+  (9, "Norwegian"),
+  (10, "Hebrew"),
+  (11, "Japanese"),
+  (12, "Arabic"),
+  (13, "Finnish"),
+  (14, "Greek"),
+  (15, "Icelandic"),
+  (16, "Maltese"),
+  (17, "Turkish"),
+  (18, "Croatian"),
+  (19, "Chinese (Traditional)"),
+  (20, "Urdu"),
+  (21, "Hindi"),
+  (22, "Thai"),
+  (23, "Korean"),
+  (24, "Lithuanian"),
+  (25, "Polish"),
+  (26, "Hungarian"),
+  (27, "Estonian"),
+  (28, "Latvian"),
+  (29, "Sami"),
+  (30, "Faroese"),
+  (31, "Persian"),
+  (32, "Russian"),
+  (33, "Chinese (Simplified)"),
+  (34, "Flemish"),
+  (35, "Irish Gaelic"),
+  (36, "Albanian"),
+  (37, "Romanian"),
+  (38, "Czech"),
+  (39, "Slovak"),
+  (40, "Slovenian"),
+  (41, "Yiddish"),
+  (42, "Serbian"),
+  (43, "Macedonian"),
+  (44, "Bulgarian"),
+  (45, "Ukrainian"),
+  (46, "Belarusian"),
+  (47, "Uzbek"),
+  (48, "Kazakh"),
+  (49, "Azerbaijani (Cyrillic)"),
+  (50, "Azerbaijani (Arabic)"),
+  (51, "Armenian"),
+  (52, "Georgian"),
+  (53, "Moldavian"),
+  (54, "Kirghiz"),
+  (55, "Tajiki"),
+  (56, "Turkmen"),
+  (57, "Mongolian (Mongolian)"),
+  (58, "Mongolian (Cyrillic)"),
+  (59, "Pashto"),
+  (60, "Kurdish"),
+  (61, "Kashmiri"),
+  (62, "Sindhi"),
+  (63, "Tibetan"),
+  (64, "Nepali"),
+  (65, "Sanskrit"),
+  (66, "Marathi"),
+  (67, "Bengali"),
+  (68, "Assamese"),
+  (69, "Gujarati"),
+  (70, "Punjabi"),
+  (71, "Oriya"),
+  (72, "Malayalam"),
+  (73, "Kannada"),
+  (74, "Tamil"),
+  (75, "Telugu"),
+  (76, "Sinhalese"),
+  (77, "Burmese"),
+  (78, "Khmer"),
+  (79, "Lao"),
+  (80, "Vietnamese"),
+  (81, "Indonesian"),
+  (82, "Tagalog"),
+  (83, "Malay (Latin)"),
+  (84, "Malay (Arabic)"),
+  (85, "Amharic"),
+  (86, "Tigrinya"),
+  (87, "Galla"),
+  (88, "Somali"),
+  (89, "Swahili"),
+  (90, "Kinyarwanda"),
+  (91, "Rundi"),
+  (92, "Nyanja"),
+  (93, "Malagasy"),
+  (94, "Esperanto"),
+  (128, "Welsh"),
+  (129, "Basque"),
+  (130, "Catalan"),
+  (131, "Latin"),
+  (132, "Quechua"),
+  (133, "Guarani"),
+  (134, "Aymara"),
+  (135, "Tatar"),
+  (136, "Uighur"),
+  (137, "Dzongkha"),
+  (138, "Javanese"),
+  (139, "Sundanese"),
+  (140, "Galician"),
+  (141, "Afrikaans"),
+  (142, "Breton"),
+  (143, "Inuktitut"),
+  (144, "Scottish Gaelic"),
+  (145, "Manx Gaelic"),
+  (146, "Irish Gaelic (dot above)"),
+  (147, "Tongan"),
+  (148, "Greek (polytonic)"),
+  (149, "Greenlandic"),
+  (150, "Azerbaijani (Latin)"),
+];
+
+pub fn decode_mac_roman(data: &[u8]) -> String {
+  data
+    .iter()
+    .map(|&byte| if byte < 0x80 { byte as char } else { MAC_ROMAN_HIGH[(byte - 0x80) as usize] })
+    .collect()
+}
+
+pub fn mac_language(language_id: u16) -> &'static str {
+  MAC_LANGUAGES.iter()
+    .find(|(id, _)| *id == language_id)
+    .map_or("Unknown", |(_, name)| *name)
+}
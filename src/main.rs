@@ -1,23 +1,71 @@
-use crate::storage::{ upload_font, get_font, list_fonts, delete_font };
+use crate::storage::{ upload_font, get_font, get_font_metadata, list_fonts, delete_font };
 use crate::auth::login_handler;
 use crate::sync_engine::ws_handler;
+use crate::subset::subset_handler;
+use crate::css::css2_handler;
+use crate::coverage::coverage_handler;
+use crate::licensing::license_report_handler;
+use crate::preview::preview_handler;
+use crate::glyphs::glyphs_handler;
+use crate::specimen::specimen_handler;
+use crate::families::families_handler;
+use crate::qa::qa_handler;
+use crate::resumable::{
+  cancel_upload_handler,
+  create_upload_handler,
+  finalize_upload_handler,
+  upload_chunk_handler,
+  upload_status_handler,
+};
+use crate::presigned::{ commit_upload_handler, presign_download_handler, presign_upload_handler };
+use crate::signed_urls::signed_font_handler;
 use app_state::create_app_state;
 use auth::{ logout_handler, me_handler };
 use axum::{
   extract::DefaultBodyLimit,
   http::{ HeaderValue, Method, StatusCode },
   response::IntoResponse,
-  routing::{ delete, get, post, put },
+  routing::{ delete, get, patch, post, put },
   Router,
 };
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{ Any, CorsLayer };
 
 mod database;
 mod storage;
 mod auth;
 mod metadata;
+mod mac_names;
 mod app_state;
 mod sync_engine;
+mod binary;
+mod variations;
+mod sfnt;
+mod container;
+mod unicode;
+mod cff;
+mod subset;
+mod css;
+mod coverage;
+mod exemplars;
+mod languages;
+mod classification;
+mod licensing;
+mod layout;
+mod color;
+mod preview;
+mod glyphs;
+mod families;
+mod specimen;
+mod validation;
+mod qa;
+mod conflicts;
+mod spool;
+mod resumable;
+mod presigned;
+mod conditional;
+mod blobs;
+mod backfill;
+mod signed_urls;
 
 const FILE_SIZE_LIMIT: usize = 100 * 1024 * 1024;
 
@@ -30,8 +78,35 @@ async fn main() {
 
   let cors = CorsLayer::new()
     .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-    .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
-    .allow_headers(["content-type".parse().unwrap(), "authorization".parse().unwrap()]);
+    .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+    .allow_headers([
+      "content-type".parse().unwrap(),
+      "authorization".parse().unwrap(),
+      "upload-offset".parse().unwrap(),
+      "range".parse().unwrap(),
+      "if-range".parse().unwrap(),
+      "if-none-match".parse().unwrap(),
+      "if-modified-since".parse().unwrap(),
+    ])
+    .expose_headers([
+      "etag".parse().unwrap(),
+      "last-modified".parse().unwrap(),
+      "content-range".parse().unwrap(),
+      "location".parse().unwrap(),
+      "upload-offset".parse().unwrap(),
+      "upload-length".parse().unwrap(),
+      "upload-expires".parse().unwrap(),
+    ]);
+
+  // Signed font links are loaded by `@font-face` from any site using the CSS
+  // kit. They carry no credentials, so any origin may read them.
+  let font_cors = CorsLayer::new()
+    .allow_origin(Any)
+    .allow_methods([Method::GET, Method::HEAD])
+    .max_age(std::time::Duration::from_secs(24 * 60 * 60));
+  let signed_fonts = Router::new()
+    .route("/kit/{*key}", get(signed_font_handler))
+    .layer(font_cors);
 
   let app = Router::new()
     .route("/", get(hello_world))
@@ -42,14 +117,33 @@ async fn main() {
     .route("/auth/me", get(me_handler))
 
     .route("/upload", put(upload_font))
+    .route("/uploads", post(create_upload_handler))
+    .route("/uploads/{id}", get(upload_status_handler))
+    .route("/uploads/{id}", patch(upload_chunk_handler))
+    .route("/uploads/{id}", delete(cancel_upload_handler))
+    .route("/uploads/{id}/finalize", post(finalize_upload_handler))
+    .route("/presign/upload", post(presign_upload_handler))
+    .route("/presign/download/{*key}", get(presign_download_handler))
+    .route("/presign/commit", post(commit_upload_handler))
     .route("/files/{*key}", get(get_font))
     .route("/files/{*key}", delete(delete_font))
     .route("/files", get(list_fonts))
+    .route("/families", get(families_handler))
+    .route("/metadata/{*key}", get(get_font_metadata))
+    .route("/subset/{*key}", get(subset_handler))
+    .route("/css2", get(css2_handler))
+    .route("/coverage", get(coverage_handler))
+    .route("/reports/licenses", get(license_report_handler))
+    .route("/qa/{*key}", get(qa_handler))
+    .route("/preview/{*key}", get(preview_handler))
+    .route("/glyphs/{*key}", get(glyphs_handler))
+    .route("/specimens/{family}", get(specimen_handler))
 
     .route("/ws/sync", get(ws_handler))
+    .layer(cors)
 
+    .merge(signed_fonts)
     .with_state(state)
-    .layer(cors)
     .layer(DefaultBodyLimit::max(FILE_SIZE_LIMIT));
 
   let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -58,7 +152,7 @@ async fn main() {
 }
 
 async fn hello_world() -> impl IntoResponse {
-  (StatusCode::OK, format!("Hello world, from a font manager API!"))
+  (StatusCode::OK, "Hello world, from a font manager API!".to_string())
 }
 
 async fn health_check() -> &'static str {
@@ -1,6 +1,80 @@
-use ttf_parser::{ Face, PlatformId, name_id };
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ fonts_in_collection, Face, PlatformId, Tag, name_id };
 
-fn get_name_string(face: &Face, target_name_id: u16) -> String {
+use crate::{
+  binary::read_fixed,
+  classification::{ extract_classification, Classification },
+  color::{ extract_color_info, ColorInfo },
+  container::{ decode_font, FontFormat, OutlineFormat },
+  coverage::{ extract_unicode_ranges, CodepointRange },
+  languages::{ detect_languages, LanguageSupport },
+  layout::{ extract_layout, LayoutInfo },
+  licensing::{ extract_embedding_permissions, EmbeddingPermissions },
+  mac_names::{ decode_mac_roman, mac_language, MAC_ROMAN_ENCODING },
+  qa::{ run_face_checks, QaReport },
+  variations::{ extract_variations, VariationInfo },
+};
+
+/// Stored with every row. Bump it whenever extraction fills in something new,
+/// so the backfill re-extracts fonts recorded before.
+pub const METADATA_VERSION: i32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalizedName {
+  pub name_id: u16,
+  /// 1 for Macintosh records, 3 for Windows; `language_id` is in that
+  /// platform's numbering.
+  #[serde(default = "windows_platform_id")]
+  pub platform_id: u16,
+  pub language_id: u16,
+  pub language: String,
+  pub value: String,
+}
+
+fn windows_platform_id() -> u16 {
+  PlatformId::Windows as u16
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FontMetadata {
+  pub font_family: String,
+  pub font_subfamily: String,
+  pub checksum: String,
+  pub container_format: Option<FontFormat>,
+  pub outline_format: Option<OutlineFormat>,
+  pub font_foundry: String,
+  pub font_designer: String,
+  pub font_license: String,
+  pub font_copyright: String,
+  pub postscript_name: String,
+  pub full_name: String,
+  pub version_string: String,
+  /// `head` fontRevision, rounded to three decimals.
+  pub font_revision: f32,
+  pub trademark: String,
+  pub vendor_url: String,
+  pub designer_url: String,
+  pub license_url: String,
+  pub sample_text: String,
+  pub description: String,
+  pub localized_names: Vec<LocalizedName>,
+  pub variations: Option<VariationInfo>,
+  pub unicode_ranges: Vec<CodepointRange>,
+  #[serde(flatten)]
+  pub language_support: LanguageSupport,
+  #[serde(flatten)]
+  pub classification: Classification,
+  #[serde(flatten)]
+  pub embedding: EmbeddingPermissions,
+  pub layout: LayoutInfo,
+  #[serde(flatten)]
+  pub color: ColorInfo,
+  /// Served by `/qa` together with the family checks; left out of listings.
+  #[serde(skip_serializing, default)]
+  pub qa_report: QaReport,
+}
+
+pub fn get_name_string(face: &Face, target_name_id: u16) -> String {
   if let Some(name_table) = face.tables().name {
     let try_get = |platform_id, encoding_id| {
       name_table.names
@@ -55,20 +129,158 @@ pub fn get_designer(face: &Face) -> String {
   get_name_string(face, name_id::DESIGNER)
 }
 
-pub fn calculate_checksum(data: &[u8]) -> String {
-  let mut hasher = blake3::Hasher::new();
-  hasher.update(data);
-  format!("{}", hasher.finalize())
+pub fn get_postscript_name(face: &Face) -> String {
+  get_name_string(face, name_id::POST_SCRIPT_NAME)
+}
+
+pub fn get_full_name(face: &Face) -> String {
+  get_name_string(face, name_id::FULL_NAME)
+}
+
+pub fn get_version_string(face: &Face) -> String {
+  get_name_string(face, name_id::VERSION)
+}
+
+pub fn get_font_revision(face: &Face) -> f32 {
+  face
+    .raw_face()
+    .table(Tag::from_bytes(b"head"))
+    .and_then(|head| read_fixed(head, 4))
+    .map_or(0.0, |revision| (revision * 1000.0).round() / 1000.0)
+}
+
+pub fn get_trademark(face: &Face) -> String {
+  get_name_string(face, name_id::TRADEMARK)
+}
+
+pub fn get_vendor_url(face: &Face) -> String {
+  get_name_string(face, name_id::VENDOR_URL)
+}
+
+pub fn get_designer_url(face: &Face) -> String {
+  get_name_string(face, name_id::DESIGNER_URL)
+}
+
+pub fn get_license_url(face: &Face) -> String {
+  get_name_string(face, name_id::LICENSE_URL)
 }
 
+pub fn get_sample_text(face: &Face) -> String {
+  get_name_string(face, name_id::SAMPLE_TEXT)
+}
+
+pub fn get_description(face: &Face) -> String {
+  get_name_string(face, name_id::DESCRIPTION)
+}
+
+/// Collects every Windows and Macintosh name record so translated family,
+/// style and license strings survive alongside the primary values. Mac records
+/// are only decoded in the Roman encoding, and Unicode-platform records, which
+/// carry no language, are left out.
+pub fn get_localized_names(face: &Face) -> Vec<LocalizedName> {
+  let mut names: Vec<LocalizedName> = Vec::new();
+
+  for name in face.names() {
+    let (value, language) = match name.platform_id {
+      PlatformId::Windows => (name.to_string(), name.language().to_string()),
+      PlatformId::Macintosh if name.encoding_id == MAC_ROMAN_ENCODING => {
+        (Some(decode_mac_roman(name.name)), mac_language(name.language_id).to_string())
+      }
+      _ => {
+        continue;
+      }
+    };
+    let Some(value) = value else {
+      continue;
+    };
+    let platform_id = name.platform_id as u16;
+
+    if
+      value.is_empty() ||
+      names
+        .iter()
+        .any(|n| {
+          n.name_id == name.name_id &&
+            n.platform_id == platform_id &&
+            n.language_id == name.language_id
+        })
+    {
+      continue;
+    }
+
+    names.push(LocalizedName {
+      name_id: name.name_id,
+      platform_id,
+      language_id: name.language_id,
+      language,
+      value,
+    });
+  }
+
+  names
+}
+
+fn extract_face_metadata(face: &Face, checksum: &str, format: FontFormat) -> FontMetadata {
+  let unicode_ranges = extract_unicode_ranges(face);
+
+  FontMetadata {
+    font_family: get_font_family(face),
+    font_subfamily: get_font_subfamily(face),
+    checksum: checksum.to_string(),
+    container_format: Some(format),
+    outline_format: OutlineFormat::of_face(face),
+    font_foundry: get_foundry(face),
+    font_designer: get_designer(face),
+    font_license: get_license(face),
+    font_copyright: get_copyright_notice(face),
+    postscript_name: get_postscript_name(face),
+    full_name: get_full_name(face),
+    version_string: get_version_string(face),
+    font_revision: get_font_revision(face),
+    trademark: get_trademark(face),
+    vendor_url: get_vendor_url(face),
+    designer_url: get_designer_url(face),
+    license_url: get_license_url(face),
+    sample_text: get_sample_text(face),
+    description: get_description(face),
+    localized_names: get_localized_names(face),
+    variations: extract_variations(face),
+    language_support: detect_languages(&unicode_ranges),
+    unicode_ranges,
+    classification: extract_classification(face),
+    embedding: extract_embedding_permissions(face),
+    layout: extract_layout(face),
+    color: extract_color_info(face),
+    qa_report: run_face_checks(face),
+  }
+}
+
+/// Returns one entry per face, in face index order. Plain sfnt files yield a
+/// single face while `.ttc`/`.otc` collections yield one per member font.
+/// WOFF and WOFF2 data is decompressed first; `checksum` is the blake3 of the
+/// bytes as uploaded, which callers compute while receiving them.
 pub fn extract_metadata(
-  data: &[u8]
-) -> Result<Option<(String, String, String)>, Box<dyn std::error::Error>> {
-  let checksum = calculate_checksum(data);
+  data: &[u8],
+  checksum: &str
+) -> Result<Vec<FontMetadata>, Box<dyn std::error::Error>> {
+  let (format, sfnt) = decode_font(data)?;
+  extract_decoded_metadata(format, &sfnt, checksum)
+}
 
-  let face = Face::parse(data, 0).map_err(|e| format!("Error parsing font data: {:?}", e))?;
-  let family = get_font_family(&face);
-  let subfamily = get_font_subfamily(&face);
+/// [`extract_metadata`] for sfnt data that has already been decoded.
+pub fn extract_decoded_metadata(
+  format: FontFormat,
+  sfnt: &[u8],
+  checksum: &str
+) -> Result<Vec<FontMetadata>, Box<dyn std::error::Error>> {
+  let face_count = fonts_in_collection(sfnt).unwrap_or(1);
 
-  Ok(Some((family, subfamily, checksum)))
+  (0..face_count)
+    .map(|index| {
+      let face = Face::parse(sfnt, index).map_err(|e|
+        format!("Error parsing font data (face {}): {:?}", index, e)
+      )?;
+      Ok(extract_face_metadata(&face, checksum, format))
+    })
+    .collect()
 }
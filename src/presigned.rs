@@ -0,0 +1,301 @@
+//! Presigned URLs let clients move font bytes to and from S3 directly instead
+//! of through this server. Uploads land under a staging key and are recorded
+//! by a separate commit call, which checks them exactly as `/upload` would and
+//! copies them into the blob store. Staged objects nobody commits are deleted
+//! after a day.
+
+use std::{ collections::BTreeMap, time::{ Duration, SystemTime, UNIX_EPOCH } };
+
+use aws_sdk_s3::presigning::{ PresignedRequest, PresigningConfig };
+use axum::{
+  extract::{ Path, Query, State },
+  http::StatusCode,
+  response::{ IntoResponse, Response },
+  Json,
+};
+use log::{ error, info, warn };
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  blobs::{ blob_written, copy_to_blob, head_stored_file, reserve_blob },
+  conflicts::{ parse_conflict_policy, ConflictPolicy },
+  database::get_path_checksum,
+  storage::{
+    check_upload,
+    finish_upload,
+    font_records_for,
+    spool_object,
+    CheckedUpload,
+    FinishedUpload,
+    StoredUpload,
+    UploadError,
+    S3_BUCKET,
+  },
+  FILE_SIZE_LIMIT,
+};
+
+static STAGING_PREFIX: &str = "presigned";
+const DEFAULT_EXPIRY_SECS: u64 = 15 * 60;
+const MIN_EXPIRY_SECS: u64 = 60;
+const MAX_EXPIRY_SECS: u64 = 60 * 60;
+
+const STAGING_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize)]
+pub struct PresignUpload {
+  /// Path relative to the user's library, as with the `path` field of `/upload`.
+  path: String,
+  content_type: Option<String>,
+  expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct PresignParams {
+  expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CommitUpload {
+  path: String,
+  on_conflict: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PresignedUrl {
+  method: String,
+  url: String,
+  path: String,
+  expires_in: u64,
+  /// Headers the client must send unchanged for the signature to hold.
+  headers: BTreeMap<String, String>,
+}
+
+/// Strips leading slashes so every key stays inside the caller's prefix.
+fn library_path(path: &str) -> Result<String, (StatusCode, String)> {
+  let path = path.trim_start_matches('/');
+  if path.is_empty() || path.ends_with('/') {
+    return Err((StatusCode::BAD_REQUEST, "Missing file path".to_string()));
+  }
+
+  Ok(path.to_string())
+}
+
+fn staging_key(user: &AuthUser, path: &str) -> String {
+  format!("{}/{}/{}", STAGING_PREFIX, user.user_id, path)
+}
+
+fn presigning_config(
+  expires_in: Option<u64>
+) -> Result<(u64, PresigningConfig), (StatusCode, String)> {
+  let expires_in = expires_in
+    .unwrap_or(DEFAULT_EXPIRY_SECS)
+    .clamp(MIN_EXPIRY_SECS, MAX_EXPIRY_SECS);
+  let config = PresigningConfig::expires_in(Duration::from_secs(expires_in)).map_err(|e| {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Invalid presigning config: {}", e))
+  })?;
+
+  Ok((expires_in, config))
+}
+
+fn presigned_url(path: String, expires_in: u64, request: PresignedRequest) -> Json<PresignedUrl> {
+  Json(PresignedUrl {
+    method: request.method().to_string(),
+    url: request.uri().to_string(),
+    path,
+    expires_in,
+    headers: request
+      .headers()
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+      .collect(),
+  })
+}
+
+fn presign_failure(key: &str, e: impl std::fmt::Display) -> (StatusCode, String) {
+  error!("Failed to presign request for {}: {}", key, e);
+  (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create presigned URL".to_string())
+}
+
+/// `POST /presign/upload` issues a PUT URL for a path in the caller's library.
+/// The upload only shows up in listings once it is committed.
+pub async fn presign_upload_handler(
+  user: AuthUser,
+  State(state): State<AppState>,
+  Json(request): Json<PresignUpload>
+) -> Result<Json<PresignedUrl>, (StatusCode, String)> {
+  let path = library_path(&request.path)?;
+  let (expires_in, config) = presigning_config(request.expires_in)?;
+  let staged_key = staging_key(&user, &path);
+
+  let presigned = state.s3_client
+    .put_object()
+    .bucket(S3_BUCKET)
+    .key(&staged_key)
+    .content_type(request.content_type.as_deref().unwrap_or("application/octet-stream"))
+    .presigned(config).await
+    .map_err(|e| presign_failure(&staged_key, e))?;
+
+  info!("User {} - Client {} presigned upload of {}", user.email, user.client_id, path);
+  Ok(presigned_url(path, expires_in, presigned))
+}
+
+/// `GET /presign/download/{*key}` issues a GET URL for a stored font.
+pub async fn presign_download_handler(
+  user: AuthUser,
+  Path(key): Path<String>,
+  Query(params): Query<PresignParams>,
+  State(state): State<AppState>
+) -> Result<Json<PresignedUrl>, (StatusCode, String)> {
+  let path = library_path(&key)?;
+  let (expires_in, config) = presigning_config(params.expires_in)?;
+  let user_key = format!("{}/{}", user.user_id, path);
+
+  let checksum = get_path_checksum(&state.db_client, &user.user_id, &user_key).await
+    .map_err(|e| {
+      error!("Failed to look up file {}: {}", user_key, e);
+      (StatusCode::INTERNAL_SERVER_ERROR, format!("Unable to access file '{}'", path))
+    })?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, format!("File '{}' does not exist", path)))?;
+  let (stored_key, _) = head_stored_file(&state, &user_key, &checksum).await
+    .map_err(|e| {
+      error!("Failed to look up stored object for {}: {}", user_key, e);
+      (StatusCode::INTERNAL_SERVER_ERROR, format!("Unable to access file '{}'", path))
+    })?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, format!("File '{}' does not exist", path)))?;
+
+  let presigned = state.s3_client
+    .get_object()
+    .bucket(S3_BUCKET)
+    .key(&stored_key)
+    .presigned(config).await
+    .map_err(|e| presign_failure(&stored_key, e))?;
+
+  info!("User {} - Client {} presigned download of {}", user.email, user.client_id, path);
+  Ok(presigned_url(path, expires_in, presigned))
+}
+
+async fn discard_object(state: &AppState, staged_key: &str) {
+  if let Err(e) = state.s3_client.delete_object().bucket(S3_BUCKET).key(staged_key).send().await {
+    warn!("Failed to delete staged upload {}: {}", staged_key, e);
+  }
+}
+
+/// Deletes the staged objects under `presigned/` older than `cutoff` seconds
+/// since the epoch. Failures are only logged and retried on the next sweep.
+async fn remove_stale_staged_objects(state: &AppState, cutoff: i64) {
+  let prefix = format!("{}/", STAGING_PREFIX);
+  let mut pages = state.s3_client
+    .list_objects_v2()
+    .bucket(S3_BUCKET)
+    .prefix(&prefix)
+    .into_paginator()
+    .send();
+
+  while let Some(page) = pages.next().await {
+    let page = match page {
+      Ok(page) => page,
+      Err(e) => {
+        warn!("Failed to list staged uploads under {}: {}", prefix, e);
+        return;
+      }
+    };
+    let stale = page.contents()
+      .iter()
+      .filter(|object| object.last_modified().is_some_and(|modified| modified.secs() < cutoff))
+      .filter_map(|object| object.key());
+    for key in stale {
+      info!("Discarding uncommitted staged upload {}", key);
+      discard_object(state, key).await;
+    }
+  }
+}
+
+/// Background job that deletes staged uploads left for longer than
+/// [`STAGING_TTL`]: never committed, or kept after a name conflict and never
+/// retried.
+pub async fn expire_staged_uploads(state: AppState) {
+  let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+  loop {
+    interval.tick().await;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let cutoff = now.saturating_sub(STAGING_TTL).as_secs() as i64;
+    remove_stale_staged_objects(&state, cutoff).await;
+  }
+}
+
+/// `POST /presign/commit` records a file uploaded through a presigned URL:
+/// it is validated, checked for duplicates and name conflicts, stored as a
+/// blob, and its metadata inserted and announced to sync clients. The staged
+/// object is removed unless a name conflict leaves the commit to be retried,
+/// in which case it is kept until [`expire_staged_uploads`] sweeps it.
+pub async fn commit_upload_handler(
+  user: AuthUser,
+  State(state): State<AppState>,
+  Json(request): Json<CommitUpload>
+) -> Result<Response, UploadError> {
+  let path = library_path(&request.path)?;
+  let policy = match request.on_conflict.as_deref() {
+    Some(value) => parse_conflict_policy(value)?,
+    None => ConflictPolicy::default(),
+  };
+  let user_key = format!("{}/{}", user.user_id, path);
+  let staged_key = staging_key(&user, &path);
+  let file_name = path.rsplit('/').next().unwrap_or(&path).to_string();
+
+  let head = match state.s3_client.head_object().bucket(S3_BUCKET).key(&staged_key).send().await {
+    Ok(head) => head,
+    Err(e) if e.as_service_error().map(|e| e.is_not_found()) == Some(true) => {
+      let message = format!("File '{}' has not been uploaded", path);
+      return Err((StatusCode::NOT_FOUND, message).into());
+    }
+    Err(e) => {
+      error!("Failed to check if file {} exists: {}", staged_key, e);
+      let message = format!("Unable to access file '{}'", path);
+      return Err((StatusCode::INTERNAL_SERVER_ERROR, message).into());
+    }
+  };
+  if head.content_length().unwrap_or_default() > (FILE_SIZE_LIMIT as i64) {
+    discard_object(&state, &staged_key).await;
+    let message = format!("File '{}' exceeds the {} byte limit", path, FILE_SIZE_LIMIT);
+    return Err((StatusCode::PAYLOAD_TOO_LARGE, message).into());
+  }
+
+  let spooled = spool_object(&state, &staged_key).await?;
+  let checked = check_upload(&state, &user, &user_key, &file_name, &spooled, policy).await;
+  let (faces, conflicts) = match checked {
+    Ok(CheckedUpload::Accepted { faces, conflicts }) => (faces, conflicts),
+    Ok(CheckedUpload::Duplicate(existing_path)) => {
+      discard_object(&state, &staged_key).await;
+      return Ok((StatusCode::OK, format!("Duplicate file: {}", existing_path)).into_response());
+    }
+    Err(e) => {
+      // Name conflicts leave the object in place so the commit can be retried
+      // with a different on_conflict.
+      if e.status().is_client_error() && e.status() != StatusCode::CONFLICT {
+        discard_object(&state, &staged_key).await;
+      }
+      return Err(e);
+    }
+  };
+
+  let content_type = head.content_type().unwrap_or("application/octet-stream").to_string();
+  if let Some(blob_key) = reserve_blob(&state, &spooled.checksum).await? {
+    copy_to_blob(&state, &staged_key, &blob_key, &content_type, &file_name).await?;
+    blob_written(&state, &spooled.checksum).await?;
+  }
+  discard_object(&state, &staged_key).await;
+
+  info!("User {} - Client {} committed presigned upload: {}", user.email, user.client_id, path);
+  let font_records = font_records_for(&user_key, faces);
+  let upload = FinishedUpload {
+    file_name: &file_name,
+    sync_path: &path,
+    font_records,
+    stored: vec![StoredUpload { spooled, content_type }],
+  };
+  Ok(finish_upload(&state, &user, upload, policy, &conflicts).await?)
+}
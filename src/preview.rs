@@ -0,0 +1,380 @@
+use std::fmt::Write;
+
+use axum::{
+  extract::{ Path, Query, State },
+  http::{ header, StatusCode },
+  response::IntoResponse,
+};
+use log::{ error, info };
+use serde::Deserialize;
+use tiny_skia::{ Color, FillRule, Paint, PathBuilder, Pixmap, Transform };
+use ttf_parser::{ Face, GlyphId, OutlineBuilder };
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  blobs::fetch_stored_file,
+  container::decode_font,
+  database::get_metadata_by_path,
+  storage::{ cache_derived_object, fetch_object, DERIVED_PREFIX },
+};
+
+const DEFAULT_TEXT: &str = "The quick brown fox jumps over the lazy dog";
+const DEFAULT_SIZE: f32 = 48.0;
+const MIN_SIZE: f32 = 4.0;
+const MAX_SIZE: f32 = 512.0;
+const MAX_TEXT_CHARS: usize = 256;
+const MAX_DIMENSION: u32 = 4096;
+
+#[derive(Deserialize)]
+pub struct PreviewParams {
+  text: Option<String>,
+  size: Option<f32>,
+  color: Option<String>,
+  background: Option<String>,
+  format: Option<String>,
+  #[serde(default)]
+  face: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PreviewFormat {
+  Png,
+  Svg,
+}
+
+impl PreviewFormat {
+  fn extension(&self) -> &'static str {
+    match self {
+      PreviewFormat::Png => "png",
+      PreviewFormat::Svg => "svg",
+    }
+  }
+
+  fn content_type(&self) -> &'static str {
+    match self {
+      PreviewFormat::Png => "image/png",
+      PreviewFormat::Svg => "image/svg+xml",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rgba(u8, u8, u8, u8);
+
+/// Accepts `RRGGBB`, `RRGGBBAA` (with or without `#`) and `transparent`.
+fn parse_color(value: &str) -> Result<Rgba, String> {
+  if value.eq_ignore_ascii_case("transparent") {
+    return Ok(Rgba(0, 0, 0, 0));
+  }
+
+  let hex = value.trim_start_matches('#');
+  let channel = |index: usize| {
+    hex
+      .get(index * 2..index * 2 + 2)
+      .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+      .ok_or_else(|| format!("Invalid color '{}'", value))
+  };
+
+  match hex.len() {
+    6 => Ok(Rgba(channel(0)?, channel(1)?, channel(2)?, 255)),
+    8 => Ok(Rgba(channel(0)?, channel(1)?, channel(2)?, channel(3)?)),
+    _ => Err(format!("Invalid color '{}'", value)),
+  }
+}
+
+struct PreviewOptions {
+  text: String,
+  size: f32,
+  color: Rgba,
+  background: Rgba,
+  format: PreviewFormat,
+}
+
+impl PreviewOptions {
+  /// Stable digest of everything that affects the rendered output.
+  fn cache_hash(&self, face_index: u32) -> String {
+    let key = format!(
+      "{}\u{0}{}\u{0}{:?}\u{0}{:?}\u{0}{}",
+      self.text,
+      self.size,
+      self.color,
+      self.background,
+      face_index
+    );
+    blake3::hash(key.as_bytes()).to_hex()[..32].to_string()
+  }
+}
+
+enum Segment {
+  MoveTo(f32, f32),
+  LineTo(f32, f32),
+  QuadTo(f32, f32, f32, f32),
+  CurveTo(f32, f32, f32, f32, f32, f32),
+  Close,
+}
+
+/// Collects glyph outlines in image space: scaled, flipped and offset to the
+/// glyph's pen position.
+struct TextOutline {
+  segments: Vec<Segment>,
+  scale: f32,
+  origin_x: f32,
+  baseline: f32,
+}
+
+impl TextOutline {
+  fn point(&self, x: f32, y: f32) -> (f32, f32) {
+    (self.origin_x + x * self.scale, self.baseline - y * self.scale)
+  }
+}
+
+impl OutlineBuilder for TextOutline {
+  fn move_to(&mut self, x: f32, y: f32) {
+    let (x, y) = self.point(x, y);
+    self.segments.push(Segment::MoveTo(x, y));
+  }
+
+  fn line_to(&mut self, x: f32, y: f32) {
+    let (x, y) = self.point(x, y);
+    self.segments.push(Segment::LineTo(x, y));
+  }
+
+  fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+    let (x1, y1) = self.point(x1, y1);
+    let (x, y) = self.point(x, y);
+    self.segments.push(Segment::QuadTo(x1, y1, x, y));
+  }
+
+  fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+    let (x1, y1) = self.point(x1, y1);
+    let (x2, y2) = self.point(x2, y2);
+    let (x, y) = self.point(x, y);
+    self.segments.push(Segment::CurveTo(x1, y1, x2, y2, x, y));
+  }
+
+  fn close(&mut self) {
+    self.segments.push(Segment::Close);
+  }
+}
+
+/// Places glyphs left to right using advance widths only; there is no shaping,
+/// so ligatures, kerning and complex scripts are not applied.
+fn layout_text(face: &Face, options: &PreviewOptions) -> Result<(TextOutline, u32, u32), String> {
+  let scale = options.size / f32::from(face.units_per_em());
+  let ascender = f32::from(face.ascender()) * scale;
+  let descender = f32::from(face.descender()) * scale;
+  let line_height = ascender - descender + f32::from(face.line_gap()) * scale;
+  let padding = (options.size * 0.25).ceil();
+
+  let mut outline = TextOutline { segments: Vec::new(), scale, origin_x: 0.0, baseline: 0.0 };
+  let mut max_width: f32 = 0.0;
+  let lines: Vec<&str> = options.text.lines().collect();
+  for (line_index, line) in lines.iter().enumerate() {
+    outline.origin_x = padding;
+    outline.baseline = padding + ascender + line_height * (line_index as f32);
+
+    for ch in line.chars() {
+      let glyph_id = face.glyph_index(ch).unwrap_or(GlyphId(0));
+      face.outline_glyph(glyph_id, &mut outline);
+      outline.origin_x += f32::from(face.glyph_hor_advance(glyph_id).unwrap_or(0)) * scale;
+    }
+    max_width = max_width.max(outline.origin_x);
+  }
+
+  let text_height = line_height * (lines.len().max(1) as f32 - 1.0) + ascender - descender;
+  let width = (max_width + padding).ceil().max(1.0);
+  let height = (text_height + padding * 2.0).ceil().max(1.0);
+  if width > (MAX_DIMENSION as f32) || height > (MAX_DIMENSION as f32) {
+    return Err(format!("Preview would exceed {}x{} pixels", MAX_DIMENSION, MAX_DIMENSION));
+  }
+
+  Ok((outline, width as u32, height as u32))
+}
+
+fn render_png(
+  outline: &TextOutline,
+  width: u32,
+  height: u32,
+  options: &PreviewOptions
+) -> Result<Vec<u8>, String> {
+  let mut pixmap = Pixmap::new(width, height).ok_or("Invalid preview dimensions")?;
+  let Rgba(r, g, b, a) = options.background;
+  pixmap.fill(Color::from_rgba8(r, g, b, a));
+
+  let mut builder = PathBuilder::new();
+  for segment in &outline.segments {
+    match *segment {
+      Segment::MoveTo(x, y) => builder.move_to(x, y),
+      Segment::LineTo(x, y) => builder.line_to(x, y),
+      Segment::QuadTo(x1, y1, x, y) => builder.quad_to(x1, y1, x, y),
+      Segment::CurveTo(x1, y1, x2, y2, x, y) => builder.cubic_to(x1, y1, x2, y2, x, y),
+      Segment::Close => builder.close(),
+    }
+  }
+
+  // Text without any outlines, e.g. only spaces, yields no path at all.
+  if let Some(path) = builder.finish() {
+    let Rgba(r, g, b, a) = options.color;
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(r, g, b, a);
+    paint.anti_alias = true;
+    pixmap.fill_path(&path, &paint, FillRule::Winding, Transform::identity(), None);
+  }
+
+  pixmap.encode_png().map_err(|e| format!("Failed to encode PNG: {}", e))
+}
+
+fn svg_fill(Rgba(r, g, b, a): Rgba) -> String {
+  format!("fill=\"#{:02x}{:02x}{:02x}\" fill-opacity=\"{:.3}\"", r, g, b, f32::from(a) / 255.0)
+}
+
+fn render_svg(
+  outline: &TextOutline,
+  width: u32,
+  height: u32,
+  options: &PreviewOptions
+) -> Vec<u8> {
+  let mut path = String::new();
+  for segment in &outline.segments {
+    let _ = match *segment {
+      Segment::MoveTo(x, y) => write!(path, "M{:.2} {:.2}", x, y),
+      Segment::LineTo(x, y) => write!(path, "L{:.2} {:.2}", x, y),
+      Segment::QuadTo(x1, y1, x, y) => write!(path, "Q{:.2} {:.2} {:.2} {:.2}", x1, y1, x, y),
+      Segment::CurveTo(x1, y1, x2, y2, x, y) => {
+        write!(path, "C{:.2} {:.2} {:.2} {:.2} {:.2} {:.2}", x1, y1, x2, y2, x, y)
+      }
+      Segment::Close => write!(path, "Z"),
+    };
+  }
+
+  let mut svg = format!(
+    "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+    w = width,
+    h = height
+  );
+  if options.background.3 > 0 {
+    let _ = write!(svg, "<rect width=\"100%\" height=\"100%\" {}/>", svg_fill(options.background));
+  }
+  if !path.is_empty() {
+    let _ = write!(svg, "<path {} d=\"{}\"/>", svg_fill(options.color), path);
+  }
+  svg.push_str("</svg>");
+
+  svg.into_bytes()
+}
+
+/// Renders the text with the font's default instance; color glyph layers and
+/// bitmap-only glyphs are not drawn.
+fn render_preview(
+  data: &[u8],
+  face_index: u32,
+  options: &PreviewOptions
+) -> Result<Vec<u8>, String> {
+  let (_, sfnt) = decode_font(data).map_err(|e| e.to_string())?;
+  let face = Face::parse(&sfnt, face_index).map_err(|e|
+    format!("Error parsing font data: {:?}", e)
+  )?;
+
+  let (outline, width, height) = layout_text(&face, options)?;
+  match options.format {
+    PreviewFormat::Png => render_png(&outline, width, height, options),
+    PreviewFormat::Svg => Ok(render_svg(&outline, width, height, options)),
+  }
+}
+
+pub async fn preview_handler(
+  user: AuthUser,
+  Path(key): Path<String>,
+  Query(params): Query<PreviewParams>,
+  State(state): State<AppState>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+  let user_key = format!("{}/{}", user.user_id, key);
+
+  let format = match params.format.as_deref() {
+    None | Some("png") => PreviewFormat::Png,
+    Some("svg") => PreviewFormat::Svg,
+    Some(other) => {
+      return Err((StatusCode::BAD_REQUEST, format!("Unsupported preview format '{}'", other)));
+    }
+  };
+  let size = params.size.unwrap_or(DEFAULT_SIZE);
+  if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+    return Err((
+      StatusCode::BAD_REQUEST,
+      format!("'size' must be between {} and {}", MIN_SIZE, MAX_SIZE),
+    ));
+  }
+  let color = parse_color(params.color.as_deref().unwrap_or("000000")).map_err(|e| {
+    (StatusCode::BAD_REQUEST, e)
+  })?;
+  let background = parse_color(params.background.as_deref().unwrap_or("ffffff")).map_err(|e| {
+    (StatusCode::BAD_REQUEST, e)
+  })?;
+
+  let records = get_metadata_by_path(&state.db_client, &user.user_id, &user_key).await.map_err(|e| {
+    error!("Failed to fetch metadata for {}: {}", key, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up font metadata".to_string())
+  })?;
+  let record = records
+    .iter()
+    .find(|record| record.face_index == (params.face as i32))
+    .ok_or_else(|| (StatusCode::NOT_FOUND, format!("File '{}' does not exist", key)))?;
+
+  let text = match params.text {
+    Some(text) => text,
+    None if !record.metadata.sample_text.is_empty() => record.metadata.sample_text.clone(),
+    None => DEFAULT_TEXT.to_string(),
+  };
+  if text.chars().count() > MAX_TEXT_CHARS {
+    return Err((
+      StatusCode::BAD_REQUEST,
+      format!("'text' is limited to {} characters", MAX_TEXT_CHARS),
+    ));
+  }
+
+  let options = PreviewOptions { text, size, color, background, format };
+  let derived_key = format!(
+    "{}/previews/{}/{}.{}",
+    DERIVED_PREFIX,
+    record.metadata.checksum,
+    options.cache_hash(params.face),
+    format.extension()
+  );
+
+  let cached = fetch_object(&state, &derived_key).await.map_err(|e| {
+    error!("Failed to read cached preview {}: {}", derived_key, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Unable to retrieve preview".to_string())
+  })?;
+  let image = match cached {
+    Some(image) => image,
+    None => {
+      let data = fetch_stored_file(&state, &record.object_path, &record.metadata.checksum).await
+        .map_err(|e| {
+          error!("Failed to retrieve file {}: {}", key, e);
+          (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Unable to retrieve file '{}': Server error", key),
+          )
+        })?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("File '{}' does not exist", key)))?;
+
+      info!("User {} - Client {} rendering preview of {}", user.email, user.client_id, key);
+      let face_index = params.face;
+      let image = tokio::task
+        ::spawn_blocking(move || render_preview(&data, face_index, &options)).await
+        .map_err(|e| {
+          error!("Preview task failed for {}: {}", key, e);
+          (StatusCode::INTERNAL_SERVER_ERROR, "Preview rendering failed".to_string())
+        })?
+        .map_err(|e| {
+          (StatusCode::UNPROCESSABLE_ENTITY, format!("Cannot render '{}': {}", key, e))
+        })?;
+
+      cache_derived_object(&state, &derived_key, format.content_type(), image.clone()).await;
+      image
+    }
+  };
+
+  Ok((StatusCode::OK, [(header::CONTENT_TYPE, format.content_type())], image))
+}
@@ -0,0 +1,510 @@
+use std::collections::{ hash_map::Entry, HashMap };
+
+use axum::{
+  extract::{ Path, State },
+  http::StatusCode,
+  response::IntoResponse,
+  Json,
+};
+use log::error;
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ name_id, Face, GlyphId, Tag };
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  binary::{ read_i16, read_u16 },
+  database::{ get_metadata_by_family, get_metadata_by_path, FontRecord },
+  metadata::{
+    get_font_family,
+    get_font_subfamily,
+    get_full_name,
+    get_name_string,
+    get_postscript_name,
+  },
+};
+
+const FS_SELECTION_ITALIC: u16 = 1 << 0;
+const FS_SELECTION_BOLD: u16 = 1 << 5;
+const FS_SELECTION_REGULAR: u16 = 1 << 6;
+const FS_SELECTION_USE_TYPO_METRICS: u16 = 1 << 7;
+const MAC_STYLE_BOLD: u16 = 1 << 0;
+const MAC_STYLE_ITALIC: u16 = 1 << 1;
+
+/// Weight keywords as they appear in style names, longest first so that
+/// "ExtraBold" is not read as "Bold".
+static WEIGHT_NAMES: &[(&str, i16)] = &[
+  ("extralight", 200),
+  ("ultralight", 200),
+  ("extrabold", 800),
+  ("ultrabold", 800),
+  ("semibold", 600),
+  ("demibold", 600),
+  ("hairline", 100),
+  ("medium", 500),
+  ("black", 900),
+  ("heavy", 900),
+  ("light", 300),
+  ("thin", 100),
+  ("bold", 700),
+];
+
+/// Outcome of a check, ordered from harmless to blocking like fontbakery's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QaStatus {
+  Pass,
+  Skip,
+  Info,
+  Warn,
+  Fail,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QaCheck {
+  pub check: String,
+  pub status: QaStatus,
+  pub message: String,
+}
+
+impl QaCheck {
+  fn new(check: &str, status: QaStatus, message: impl Into<String>) -> Self {
+    QaCheck { check: check.to_string(), status, message: message.into() }
+  }
+}
+
+/// Raw vertical metrics, kept so family-wide consistency can be checked
+/// without reopening the fonts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerticalMetrics {
+  pub hhea_ascender: i16,
+  pub hhea_descender: i16,
+  pub hhea_line_gap: i16,
+  pub typo_ascender: Option<i16>,
+  pub typo_descender: Option<i16>,
+  pub typo_line_gap: Option<i16>,
+  pub win_ascent: Option<u16>,
+  pub win_descent: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QaReport {
+  pub vertical_metrics: Option<VerticalMetrics>,
+  pub checks: Vec<QaCheck>,
+}
+
+fn table<'a>(face: &Face<'a>, tag: &[u8; 4]) -> Option<&'a [u8]> {
+  face.raw_face().table(Tag::from_bytes(tag))
+}
+
+fn extract_vertical_metrics(face: &Face) -> Option<VerticalMetrics> {
+  let hhea = table(face, b"hhea")?;
+  let os2 = table(face, b"OS/2");
+
+  Some(VerticalMetrics {
+    hhea_ascender: read_i16(hhea, 4)?,
+    hhea_descender: read_i16(hhea, 6)?,
+    hhea_line_gap: read_i16(hhea, 8)?,
+    typo_ascender: os2.and_then(|os2| read_i16(os2, 68)),
+    typo_descender: os2.and_then(|os2| read_i16(os2, 70)),
+    typo_line_gap: os2.and_then(|os2| read_i16(os2, 72)),
+    win_ascent: os2.and_then(|os2| read_u16(os2, 74)),
+    win_descent: os2.and_then(|os2| read_u16(os2, 76)),
+  })
+}
+
+fn check_notdef(face: &Face) -> QaCheck {
+  const CHECK: &str = "notdef";
+
+  if face.number_of_glyphs() == 0 {
+    return QaCheck::new(CHECK, QaStatus::Fail, "Font has no glyphs");
+  }
+  if let Some(name) = face.glyph_name(GlyphId(0)) && name != ".notdef" {
+    return QaCheck::new(
+      CHECK,
+      QaStatus::Warn,
+      format!("Glyph 0 is named '{}' instead of '.notdef'", name)
+    );
+  }
+  if face.glyph_bounding_box(GlyphId(0)).is_none() {
+    return QaCheck::new(CHECK, QaStatus::Warn, "The .notdef glyph has no outline");
+  }
+
+  QaCheck::new(CHECK, QaStatus::Pass, "Glyph 0 is a drawn .notdef")
+}
+
+fn check_postscript_name(face: &Face) -> QaCheck {
+  const CHECK: &str = "postscript_name";
+
+  let postscript_name = get_postscript_name(face);
+  if postscript_name.is_empty() {
+    return QaCheck::new(CHECK, QaStatus::Fail, "Name ID 6 (PostScript name) is missing");
+  }
+  let invalid = postscript_name
+    .chars()
+    .any(|ch| !ch.is_ascii_graphic() || "[](){}<>/%".contains(ch));
+  if invalid || postscript_name.len() > 63 {
+    return QaCheck::new(
+      CHECK,
+      QaStatus::Fail,
+      format!(
+        "'{}' must be at most 63 printable ASCII characters without spaces or []{{}}()<>/%",
+        postscript_name
+      )
+    );
+  }
+
+  // Fonts without typographic names often fold the weight into the family,
+  // e.g. "Open Sans Light" / "Regular" for OpenSans-Light, so the full name
+  // is accepted as well.
+  let compact = |name: &str| name.split_whitespace().collect::<String>();
+  let family = get_font_family(face);
+  let subfamily = get_font_subfamily(face);
+  let expected = format!("{}-{}", compact(&family), compact(&subfamily));
+  let candidates = [
+    compact(&format!("{}{}", family, subfamily)),
+    compact(&get_full_name(face)),
+  ];
+  if !candidates.contains(&postscript_name.replace('-', "")) {
+    return QaCheck::new(
+      CHECK,
+      QaStatus::Warn,
+      format!(
+        "'{}' does not match the family and style names, expected '{}'",
+        postscript_name,
+        expected
+      )
+    );
+  }
+
+  QaCheck::new(CHECK, QaStatus::Pass, "PostScript name matches family and style")
+}
+
+fn check_full_name(face: &Face) -> QaCheck {
+  const CHECK: &str = "full_name";
+
+  let full_name = get_full_name(face);
+  let family = get_font_family(face);
+  let subfamily = get_font_subfamily(face);
+  let expected = format!("{} {}", family, subfamily);
+  if full_name == expected || (subfamily == "Regular" && full_name == family) {
+    return QaCheck::new(CHECK, QaStatus::Pass, "Full name matches family and style");
+  }
+
+  QaCheck::new(
+    CHECK,
+    QaStatus::Warn,
+    format!(
+      "Full name '{}' does not match the family and style, expected '{}'",
+      full_name,
+      expected
+    )
+  )
+}
+
+/// Compares fsSelection and macStyle against the legacy (name ID 2) style name,
+/// which is what the RIBBI style-linking bits describe.
+fn check_fs_selection(face: &Face) -> QaCheck {
+  const CHECK: &str = "fs_selection";
+
+  let (Some(os2), Some(head)) = (table(face, b"OS/2"), table(face, b"head")) else {
+    return QaCheck::new(CHECK, QaStatus::Skip, "Font has no OS/2 table");
+  };
+  let (Some(fs_selection), Some(mac_style)) = (read_u16(os2, 62), read_u16(head, 44)) else {
+    return QaCheck::new(CHECK, QaStatus::Skip, "OS/2 or head table is truncated");
+  };
+
+  let style = get_name_string(face, name_id::SUBFAMILY).to_lowercase();
+  let named_italic = style.contains("italic") || style.contains("oblique");
+  let named_bold = style.split_whitespace().any(|word| word == "bold");
+  let italic = fs_selection & FS_SELECTION_ITALIC != 0;
+  let bold = fs_selection & FS_SELECTION_BOLD != 0;
+  let regular = fs_selection & FS_SELECTION_REGULAR != 0;
+
+  let state = |set: bool| if set { "set" } else { "unset" };
+  let mut problems = Vec::new();
+  if italic != named_italic {
+    problems.push(format!("ITALIC bit is {} for style '{}'", state(italic), style));
+  }
+  if bold != named_bold {
+    problems.push(format!("BOLD bit is {} for style '{}'", state(bold), style));
+  }
+  if regular && (bold || italic) {
+    problems.push("REGULAR bit is set together with BOLD or ITALIC".to_string());
+  }
+  if !regular && !bold && !italic {
+    problems.push("none of REGULAR, BOLD or ITALIC is set".to_string());
+  }
+  let mac_bold = mac_style & MAC_STYLE_BOLD != 0;
+  let mac_italic = mac_style & MAC_STYLE_ITALIC != 0;
+  if problems.is_empty() && (mac_bold != bold || mac_italic != italic) {
+    return QaCheck::new(CHECK, QaStatus::Warn, "head.macStyle disagrees with OS/2 fsSelection");
+  }
+
+  if problems.is_empty() {
+    QaCheck::new(CHECK, QaStatus::Pass, "fsSelection matches the style name")
+  } else {
+    QaCheck::new(CHECK, QaStatus::Fail, format!("fsSelection: {}", problems.join("; ")))
+  }
+}
+
+fn check_weight_class(face: &Face) -> QaCheck {
+  const CHECK: &str = "weight_class";
+
+  let weight = face.weight().to_number() as i16;
+  if !(100..=900).contains(&weight) || weight % 100 != 0 {
+    return QaCheck::new(
+      CHECK,
+      QaStatus::Warn,
+      format!("usWeightClass {} is not a multiple of 100 between 100 and 900", weight)
+    );
+  }
+
+  // Without a typographic subfamily the weight is usually part of the
+  // legacy family name, which the full name includes.
+  let style_name = match get_name_string(face, name_id::TYPOGRAPHIC_SUBFAMILY) {
+    name if name.is_empty() => get_full_name(face),
+    name => name,
+  };
+  let style: String = style_name
+    .to_lowercase()
+    .chars()
+    .filter(|ch| ch.is_alphabetic())
+    .collect();
+  let expected = WEIGHT_NAMES
+    .iter()
+    .find(|(name, _)| style.contains(name))
+    .map_or(400, |&(_, weight)| weight);
+  if weight != expected {
+    return QaCheck::new(
+      CHECK,
+      QaStatus::Warn,
+      format!("usWeightClass is {} but the style name suggests {}", weight, expected)
+    );
+  }
+
+  QaCheck::new(CHECK, QaStatus::Pass, format!("usWeightClass {} matches the style name", weight))
+}
+
+fn check_kerning(face: &Face) -> QaCheck {
+  const CHECK: &str = "kerning";
+
+  if face.is_monospaced() {
+    return QaCheck::new(CHECK, QaStatus::Skip, "Monospaced fonts are not kerned");
+  }
+
+  let gpos_kern = face.tables().gpos.is_some_and(|gpos| {
+    gpos.features.into_iter().any(|feature| feature.tag == Tag::from_bytes(b"kern"))
+  });
+  let legacy_kern = face
+    .tables()
+    .kern.is_some_and(|kern| kern.subtables.into_iter().next().is_some());
+  match (gpos_kern, legacy_kern) {
+    (true, _) => QaCheck::new(CHECK, QaStatus::Pass, "GPOS has a kern feature"),
+    (false, true) => {
+      QaCheck::new(CHECK, QaStatus::Info, "Only a legacy kern table is present, not GPOS kerning")
+    }
+    (false, false) => QaCheck::new(CHECK, QaStatus::Warn, "Font has no kerning"),
+  }
+}
+
+/// usWinAscent and usWinDescent clip rendering on Windows, so they have to
+/// cover the font's bounding box.
+fn check_win_metrics(face: &Face, metrics: Option<&VerticalMetrics>) -> QaCheck {
+  const CHECK: &str = "win_metrics";
+
+  let (Some(win_ascent), Some(win_descent)) = (
+    metrics.and_then(|metrics| metrics.win_ascent),
+    metrics.and_then(|metrics| metrics.win_descent),
+  ) else {
+    return QaCheck::new(CHECK, QaStatus::Skip, "Font has no OS/2 table");
+  };
+  let bbox = face.global_bounding_box();
+
+  if
+    i32::from(win_ascent) < i32::from(bbox.y_max) ||
+    -i32::from(win_descent) > i32::from(bbox.y_min)
+  {
+    return QaCheck::new(
+      CHECK,
+      QaStatus::Fail,
+      format!(
+        "usWinAscent {} / usWinDescent {} clip the bounding box ({} to {})",
+        win_ascent,
+        win_descent,
+        bbox.y_min,
+        bbox.y_max
+      )
+    );
+  }
+
+  let use_typo_metrics = table(face, b"OS/2")
+    .and_then(|os2| read_u16(os2, 62))
+    .is_some_and(|fs_selection| fs_selection & FS_SELECTION_USE_TYPO_METRICS != 0);
+  if !use_typo_metrics {
+    return QaCheck::new(CHECK, QaStatus::Info, "USE_TYPO_METRICS is not set");
+  }
+
+  QaCheck::new(CHECK, QaStatus::Pass, "Windows metrics cover the bounding box")
+}
+
+/// Checks that only need the face itself. Family-wide checks run when the
+/// report is requested, against whatever styles the family has by then.
+pub fn run_face_checks(face: &Face) -> QaReport {
+  let vertical_metrics = extract_vertical_metrics(face);
+  let checks = vec![
+    check_notdef(face),
+    check_postscript_name(face),
+    check_full_name(face),
+    check_fs_selection(face),
+    check_weight_class(face),
+    check_kerning(face),
+    check_win_metrics(face, vertical_metrics.as_ref())
+  ];
+
+  QaReport { vertical_metrics, checks }
+}
+
+fn style_name(record: &FontRecord) -> String {
+  format!("{} ({})", record.metadata.font_subfamily, record.object_path)
+}
+
+fn check_family_vertical_metrics(record: &FontRecord, family: &[FontRecord]) -> QaCheck {
+  const CHECK: &str = "family_vertical_metrics";
+
+  let Some(metrics) = &record.metadata.qa_report.vertical_metrics else {
+    return QaCheck::new(CHECK, QaStatus::Skip, "Vertical metrics were not recorded");
+  };
+  let differing: Vec<String> = family
+    .iter()
+    .filter(|other| {
+      other.metadata.qa_report.vertical_metrics.as_ref().is_some_and(|other| other != metrics)
+    })
+    .map(style_name)
+    .collect();
+
+  if differing.is_empty() {
+    QaCheck::new(CHECK, QaStatus::Pass, "Vertical metrics match the rest of the family")
+  } else {
+    QaCheck::new(
+      CHECK,
+      QaStatus::Warn,
+      format!("Vertical metrics differ from {}", differing.join(", "))
+    )
+  }
+}
+
+fn check_family_style_names(record: &FontRecord, family: &[FontRecord]) -> QaCheck {
+  const CHECK: &str = "family_style_names";
+
+  let duplicates: Vec<String> = family
+    .iter()
+    .filter(|other| {
+      (other.object_path != record.object_path || other.face_index != record.face_index) &&
+        other.metadata.font_subfamily.eq_ignore_ascii_case(&record.metadata.font_subfamily)
+    })
+    .map(|other| other.object_path.clone())
+    .collect();
+
+  if duplicates.is_empty() {
+    QaCheck::new(CHECK, QaStatus::Pass, "Style name is unique within the family")
+  } else {
+    QaCheck::new(
+      CHECK,
+      QaStatus::Fail,
+      format!("Style '{}' also exists in {}", record.metadata.font_subfamily, duplicates.join(", "))
+    )
+  }
+}
+
+#[derive(Serialize, Default)]
+struct QaSummary {
+  pass: usize,
+  skip: usize,
+  info: usize,
+  warn: usize,
+  fail: usize,
+}
+
+#[derive(Serialize)]
+struct FaceQa {
+  object_path: String,
+  face_index: i32,
+  font_family: String,
+  font_subfamily: String,
+  /// The worst status among the checks.
+  status: QaStatus,
+  summary: QaSummary,
+  checks: Vec<QaCheck>,
+}
+
+fn face_qa(record: FontRecord, family: &[FontRecord]) -> FaceQa {
+  let mut checks = record.metadata.qa_report.checks.clone();
+  checks.push(check_family_vertical_metrics(&record, family));
+  checks.push(check_family_style_names(&record, family));
+
+  let mut summary = QaSummary::default();
+  for check in &checks {
+    *(match check.status {
+      QaStatus::Pass => &mut summary.pass,
+      QaStatus::Skip => &mut summary.skip,
+      QaStatus::Info => &mut summary.info,
+      QaStatus::Warn => &mut summary.warn,
+      QaStatus::Fail => &mut summary.fail,
+    }) += 1;
+  }
+
+  FaceQa {
+    status: checks.iter().map(|check| check.status).max().unwrap_or(QaStatus::Pass),
+    object_path: record.object_path,
+    face_index: record.face_index,
+    font_family: record.metadata.font_family,
+    font_subfamily: record.metadata.font_subfamily,
+    summary,
+    checks,
+  }
+}
+
+/// Serves `GET /qa/{key}`: the stored per-face checks of every face in the
+/// file plus family checks against the family's current styles.
+pub async fn qa_handler(
+  user: AuthUser,
+  Path(key): Path<String>,
+  State(state): State<AppState>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+  let user_key = format!("{}/{}", user.user_id, key);
+  let db_error = |e: Box<dyn std::error::Error>| {
+    error!("Failed to fetch metadata for QA report of {}: {}", key, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up font metadata".to_string())
+  };
+
+  let records = get_metadata_by_path(&state.db_client, &user.user_id, &user_key).await.map_err(
+    db_error
+  )?;
+  if records.is_empty() {
+    return Err((StatusCode::NOT_FOUND, format!("File '{}' does not exist", key)));
+  }
+
+  let mut families: HashMap<String, Vec<FontRecord>> = HashMap::new();
+  for record in &records {
+    if let Entry::Vacant(entry) = families.entry(record.metadata.font_family.to_lowercase()) {
+      let members = get_metadata_by_family(
+        &state.db_client,
+        &user.user_id,
+        &record.metadata.font_family
+      ).await.map_err(db_error)?;
+      entry.insert(members);
+    }
+  }
+
+  let reports: Vec<FaceQa> = records
+    .into_iter()
+    .map(|record| {
+      let family = &families[&record.metadata.font_family.to_lowercase()];
+      face_qa(record, family)
+    })
+    .collect();
+
+  Ok(Json(reports))
+}
@@ -0,0 +1,584 @@
+//! Resumable uploads in the style of tus: a client creates a session, sends the
+//! file in PATCH requests that each carry the offset they start at, can ask
+//! how far the server got after a dropped connection, and finalizes once all
+//! bytes have arrived. Data goes straight into an S3 multipart upload under a
+//! staging key; finalizing checks and records the font exactly as `/upload`
+//! does before copying it into the blob store. Sessions without progress for
+//! a day expire; `Upload-Expires` tells clients when.
+
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use aws_sdk_s3::primitives::DateTime;
+use axum::{
+  body::{ Body, Bytes },
+  extract::{ Path, Query, State },
+  http::{ header, HeaderMap, StatusCode },
+  response::{ IntoResponse, Response },
+  Json,
+};
+use aws_sdk_s3::{
+  primitives::ByteStream,
+  types::{ CompletedMultipartUpload, CompletedPart },
+};
+use futures_util::StreamExt;
+use log::{ error, info, warn };
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  blobs::{ blob_written, copy_to_blob, reserve_blob },
+  conditional::http_date,
+  conflicts::{ parse_conflict_policy, ConflictPolicy },
+  database::{
+    claim_upload_lease,
+    create_upload_session,
+    delete_upload_session,
+    get_expired_upload_sessions,
+    get_upload_session,
+    mark_upload_completed,
+    release_upload_lease,
+    renew_upload_lease,
+    update_upload_progress,
+    UploadSession,
+    UploadedPart,
+  },
+  storage::{
+    check_upload,
+    finish_upload,
+    font_records_for,
+    spool_object,
+    upload_failure,
+    CheckedUpload,
+    FinishedUpload,
+    StoredUpload,
+    UploadError,
+    S3_BUCKET,
+    UPLOAD_PART_SIZE,
+  },
+  FILE_SIZE_LIMIT,
+};
+
+static STAGING_PREFIX: &str = "uploads";
+static UPLOAD_OFFSET: &str = "upload-offset";
+static UPLOAD_LENGTH: &str = "upload-length";
+static UPLOAD_EXPIRES: &str = "upload-expires";
+
+const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SWEEP_BATCH_SIZE: i64 = 100;
+/// How long a PATCH may go without writing a part before another request can
+/// take over the session. Renewed before every part.
+const LEASE_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Deserialize)]
+pub struct CreateUpload {
+  /// Path relative to the user's library, as with the `path` field of `/upload`.
+  path: String,
+  length: u64,
+  content_type: Option<String>,
+  on_conflict: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct FinalizeParams {
+  /// Overrides the policy chosen at creation, e.g. to retry after a 409.
+  on_conflict: Option<String>,
+}
+
+fn staging_key(id: &Uuid) -> String {
+  format!("{}/{}", STAGING_PREFIX, id)
+}
+
+fn db_failure(context: &str, e: Box<dyn std::error::Error>) -> (StatusCode, String) {
+  error!("{}: {}", context, e);
+  (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string())
+}
+
+fn concurrent_write() -> (StatusCode, String) {
+  (StatusCode::CONFLICT, "Upload was modified concurrently".to_string())
+}
+
+fn now_secs() -> i64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs() as i64).unwrap_or_default()
+}
+
+fn expires_at(session: &UploadSession) -> i64 {
+  session.updated_at + (SESSION_TTL.as_secs() as i64)
+}
+
+fn upload_expires(session: &UploadSession) -> String {
+  http_date(&DateTime::from_secs(expires_at(session))).unwrap_or_default()
+}
+
+async fn load_session(
+  state: &AppState,
+  user: &AuthUser,
+  id: &Uuid
+) -> Result<UploadSession, (StatusCode, String)> {
+  let session = get_upload_session(&state.db_client, &user.user_id, id).await
+    .map_err(|e| db_failure("Failed to load upload session", e))?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Upload session '{}' does not exist", id)))?;
+
+  if expires_at(&session) <= now_secs() {
+    discard_session(state, &session).await;
+    return Err((StatusCode::GONE, format!("Upload session '{}' has expired", id)));
+  }
+
+  Ok(session)
+}
+
+fn progress_response(status: StatusCode, session: &UploadSession) -> Response {
+  (
+    status,
+    [
+      (UPLOAD_OFFSET, session.upload_offset.to_string()),
+      (UPLOAD_LENGTH, session.upload_length.to_string()),
+      (UPLOAD_EXPIRES, upload_expires(session)),
+      (header::CACHE_CONTROL.as_str(), "no-store".to_string()),
+    ],
+    Json(session),
+  ).into_response()
+}
+
+/// Drops everything a session left in S3 and Postgres. Failures are only
+/// logged; a leftover staging object is never listed or synced.
+async fn discard_session(state: &AppState, session: &UploadSession) {
+  let key = staging_key(&session.id);
+
+  if session.completed {
+    if let Err(e) = state.s3_client.delete_object().bucket(S3_BUCKET).key(&key).send().await {
+      warn!("Failed to delete staged upload {}: {}", key, e);
+    }
+  } else if
+    let Err(e) = state.s3_client
+      .abort_multipart_upload()
+      .bucket(S3_BUCKET)
+      .key(&key)
+      .upload_id(&session.s3_upload_id)
+      .send().await
+  {
+    warn!("Failed to abort multipart upload for {}: {}", key, e);
+  }
+
+  if let Err(e) = delete_upload_session(&state.db_client, &session.id).await {
+    error!("Failed to delete upload session {}: {}", session.id, e);
+  }
+}
+
+/// Background job that discards sessions idle for longer than [`SESSION_TTL`],
+/// so abandoned multipart uploads stop accruing storage.
+pub async fn expire_upload_sessions(state: AppState) {
+  let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+  loop {
+    interval.tick().await;
+
+    let lookup = get_expired_upload_sessions(
+      &state.db_client,
+      SESSION_TTL.as_secs_f64(),
+      SWEEP_BATCH_SIZE
+    );
+    let expired = match lookup.await {
+      Ok(expired) => expired,
+      Err(e) => {
+        error!("Failed to look up expired upload sessions: {}", e);
+        continue;
+      }
+    };
+    for session in expired {
+      info!("Discarding expired upload session {} for {}", session.id, session.path);
+      discard_session(&state, &session).await;
+    }
+  }
+}
+
+async fn upload_session_part(
+  state: &AppState,
+  session: &UploadSession,
+  part_number: i32,
+  data: Bytes
+) -> Result<UploadedPart, (StatusCode, String)> {
+  let part = state.s3_client
+    .upload_part()
+    .bucket(S3_BUCKET)
+    .key(staging_key(&session.id))
+    .upload_id(&session.s3_upload_id)
+    .part_number(part_number)
+    .body(ByteStream::from(data))
+    .send().await
+    .map_err(|e| upload_failure(&session.file_name, e))?;
+
+  Ok(UploadedPart { part_number, e_tag: part.e_tag().unwrap_or_default().to_string() })
+}
+
+/// `POST /uploads` starts a session and answers with its location.
+pub async fn create_upload_handler(
+  user: AuthUser,
+  State(state): State<AppState>,
+  Json(request): Json<CreateUpload>
+) -> Result<Response, (StatusCode, String)> {
+  let path = request.path.trim_start_matches('/').to_string();
+  let file_name = path.rsplit('/').next().unwrap_or_default().to_string();
+  if file_name.is_empty() {
+    return Err((StatusCode::BAD_REQUEST, "Missing file path".to_string()));
+  }
+  if request.length == 0 || request.length > (FILE_SIZE_LIMIT as u64) {
+    return Err((
+      StatusCode::PAYLOAD_TOO_LARGE,
+      format!("Upload length must be between 1 and {} bytes", FILE_SIZE_LIMIT),
+    ));
+  }
+  let policy = match request.on_conflict.as_deref() {
+    Some(value) => parse_conflict_policy(value)?,
+    None => ConflictPolicy::default(),
+  };
+  let content_type = request.content_type.unwrap_or_else(|| {
+    "application/octet-stream".to_string()
+  });
+
+  let id = Uuid::new_v4();
+  let upload = state.s3_client
+    .create_multipart_upload()
+    .bucket(S3_BUCKET)
+    .key(staging_key(&id))
+    .content_type(&content_type)
+    .send().await
+    .map_err(|e| upload_failure(&file_name, e))?;
+
+  let session = UploadSession {
+    id,
+    user_id: user.user_id,
+    path,
+    file_name,
+    content_type,
+    on_conflict: policy.as_str().to_string(),
+    upload_length: request.length as i64,
+    upload_offset: 0,
+    s3_upload_id: upload.upload_id().unwrap_or_default().to_string(),
+    parts: Vec::new(),
+    pending: Vec::new(),
+    completed: false,
+    updated_at: now_secs(),
+  };
+  let created = create_upload_session(&state.db_client, &session).await.map_err(|e|
+    db_failure("Failed to create upload session", e)
+  );
+  if let Err(failure) = created {
+    discard_session(&state, &session).await;
+    return Err(failure);
+  }
+
+  info!(
+    "User {} - Client {} started resumable upload {} for {} ({} bytes)",
+    user.email,
+    user.client_id,
+    id,
+    session.path,
+    session.upload_length
+  );
+  let mut response = progress_response(StatusCode::CREATED, &session);
+  if let Ok(location) = format!("/uploads/{}", id).parse() {
+    response.headers_mut().insert(header::LOCATION, location);
+  }
+
+  Ok(response)
+}
+
+/// `GET`/`HEAD /uploads/{id}` reports how many bytes the server has.
+pub async fn upload_status_handler(
+  user: AuthUser,
+  Path(id): Path<Uuid>,
+  State(state): State<AppState>
+) -> Result<Response, (StatusCode, String)> {
+  let session = load_session(&state, &user, &id).await?;
+
+  Ok(progress_response(StatusCode::OK, &session))
+}
+
+/// Streams `body` into the session's multipart upload while holding the lease
+/// `lease_id`, recording progress after every part.
+async fn write_chunk(
+  state: &AppState,
+  session: &mut UploadSession,
+  lease_id: &Uuid,
+  body: Body
+) -> Result<(), (StatusCode, String)> {
+  let part_size = UPLOAD_PART_SIZE as usize;
+  let mut parts = std::mem::take(&mut session.parts);
+  let mut buffer = std::mem::take(&mut session.pending);
+  let mut received = session.upload_offset;
+  let mut failure = None;
+  let mut stream = body.into_data_stream();
+
+  'receive: while let Some(chunk) = stream.next().await {
+    let chunk = match chunk {
+      Ok(chunk) => chunk,
+      Err(e) => {
+        failure = Some((StatusCode::BAD_REQUEST, format!("Upload interrupted: {}", e)));
+        break;
+      }
+    };
+    if received + (chunk.len() as i64) > session.upload_length {
+      failure = Some((
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("Data beyond the declared length of {} bytes", session.upload_length),
+      ));
+      break;
+    }
+    buffer.extend_from_slice(&chunk);
+    received += chunk.len() as i64;
+
+    while buffer.len() >= part_size {
+      let renewed = renew_upload_lease(
+        &state.db_client,
+        &session.id,
+        lease_id,
+        LEASE_TTL.as_secs_f64()
+      ).await.map_err(|e| db_failure("Failed to renew upload lease", e))?;
+      if !renewed {
+        return Err(concurrent_write());
+      }
+
+      let part_number = (parts.len() as i32) + 1;
+      let data = Bytes::copy_from_slice(&buffer[..part_size]);
+      match upload_session_part(state, session, part_number, data).await {
+        Ok(part) => parts.push(part),
+        Err(e) => {
+          failure = Some(e);
+          break 'receive;
+        }
+      }
+      buffer.drain(..part_size);
+
+      let recorded = update_upload_progress(
+        &state.db_client,
+        &session.id,
+        lease_id,
+        session.upload_offset,
+        received,
+        &parts,
+        &buffer
+      ).await.map_err(|e| db_failure("Failed to record upload progress", e))?;
+      if !recorded {
+        return Err(concurrent_write());
+      }
+      session.upload_offset = received;
+      session.updated_at = now_secs();
+    }
+  }
+
+  // Bytes that arrived after the last full part still count as received, even
+  // when the stream broke off or a part failed to upload.
+  if received != session.upload_offset {
+    let recorded = update_upload_progress(
+      &state.db_client,
+      &session.id,
+      lease_id,
+      session.upload_offset,
+      received,
+      &parts,
+      &buffer
+    ).await.map_err(|e| db_failure("Failed to record upload progress", e))?;
+    if !recorded {
+      return Err(concurrent_write());
+    }
+    session.upload_offset = received;
+    session.updated_at = now_secs();
+  }
+
+  if let Some(failure) = failure {
+    warn!("Resumable upload {} stopped at {}: {}", session.id, session.upload_offset, failure.1);
+    return Err(failure);
+  }
+
+  Ok(())
+}
+
+/// `PATCH /uploads/{id}` appends the body at `Upload-Offset`, which must match
+/// the offset the server reports. Whatever arrives before a connection drops
+/// is kept, so the client resumes from the offset reported afterwards. Only
+/// one request writes to a session at a time; others get a 409.
+pub async fn upload_chunk_handler(
+  user: AuthUser,
+  Path(id): Path<Uuid>,
+  State(state): State<AppState>,
+  headers: HeaderMap,
+  body: Body
+) -> Result<Response, (StatusCode, String)> {
+  let offset: i64 = headers
+    .get(UPLOAD_OFFSET)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.parse().ok())
+    .ok_or((StatusCode::BAD_REQUEST, "Missing or invalid Upload-Offset header".to_string()))?;
+
+  let mut session = load_session(&state, &user, &id).await?;
+  if session.completed {
+    return Err((StatusCode::CONFLICT, "Upload has already been finalized".to_string()));
+  }
+  if offset != session.upload_offset {
+    return Err((
+      StatusCode::CONFLICT,
+      format!(
+        "Upload-Offset {} does not match the current offset {}",
+        offset,
+        session.upload_offset
+      ),
+    ));
+  }
+
+  let lease_id = Uuid::new_v4();
+  let claimed = claim_upload_lease(
+    &state.db_client,
+    &id,
+    offset,
+    &lease_id,
+    LEASE_TTL.as_secs_f64()
+  ).await.map_err(|e| db_failure("Failed to claim upload session", e))?;
+  if !claimed {
+    return Err((StatusCode::CONFLICT, "Another request is writing to this upload".to_string()));
+  }
+
+  let written = write_chunk(&state, &mut session, &lease_id, body).await;
+  if let Err(e) = release_upload_lease(&state.db_client, &id, &lease_id).await {
+    warn!("Failed to release lease on upload session {}: {}", id, e);
+  }
+  written?;
+
+  Ok(
+    (
+      StatusCode::NO_CONTENT,
+      [
+        (UPLOAD_OFFSET, session.upload_offset.to_string()),
+        (UPLOAD_LENGTH, session.upload_length.to_string()),
+        (UPLOAD_EXPIRES, upload_expires(&session)),
+      ],
+    ).into_response()
+  )
+}
+
+/// Completes the S3 multipart upload once every byte is there. Safe to repeat
+/// after a later step of finalizing failed.
+async fn complete_session_upload(
+  state: &AppState,
+  session: &mut UploadSession
+) -> Result<(), (StatusCode, String)> {
+  if session.completed {
+    return Ok(());
+  }
+
+  if !session.pending.is_empty() {
+    let part_number = (session.parts.len() as i32) + 1;
+    let data = Bytes::from(std::mem::take(&mut session.pending));
+    let part = upload_session_part(state, session, part_number, data).await?;
+    session.parts.push(part);
+  }
+
+  let parts = session.parts
+    .iter()
+    .map(|part| CompletedPart::builder().part_number(part.part_number).e_tag(&part.e_tag).build())
+    .collect();
+  state.s3_client
+    .complete_multipart_upload()
+    .bucket(S3_BUCKET)
+    .key(staging_key(&session.id))
+    .upload_id(&session.s3_upload_id)
+    .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+    .send().await
+    .map_err(|e| upload_failure(&session.file_name, e))?;
+
+  mark_upload_completed(&state.db_client, &session.id).await.map_err(|e|
+    db_failure("Failed to mark upload as completed", e)
+  )?;
+  session.completed = true;
+
+  Ok(())
+}
+
+/// `POST /uploads/{id}/finalize` validates the assembled file, stores it unless
+/// the same bytes already are, and records its path in the user's library.
+pub async fn finalize_upload_handler(
+  user: AuthUser,
+  Path(id): Path<Uuid>,
+  Query(params): Query<FinalizeParams>,
+  State(state): State<AppState>
+) -> Result<Response, UploadError> {
+  let mut session = load_session(&state, &user, &id).await?;
+  if session.upload_offset != session.upload_length {
+    let message = format!(
+      "Upload incomplete: {} of {} bytes received",
+      session.upload_offset,
+      session.upload_length
+    );
+    return Err((StatusCode::CONFLICT, message).into());
+  }
+  let policy_name = params.on_conflict.as_deref().unwrap_or(&session.on_conflict);
+  let policy = parse_conflict_policy(policy_name)?;
+
+  complete_session_upload(&state, &mut session).await?;
+
+  let key = staging_key(&session.id);
+  let spooled = spool_object(&state, &key).await?;
+
+  let user_key = format!("{}/{}", user.user_id, session.path);
+  let checked = check_upload(
+    &state,
+    &user,
+    &user_key,
+    &session.file_name,
+    &spooled,
+    policy
+  ).await;
+  let (faces, conflicts) = match checked {
+    Ok(CheckedUpload::Accepted { faces, conflicts }) => (faces, conflicts),
+    Ok(CheckedUpload::Duplicate(existing_path)) => {
+      discard_session(&state, &session).await;
+      return Ok((StatusCode::OK, format!("Duplicate file: {}", existing_path)).into_response());
+    }
+    Err(e) => {
+      // A rejected or invalid font will not get better; name conflicts can
+      // be retried with a different on_conflict.
+      if e.status().is_client_error() && e.status() != StatusCode::CONFLICT {
+        discard_session(&state, &session).await;
+      }
+      return Err(e);
+    }
+  };
+
+  if let Some(blob_key) = reserve_blob(&state, &spooled.checksum).await? {
+    copy_to_blob(&state, &key, &blob_key, &session.content_type, &session.file_name).await?;
+    blob_written(&state, &spooled.checksum).await?;
+  }
+  discard_session(&state, &session).await;
+
+  let font_records = font_records_for(&user_key, faces);
+  info!(
+    "User {} - Client {} finalized resumable upload {}: {}",
+    user.email,
+    user.client_id,
+    id,
+    session.path
+  );
+
+  let upload = FinishedUpload {
+    file_name: &session.file_name,
+    sync_path: &session.path,
+    font_records,
+    stored: vec![StoredUpload { spooled, content_type: session.content_type.clone() }],
+  };
+  Ok(finish_upload(&state, &user, upload, policy, &conflicts).await?)
+}
+
+/// `DELETE /uploads/{id}` abandons a session and frees what it stored.
+pub async fn cancel_upload_handler(
+  user: AuthUser,
+  Path(id): Path<Uuid>,
+  State(state): State<AppState>
+) -> Result<StatusCode, (StatusCode, String)> {
+  let session = load_session(&state, &user, &id).await?;
+  discard_session(&state, &session).await;
+  info!("User {} cancelled resumable upload {}", user.email, id);
+
+  Ok(StatusCode::NO_CONTENT)
+}
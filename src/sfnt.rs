@@ -0,0 +1,165 @@
+use ttf_parser::{ RawFace, Tag };
+
+use crate::binary::{ read_i16, read_u16, read_u32 };
+
+pub const TTCF_TAG: u32 = u32::from_be_bytes(*b"ttcf");
+const HEAD_TAG: Tag = Tag::from_bytes(b"head");
+pub const CHECKSUM_MAGIC: u32 = 0xb1b0afba;
+
+pub const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+pub const WE_HAVE_A_SCALE: u16 = 0x0008;
+pub const MORE_COMPONENTS: u16 = 0x0020;
+pub const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+pub const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+pub const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+#[derive(Debug, Clone)]
+pub struct SfntTable {
+  pub tag: Tag,
+  pub data: Vec<u8>,
+}
+
+pub fn table_checksum(data: &[u8]) -> u32 {
+  data.chunks(4).fold(0u32, |sum, chunk| {
+    let mut word = [0u8; 4];
+    word[..chunk.len()].copy_from_slice(chunk);
+    sum.wrapping_add(u32::from_be_bytes(word))
+  })
+}
+
+/// Returns the offset of a face's table directory, resolving collection
+/// headers for `.ttc`/`.otc` data.
+pub fn face_offset(data: &[u8], index: u32) -> Option<usize> {
+  if read_u32(data, 0)? == TTCF_TAG {
+    read_u32(data, 12 + 4 * (index as usize)).map(|offset| offset as usize)
+  } else if index == 0 {
+    Some(0)
+  } else {
+    None
+  }
+}
+
+/// Copies every table of a face out of an sfnt or collection, in directory
+/// order, along with the face's sfnt version.
+pub fn read_tables(
+  data: &[u8],
+  index: u32
+) -> Result<(u32, Vec<SfntTable>), Box<dyn std::error::Error>> {
+  let raw_face = RawFace::parse(data, index).map_err(|e|
+    format!("Error parsing font data: {:?}", e)
+  )?;
+  let flavor = face_offset(data, index)
+    .and_then(|offset| read_u32(data, offset))
+    .ok_or("Missing sfnt version")?;
+
+  let mut tables = Vec::with_capacity(raw_face.table_records.len() as usize);
+  for record in raw_face.table_records {
+    let table = raw_face
+      .table(record.tag)
+      .ok_or_else(|| format!("Table '{}' lies outside of the font data", record.tag))?;
+    tables.push(SfntTable { tag: record.tag, data: table.to_vec() });
+  }
+
+  Ok((flavor, tables))
+}
+
+/// Size of the sfnt `build_sfnt` would produce for these tables.
+pub fn sfnt_size(tables: &[SfntTable]) -> usize {
+  12 + 16 * tables.len() + tables.iter().map(|t| (t.data.len() + 3) & !3).sum::<usize>()
+}
+
+/// Serializes tables into a single sfnt, sorting the directory, padding every
+/// table to four bytes and recomputing the `head` checkSumAdjustment.
+pub fn build_sfnt(flavor: u32, mut tables: Vec<SfntTable>) -> Vec<u8> {
+  tables.sort_by_key(|table| table.tag);
+
+  let num_tables = tables.len() as u16;
+  let entry_selector = if num_tables == 0 { 0 } else { 15 - num_tables.leading_zeros() as u16 };
+  let search_range = (1u16 << entry_selector) * 16;
+  let range_shift = num_tables * 16 - search_range;
+
+  let header_size = 12 + 16 * tables.len();
+  let mut font = Vec::with_capacity(sfnt_size(&tables));
+  font.extend_from_slice(&flavor.to_be_bytes());
+  font.extend_from_slice(&num_tables.to_be_bytes());
+  font.extend_from_slice(&search_range.to_be_bytes());
+  font.extend_from_slice(&entry_selector.to_be_bytes());
+  font.extend_from_slice(&range_shift.to_be_bytes());
+
+  let mut head_offset = None;
+  let mut offset = header_size;
+  for table in &mut tables {
+    if table.tag == HEAD_TAG && table.data.len() >= 12 {
+      table.data[8..12].copy_from_slice(&[0; 4]);
+      head_offset = Some(offset);
+    }
+
+    font.extend_from_slice(&table.tag.to_bytes());
+    font.extend_from_slice(&table_checksum(&table.data).to_be_bytes());
+    font.extend_from_slice(&(offset as u32).to_be_bytes());
+    font.extend_from_slice(&(table.data.len() as u32).to_be_bytes());
+    offset += (table.data.len() + 3) & !3;
+  }
+
+  for table in &tables {
+    font.extend_from_slice(&table.data);
+    font.resize((font.len() + 3) & !3, 0);
+  }
+
+  if let Some(head_offset) = head_offset {
+    let adjustment = CHECKSUM_MAGIC.wrapping_sub(table_checksum(&font));
+    font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+  }
+
+  font
+}
+
+/// Byte length of a composite glyph component's arguments and transform,
+/// excluding its flags and glyph index.
+pub fn component_args_len(flags: u16) -> usize {
+  let mut len = if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+  if flags & WE_HAVE_A_SCALE != 0 {
+    len += 2;
+  } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+    len += 4;
+  } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+    len += 8;
+  }
+  len
+}
+
+/// Returns the glyph IDs referenced by a composite `glyf` entry, or nothing
+/// for simple and empty glyphs.
+pub fn composite_components(glyph: &[u8]) -> Vec<u16> {
+  let mut components = Vec::new();
+  if read_i16(glyph, 0).is_none_or(|contours| contours >= 0) {
+    return components;
+  }
+
+  let mut offset = 10;
+  while let (Some(flags), Some(glyph_id)) = (read_u16(glyph, offset), read_u16(glyph, offset + 2)) {
+    components.push(glyph_id);
+    offset += 4 + component_args_len(flags);
+    if flags & MORE_COMPONENTS == 0 {
+      break;
+    }
+  }
+
+  components
+}
+
+/// Byte range of a glyph inside `glyf` according to `loca`.
+pub fn glyph_range(
+  loca: &[u8],
+  long_offsets: bool,
+  glyph_id: u16
+) -> Option<std::ops::Range<usize>> {
+  let index = glyph_id as usize;
+  let (start, end) = if long_offsets {
+    (read_u32(loca, index * 4)? as usize, read_u32(loca, index * 4 + 4)? as usize)
+  } else {
+    ((read_u16(loca, index * 2)? as usize) * 2, (read_u16(loca, index * 2 + 2)? as usize) * 2)
+  };
+
+  (start <= end).then_some(start..end)
+}
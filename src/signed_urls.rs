@@ -0,0 +1,132 @@
+//! Short-lived signed links to library fonts. Browsers fetch `@font-face`
+//! sources without an `Authorization` header, so the CSS kit points at
+//! `/kit/{user_id}/{path}` with an expiry and a keyed blake3 signature over
+//! everything that decides which bytes are served.
+
+use std::{ env, error::Error, fmt::Write, time::{ SystemTime, UNIX_EPOCH } };
+
+use axum::{
+  extract::{ Path, Query, State },
+  http::{ HeaderMap, Method, StatusCode },
+  response::{ IntoResponse, Response },
+};
+use base64::{ engine::general_purpose::URL_SAFE_NO_PAD, Engine };
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+  app_state::AppState,
+  storage::serve_library_file,
+  subset::{ serve_subset, SubsetParams },
+};
+
+/// How long a link stays valid past the hour it was issued in. Expiry is
+/// rounded up to the hour so repeated CSS requests hand out the same URLs and
+/// browsers can reuse cached fonts.
+const SIGNED_URL_TTL_SECS: u64 = 6 * 60 * 60;
+const EXPIRY_ROUNDING_SECS: u64 = 60 * 60;
+
+#[derive(Deserialize)]
+pub struct SignedFontParams {
+  expires: u64,
+  signature: String,
+  format: Option<String>,
+  text: Option<String>,
+}
+
+/// Derives the link signing key from `FONT_URL_SECRET`, falling back to the
+/// Supabase JWT secret. Called once at startup.
+pub fn url_signing_key() -> Result<[u8; 32], Box<dyn Error>> {
+  let secret = env
+    ::var("FONT_URL_SECRET")
+    .or_else(|_| env::var("SUPABASE_JWT_SECRET"))
+    .map_err(|_| "FONT_URL_SECRET or SUPABASE_JWT_SECRET must be set")?;
+  Ok(blake3::derive_key("font-app-server signed font urls v1", secret.as_bytes()))
+}
+
+fn sign(
+  signing_key: &[u8; 32],
+  key: &str,
+  format: Option<&str>,
+  text: Option<&str>,
+  expires: u64
+) -> String {
+  // Lengths keep field boundaries unambiguous whatever the values contain.
+  let mut message = String::new();
+  for field in [key, format.unwrap_or_default(), text.unwrap_or_default()] {
+    let _ = writeln!(message, "{}:{}", field.len(), field);
+  }
+  let _ = write!(message, "{}", expires);
+
+  URL_SAFE_NO_PAD.encode(blake3::keyed_hash(signing_key, message.as_bytes()).as_bytes())
+}
+
+fn now_secs() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or_default()
+}
+
+/// Returns the `expires` and `signature` query values for a link to `path` in
+/// `user_id`'s library, served as `format` or subset to `text`.
+pub fn sign_font_url(
+  signing_key: &[u8; 32],
+  user_id: &Uuid,
+  path: &str,
+  format: Option<&str>,
+  text: Option<&str>
+) -> (u64, String) {
+  let expires =
+    now_secs().div_ceil(EXPIRY_ROUNDING_SECS) * EXPIRY_ROUNDING_SECS + SIGNED_URL_TTL_SECS;
+  let signature = sign(signing_key, &format!("{}/{}", user_id, path), format, text, expires);
+  (expires, signature)
+}
+
+/// Serves a font through a link from [`sign_font_url`]. No session is needed;
+/// the signature stands in for it until the link expires.
+pub async fn signed_font_handler(
+  Path(key): Path<String>,
+  Query(params): Query<SignedFontParams>,
+  State(state): State<AppState>,
+  method: Method,
+  headers: HeaderMap
+) -> Response {
+  let expected = sign(
+    &state.url_signing_key,
+    &key,
+    params.format.as_deref(),
+    params.text.as_deref(),
+    params.expires
+  );
+  if
+    params.expires < now_secs() ||
+    !constant_time_eq(expected.as_bytes(), params.signature.as_bytes())
+  {
+    return (StatusCode::FORBIDDEN, "Invalid or expired link").into_response();
+  }
+
+  let Some((user_id, path)) = key
+    .split_once('/')
+    .and_then(|(user_id, path)| Some((Uuid::parse_str(user_id).ok()?, path))) else {
+    return (StatusCode::NOT_FOUND, format!("File '{}' does not exist", key)).into_response();
+  };
+
+  match params.text {
+    Some(text) => {
+      let params = SubsetParams { text: Some(text), format: params.format, ..Default::default() };
+      serve_subset(&state, &user_id, path, params).await.into_response()
+    }
+    None => {
+      serve_library_file(
+        &state,
+        &user_id,
+        path,
+        params.format.as_deref(),
+        &method,
+        &headers
+      ).await.into_response()
+    }
+  }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,883 @@
+use std::{ collections::HashMap, fmt::Write, io::Write as _, sync::Arc, time::Duration };
+
+use axum::{
+  extract::{ Path, Query, State },
+  http::{ header, StatusCode },
+  response::IntoResponse,
+};
+use base64::{ engine::general_purpose::STANDARD as BASE64, Engine };
+use log::{ error, info, warn };
+use flate2::{ write::ZlibEncoder, Compression };
+use pdf_writer::{ Content, Filter, Name, Pdf, Rect, Ref, Str, TextStr };
+use serde::Deserialize;
+use ttf_parser::{ Face, GlyphId, OutlineBuilder };
+use uuid::Uuid;
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  blobs::fetch_stored_file,
+  container::{ decode_font, encode_woff2 },
+  database::{ get_metadata_by_family, FontRecord },
+  sfnt::{ build_sfnt, read_tables },
+  storage::{ cache_derived_object, fetch_object, DERIVED_PREFIX, S3_BUCKET },
+};
+
+/// Quiet period before regenerating a family's specimens, so a batch of
+/// uploads to one family renders once.
+const REGENERATE_DELAY: Duration = Duration::from_secs(10);
+
+const DEFAULT_TEXT: &str = "The quick brown fox jumps over the lazy dog";
+const WATERFALL_SIZES: [f32; 8] = [72.0, 48.0, 36.0, 28.0, 24.0, 18.0, 14.0, 12.0];
+const PANGRAM_SIZE: f32 = 18.0;
+/// Characters beyond this are left out of the character set section, which
+/// keeps CJK specimens to a reasonable number of pages.
+const MAX_CHARSET: usize = 1024;
+
+/// A4 in PDF points.
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 48.0;
+const CHARSET_CELL: f32 = 36.0;
+const CHARSET_SIZE: f32 = 18.0;
+
+/// Sample sentences shown for each supported language. Most are pangrams; the
+/// scripts without a well-known one get a classic sample line instead.
+static PANGRAMS: &[(&str, &str)] = &[
+  ("en", "The quick brown fox jumps over the lazy dog"),
+  ("de", "Victor jagt zwölf Boxkämpfer quer über den großen Sylter Deich"),
+  ("fr", "Portez ce vieux whisky au juge blond qui fume"),
+  ("es", "El veloz murciélago hindú comía feliz cardillo y kiwi"),
+  ("it", "Quel vituperabile xenofobo zelante assaggia il whisky ed esclama: alleluja!"),
+  ("pt", "Luís argüia à Júlia que «brações, fé, chá, óxido, pôr, zângão» eram palavras do português"),
+  ("nl", "Pa's wijze lynx bezag vroom het fikse aquaduct"),
+  ("ca", "Jove xef, porti whisky amb quinze glaçons d'hidrogen, coi!"),
+  ("pl", "Pchnąć w tę łódź jeża lub ośm skrzyń fig"),
+  ("cs", "Příliš žluťoučký kůň úpěl ďábelské ódy"),
+  ("sk", "Kŕdeľ šťastných ďatľov učí pri ústí Váhu mĺkveho koňa obhrýzať kôru"),
+  ("sl", "Šerif bo za vajo spet kuhal domače žgance"),
+  ("hr", "Gojazni đačić s biciklom drži hmelj i finu vatu u džepu nošnje"),
+  ("hu", "Árvíztűrő tükörfúrógép"),
+  ("ro", "Muzicologă în bej vând whisky și tequila, preț fix"),
+  ("tr", "Pijamalı hasta yağız şoföre çabucak güvendi"),
+  ("sv", "Flygande bäckasiner söka hwila på mjuka tuvor"),
+  ("da", "Høj bly gom vandt fræk sexquiz på wc"),
+  ("nb", "Vår sære Zulu fra badeøya spilte jo whist og quickstep i min taxi"),
+  ("fi", "Törkylempijävongahdus"),
+  ("is", "Kæmi ný öxi hér, ykist þjófum nú bæði víl og ádrepa"),
+  ("et", "Põdur Zagrebi tšellomängija-följetonist Ciqo külmetas kehvas garaažis"),
+  ("lv", "Glāžšķūņa rūķīši dzērumā čiepj Baha koncertflīģeļu vākus"),
+  ("lt", "Įlinkdama fechtuotojo špaga sublykčiojusi pragręžė apvalų arbūzą"),
+  ("ga", "D'fhuascail Íosa Úrmhac na hÓighe Beannaithe pór Éava agus Ádhaimh"),
+  ("cy", "Parciais fy jac codi baw hud llawn dŵr ger tŷ Mabon"),
+  ("id", "Muharjo seorang xenofobia universal yang takut pada warga jazirah, contohnya Qatar"),
+  ("vi", "Trăm năm trong cõi người ta, chữ tài chữ mệnh khéo là ghét nhau"),
+  ("ru", "Съешь же ещё этих мягких французских булок, да выпей чаю"),
+  ("uk", "Чуєш їх, доцю, га? Кумедна ж ти, прощайся без ґольфів!"),
+  ("be", "У рудога вераб'я ў сховішчы пад фатэлем ляжаць нейкія гаючыя зёлкі"),
+  ("bg", "Жълтата дюля беше щастлива, че пухът, който цъфна, замръзна като гьон"),
+  ("sr", "Љубазни фењерџија чађавог лица хоће да ми покаже штос"),
+  ("mk", "Ѕидарски пејзаж: шугав билмез со чудење џвака ќофте и кељ на туѓ цех"),
+  ("el", "Ξεσκεπάζω την ψυχοφθόρα βδελυγμία"),
+  ("hy", "Բել դղյակի ձախ ժամն օֆ ազգությանը ցպահանջ չճշտած վնաս էր եւ փառք"),
+  ("he", "דג סקרן שט בים מאוכזב ולפתע מצא חברה"),
+  ("ar", "نص حكيم له سر قاطع وذو شأن عظيم مكتوب على ثوب أخضر ومغلف بجلد أزرق"),
+  ("th", "เป็นมนุษย์สุดประเสริฐเลิศคุณค่า"),
+  ("hi", "ऋषियों को सताने वाले दुष्ट राक्षसों के राजा रावण का सर्वनाश करने वाले विष्णुवतार भगवान श्रीराम"),
+  ("ja", "いろはにほへと ちりぬるを わかよたれそ つねならむ"),
+  ("ko", "키스의 고유조건은 입술끼리 만나야 하고 특별한 기술은 필요치 않다"),
+  ("zh", "天地玄黄，宇宙洪荒。日月盈昃，辰宿列张。"),
+  ("zh-Hant", "天地玄黃，宇宙洪荒。日月盈昃，辰宿列張。"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpecimenFormat {
+  Html,
+  Pdf,
+}
+
+impl SpecimenFormat {
+  const ALL: [SpecimenFormat; 2] = [SpecimenFormat::Html, SpecimenFormat::Pdf];
+
+  fn extension(&self) -> &'static str {
+    match self {
+      SpecimenFormat::Html => "html",
+      SpecimenFormat::Pdf => "pdf",
+    }
+  }
+
+  fn content_type(&self) -> &'static str {
+    match self {
+      SpecimenFormat::Html => "text/html; charset=utf-8",
+      SpecimenFormat::Pdf => "application/pdf",
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub struct SpecimenParams {
+  format: Option<String>,
+}
+
+/// One style of the family with its decoded sfnt data. Collection members
+/// share the whole collection and select themselves through `face_index`.
+struct SpecimenFace {
+  record: FontRecord,
+  sfnt: Arc<[u8]>,
+}
+
+impl SpecimenFace {
+  fn face(&self) -> Result<Face<'_>, String> {
+    Face::parse(&self.sfnt, self.record.face_index as u32).map_err(|e| {
+      format!("Error parsing font data for {}: {:?}", self.record.object_path, e)
+    })
+  }
+
+  fn display_name(&self) -> String {
+    format!("{} {}", self.record.metadata.font_family, self.record.metadata.font_subfamily)
+  }
+
+  /// Printable characters the face maps, up to `MAX_CHARSET`.
+  fn charset(&self) -> Vec<char> {
+    self.record.metadata.unicode_ranges
+      .iter()
+      .flat_map(|&(start, end)| start..=end)
+      .filter_map(char::from_u32)
+      .filter(|ch| !ch.is_control() && !ch.is_whitespace())
+      .take(MAX_CHARSET)
+      .collect()
+  }
+
+  fn pangrams(&self) -> Vec<(&'static str, &'static str)> {
+    let languages = &self.record.metadata.language_support.languages;
+    let pangrams: Vec<_> = PANGRAMS.iter()
+      .filter(|(tag, _)| languages.iter().any(|language| language == tag))
+      .copied()
+      .collect();
+
+    if pangrams.is_empty() { vec![("en", DEFAULT_TEXT)] } else { pangrams }
+  }
+
+  fn sample_text(&self) -> &str {
+    match self.record.metadata.sample_text.as_str() {
+      "" => DEFAULT_TEXT,
+      text => text,
+    }
+  }
+}
+
+/// Family-level metadata, taken from the first style that provides each value.
+fn family_metadata(faces: &[SpecimenFace]) -> Vec<(&'static str, String)> {
+  let first = |value: fn(&FontRecord) -> &str| {
+    faces
+      .iter()
+      .map(|face| value(&face.record).trim())
+      .find(|value| !value.is_empty())
+      .map(str::to_string)
+  };
+
+  [
+    ("Designer", first(|record| &record.metadata.font_designer)),
+    ("Foundry", first(|record| &record.metadata.font_foundry)),
+    ("License", first(|record| &record.metadata.font_license)),
+    ("License URL", first(|record| &record.metadata.license_url)),
+    ("Copyright", first(|record| &record.metadata.font_copyright)),
+    ("Version", first(|record| &record.metadata.version_string)),
+  ]
+    .into_iter()
+    .filter_map(|(label, value)| value.map(|value| (label, value)))
+    .collect()
+}
+
+fn html_escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for ch in text.chars() {
+    match ch {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      _ => escaped.push(ch),
+    }
+  }
+  escaped
+}
+
+/// Each style is embedded as WOFF2 under its own CSS family name, so the page
+/// renders the exact face regardless of how its weight or style are declared.
+fn render_html(family: &str, faces: &[SpecimenFace]) -> Result<Vec<u8>, String> {
+  let family = html_escape(family);
+  let mut html = String::from("<!DOCTYPE html>\n<html lang=\"en\">\n");
+  html.push_str("<head>\n<meta charset=\"utf-8\">\n");
+  let _ = writeln!(html, "<title>{} Specimen</title>\n<style>", family);
+  html.push_str(
+    "body{margin:0 auto;max-width:960px;padding:48px;font-family:sans-serif;color:#111}\
+     dl{display:grid;grid-template-columns:max-content 1fr;gap:4px 16px;color:#555}\
+     dd{margin:0}section{margin-top:64px;border-top:1px solid #ddd}\
+     .label{font:11px sans-serif;color:#888;margin-top:16px}\
+     .line{white-space:nowrap;overflow:hidden;text-overflow:ellipsis}\
+     .charset{display:flex;flex-wrap:wrap}\
+     .charset span{width:48px;height:48px;line-height:48px;text-align:center;font-size:24px;\
+     border:1px solid #eee;margin:-1px 0 0 -1px}\n"
+  );
+
+  for (index, face) in faces.iter().enumerate() {
+    let (flavor, tables) = read_tables(&face.sfnt, face.record.face_index as u32).map_err(|e| {
+      format!("Cannot read {}: {}", face.record.object_path, e)
+    })?;
+    let woff2 = encode_woff2(&build_sfnt(flavor, tables)).map_err(|e| e.to_string())?;
+    let _ = writeln!(
+      html,
+      "@font-face{{font-family:\"specimen-{}\";src:url(data:font/woff2;base64,{})}}",
+      index,
+      BASE64.encode(woff2)
+    );
+  }
+  html.push_str("</style>\n</head>\n<body>\n");
+
+  let _ = writeln!(html, "<h1>{}</h1>", family);
+  html.push_str("<dl>\n");
+  for (label, value) in family_metadata(faces) {
+    let _ = writeln!(html, "<dt>{}</dt><dd>{}</dd>", label, html_escape(&value));
+  }
+  let styles: Vec<String> = faces
+    .iter()
+    .map(|face| html_escape(&face.record.metadata.font_subfamily))
+    .collect();
+  let _ = writeln!(html, "<dt>Styles</dt><dd>{}</dd>", styles.join(", "));
+  html.push_str("</dl>\n");
+
+  for (index, face) in faces.iter().enumerate() {
+    let metadata = &face.record.metadata;
+    let _ = writeln!(html, "<section style=\"font-family:'specimen-{}',sans-serif\">", index);
+    let _ = writeln!(
+      html,
+      "<h2 style=\"font-family:sans-serif\">{}</h2>",
+      html_escape(&face.display_name())
+    );
+    let _ = writeln!(
+      html,
+      "<div class=\"label\">{} · {} · weight {}</div>",
+      html_escape(&metadata.postscript_name),
+      html_escape(&metadata.version_string),
+      metadata.classification.weight_class
+    );
+
+    html.push_str("<h3 class=\"label\">Waterfall</h3>\n");
+    let sample = html_escape(face.sample_text());
+    for size in WATERFALL_SIZES {
+      let _ = writeln!(
+        html,
+        "<div class=\"label\">{}px</div><div class=\"line\" style=\"font-size:{}px\">{}</div>",
+        size,
+        size,
+        sample
+      );
+    }
+
+    html.push_str("<h3 class=\"label\">Languages</h3>\n");
+    for (tag, text) in face.pangrams() {
+      let _ = writeln!(
+        html,
+        "<div class=\"label\">{}</div><p lang=\"{}\" dir=\"auto\" style=\"font-size:{}px\">{}</p>",
+        tag,
+        tag,
+        PANGRAM_SIZE,
+        html_escape(text)
+      );
+    }
+
+    let charset = face.charset();
+    let _ = writeln!(html, "<h3 class=\"label\">Character set ({} shown)</h3>", charset.len());
+    html.push_str("<div class=\"charset\">");
+    for ch in charset {
+      let _ = write!(
+        html,
+        "<span title=\"U+{:04X}\">{}</span>",
+        ch as u32,
+        html_escape(&ch.to_string())
+      );
+    }
+    html.push_str("</div>\n</section>\n");
+  }
+
+  html.push_str("</body>\n</html>\n");
+  Ok(html.into_bytes())
+}
+
+/// Writes glyph outlines as PDF path operators, converting quadratic curves to
+/// cubic ones since PDF has no quadratic segment.
+struct PdfOutline<'a> {
+  content: &'a mut Content,
+  scale: f32,
+  x: f32,
+  y: f32,
+  current: (f32, f32),
+}
+
+impl PdfOutline<'_> {
+  /// Rounded to a hundredth of a point, which is far below print resolution
+  /// and keeps the content streams compact.
+  fn point(&self, x: f32, y: f32) -> (f32, f32) {
+    let round = |value: f32| (value * 100.0).round() / 100.0;
+    (round(self.x + x * self.scale), round(self.y + y * self.scale))
+  }
+}
+
+impl OutlineBuilder for PdfOutline<'_> {
+  fn move_to(&mut self, x: f32, y: f32) {
+    let (x, y) = self.point(x, y);
+    self.content.move_to(x, y);
+    self.current = (x, y);
+  }
+
+  fn line_to(&mut self, x: f32, y: f32) {
+    let (x, y) = self.point(x, y);
+    self.content.line_to(x, y);
+    self.current = (x, y);
+  }
+
+  fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+    let (x1, y1) = self.point(x1, y1);
+    let (x, y) = self.point(x, y);
+    let (x0, y0) = self.current;
+    self.content.cubic_to(
+      x0 + (2.0 / 3.0) * (x1 - x0),
+      y0 + (2.0 / 3.0) * (y1 - y0),
+      x + (2.0 / 3.0) * (x1 - x),
+      y + (2.0 / 3.0) * (y1 - y),
+      x,
+      y
+    );
+    self.current = (x, y);
+  }
+
+  fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+    let (x1, y1) = self.point(x1, y1);
+    let (x2, y2) = self.point(x2, y2);
+    let (x, y) = self.point(x, y);
+    self.content.cubic_to(x1, y1, x2, y2, x, y);
+    self.current = (x, y);
+  }
+
+  fn close(&mut self) {
+    self.content.close_path();
+  }
+}
+
+fn advance(face: &Face, ch: char, scale: f32) -> f32 {
+  let glyph_id = face.glyph_index(ch).unwrap_or(GlyphId(0));
+  f32::from(face.glyph_hor_advance(glyph_id).unwrap_or(0)) * scale
+}
+
+/// Splits text into lines no wider than `max_width`, breaking at the last
+/// space when there is one and between characters otherwise.
+fn wrap_text(face: &Face, text: &str, size: f32, max_width: f32) -> Vec<String> {
+  let scale = size / f32::from(face.units_per_em());
+  let mut lines = Vec::new();
+  let mut line: Vec<char> = Vec::new();
+  let mut width = 0.0;
+
+  for ch in text.chars() {
+    let ch_width = advance(face, ch, scale);
+    if width + ch_width > max_width && !line.is_empty() {
+      let rest = match line.iter().rposition(|c| c.is_whitespace()) {
+        Some(space) => line.split_off(space + 1),
+        None => Vec::new(),
+      };
+      lines.push(line.iter().collect::<String>().trim_end().to_string());
+      line = rest;
+      width = line.iter().map(|&c| advance(face, c, scale)).sum();
+    }
+    line.push(ch);
+    width += ch_width;
+  }
+  if !line.is_empty() {
+    lines.push(line.into_iter().collect());
+  }
+
+  lines
+}
+
+/// Maps text onto the WinAnsi encoding of the built-in Helvetica, replacing
+/// anything outside Latin-1 with `?`.
+fn win_ansi(text: &str) -> Vec<u8> {
+  text
+    .chars()
+    .map(|ch| {
+      match ch as u32 {
+        0x20..=0x7e | 0xa0..=0xff => ch as u8,
+        _ => b'?',
+      }
+    })
+    .collect()
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+  let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+  let _ = encoder.write_all(data);
+  encoder.finish().unwrap_or_default()
+}
+
+/// Lays out the PDF top to bottom, starting a new page whenever the next block
+/// does not fit.
+struct PdfSpecimen {
+  pages: Vec<Content>,
+  y: f32,
+}
+
+impl PdfSpecimen {
+  const LABEL_FONT: Name<'static> = Name(b"F1");
+
+  fn new() -> Self {
+    PdfSpecimen { pages: Vec::new(), y: 0.0 }
+  }
+
+  fn new_page(&mut self) {
+    self.pages.push(Content::new());
+    self.y = PAGE_HEIGHT - MARGIN;
+  }
+
+  fn reserve(&mut self, height: f32) {
+    if self.pages.is_empty() || self.y - height < MARGIN {
+      self.new_page();
+    }
+  }
+
+  fn content(&mut self) -> &mut Content {
+    self.pages.last_mut().expect("a page is started before drawing")
+  }
+
+  /// Helvetica text for headings and labels. Long values are wrapped using an
+  /// average character width since the built-in font has no metrics here.
+  fn label(&mut self, text: &str, size: f32, gray: f32) {
+    let max_chars = ((PAGE_WIDTH - 2.0 * MARGIN) / (size * 0.5)) as usize;
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+      match lines.last_mut() {
+        Some(line) if line.chars().count() + word.chars().count() < max_chars => {
+          line.push(' ');
+          line.push_str(word);
+        }
+        _ => lines.push(word.to_string()),
+      }
+    }
+
+    for line in lines {
+      self.reserve(size * 1.4);
+      self.y -= size * 1.2;
+      let y = self.y;
+      self
+        .content()
+        .set_fill_gray(gray)
+        .begin_text()
+        .set_font(Self::LABEL_FONT, size)
+        .next_line(MARGIN, y)
+        .show(Str(&win_ansi(&line)))
+        .end_text();
+      self.y -= size * 0.2;
+    }
+  }
+
+  /// Draws one line of glyph outlines at the current baseline, dropping glyphs
+  /// that would run past the right margin.
+  fn glyph_line(&mut self, face: &Face, text: &str, size: f32, x: f32) {
+    let scale = size / f32::from(face.units_per_em());
+    let y = self.y;
+    let content = self.content();
+    content.set_fill_gray(0.0);
+
+    let mut outline = PdfOutline { content, scale, x, y, current: (0.0, 0.0) };
+    for ch in text.chars() {
+      let glyph_id = face.glyph_index(ch).unwrap_or(GlyphId(0));
+      let width = f32::from(face.glyph_hor_advance(glyph_id).unwrap_or(0)) * scale;
+      if outline.x + width > PAGE_WIDTH - MARGIN {
+        break;
+      }
+      if face.outline_glyph(glyph_id, &mut outline).is_some() {
+        outline.content.fill_nonzero();
+      }
+      outline.x += width;
+    }
+  }
+
+  fn text_block(&mut self, face: &Face, text: &str, size: f32) {
+    let ascender = f32::from(face.ascender()) / f32::from(face.units_per_em()) * size;
+    let line_height = size * 1.3;
+    for line in wrap_text(face, text, size, PAGE_WIDTH - 2.0 * MARGIN) {
+      self.reserve(line_height);
+      self.y -= ascender.max(size * 0.8);
+      self.glyph_line(face, &line, size, MARGIN);
+      self.y -= line_height - ascender.max(size * 0.8);
+    }
+  }
+
+  fn charset(&mut self, face: &Face, charset: &[char]) {
+    let columns = ((PAGE_WIDTH - 2.0 * MARGIN) / CHARSET_CELL) as usize;
+    let scale = CHARSET_SIZE / f32::from(face.units_per_em());
+
+    for row in charset.chunks(columns.max(1)) {
+      self.reserve(CHARSET_CELL);
+      let baseline = self.y - CHARSET_CELL * 0.6;
+      for (column, &ch) in row.iter().enumerate() {
+        let cell_x = MARGIN + (column as f32) * CHARSET_CELL;
+        let x = cell_x + (CHARSET_CELL - advance(face, ch, scale)) / 2.0;
+        self.y = baseline;
+        self.glyph_line(face, &ch.to_string(), CHARSET_SIZE, x);
+
+        let code = format!("{:04X}", ch as u32);
+        self
+          .content()
+          .set_fill_gray(0.55)
+          .begin_text()
+          .set_font(Self::LABEL_FONT, 5.0)
+          .next_line(cell_x + 2.0, baseline - CHARSET_CELL * 0.3)
+          .show(Str(code.as_bytes()))
+          .end_text();
+      }
+      self.y = baseline - CHARSET_CELL * 0.4;
+    }
+  }
+
+  fn finish(self, title: &str) -> Vec<u8> {
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let font_id = Ref::new(3);
+    let info_id = Ref::new(4);
+    let page_ids: Vec<(Ref, Ref)> = (0..self.pages.len() as i32)
+      .map(|index| (Ref::new(5 + index * 2), Ref::new(6 + index * 2)))
+      .collect();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.document_info(info_id).title(TextStr(title)).producer(TextStr("font-app-server"));
+    pdf
+      .pages(page_tree_id)
+      .kids(page_ids.iter().map(|&(page_id, _)| page_id))
+      .count(page_ids.len() as i32);
+    pdf
+      .type1_font(font_id)
+      .base_font(Name(b"Helvetica"))
+      .encoding_predefined(Name(b"WinAnsiEncoding"));
+
+    for (content, &(page_id, content_id)) in self.pages.into_iter().zip(&page_ids) {
+      {
+        let mut page = pdf.page(page_id);
+        page
+          .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+          .parent(page_tree_id)
+          .contents(content_id);
+        page.resources().fonts().pair(Self::LABEL_FONT, font_id);
+      }
+      let compressed = deflate(&content.finish());
+      pdf.stream(content_id, &compressed).filter(Filter::FlateDecode);
+    }
+
+    pdf.finish()
+  }
+}
+
+/// Glyphs are drawn as filled outlines rather than embedded fonts, so text is
+/// not selectable and, as with previews, there is no shaping.
+fn render_pdf(family: &str, faces: &[SpecimenFace]) -> Result<Vec<u8>, String> {
+  let mut pdf = PdfSpecimen::new();
+
+  pdf.new_page();
+  pdf.label(family, 28.0, 0.0);
+  pdf.y -= 12.0;
+  for (label, value) in family_metadata(faces) {
+    pdf.label(&format!("{}: {}", label, value), 9.0, 0.35);
+  }
+  pdf.y -= 12.0;
+  pdf.label("Styles", 12.0, 0.0);
+  for face in faces {
+    let parsed = face.face()?;
+    pdf.text_block(&parsed, &face.display_name(), 24.0);
+  }
+
+  for face in faces {
+    let parsed = face.face()?;
+    let metadata = &face.record.metadata;
+
+    pdf.new_page();
+    pdf.label(&face.display_name(), 20.0, 0.0);
+    pdf.label(
+      &format!(
+        "{} - {} - weight {}",
+        metadata.postscript_name,
+        metadata.version_string,
+        metadata.classification.weight_class
+      ),
+      8.0,
+      0.45
+    );
+
+    pdf.y -= 12.0;
+    pdf.label("Waterfall", 10.0, 0.45);
+    for size in WATERFALL_SIZES {
+      pdf.label(&format!("{} pt", size), 6.0, 0.55);
+      pdf.reserve(size * 1.2);
+      pdf.y -= size;
+      pdf.glyph_line(&parsed, face.sample_text(), size, MARGIN);
+      pdf.y -= size * 0.3;
+    }
+
+    pdf.y -= 12.0;
+    pdf.label("Languages", 10.0, 0.45);
+    for (tag, text) in face.pangrams() {
+      pdf.label(tag, 6.0, 0.55);
+      pdf.text_block(&parsed, text, PANGRAM_SIZE);
+    }
+
+    let charset = face.charset();
+    pdf.y -= 12.0;
+    pdf.label(&format!("Character set ({} shown)", charset.len()), 10.0, 0.45);
+    pdf.charset(&parsed, &charset);
+  }
+
+  Ok(pdf.finish(&format!("{} Specimen", family)))
+}
+
+/// Orders styles the way a type specimen reads: by width, weight, then
+/// upright before italic.
+fn sort_faces(faces: &mut [SpecimenFace]) {
+  faces.sort_by(|a, b| {
+    let key = |face: &SpecimenFace| {
+      let classification = &face.record.metadata.classification;
+      (
+        classification.width_class,
+        classification.weight_class,
+        classification.is_italic || classification.is_oblique,
+      )
+    };
+    key(a).cmp(&key(b))
+  });
+}
+
+fn specimen_prefix(user_id: &Uuid, family: &str) -> String {
+  let family_digest = blake3::hash(family.to_lowercase().as_bytes());
+  format!("{}/specimens/{}/{}/", DERIVED_PREFIX, user_id, &family_digest.to_hex()[..32])
+}
+
+/// Digest over every style in the family, so adding, replacing or removing a
+/// style changes the cache key. Keys of one family share a prefix, so older
+/// renderings can be found and removed.
+fn specimen_key(
+  user_id: &Uuid,
+  family: &str,
+  records: &[FontRecord],
+  format: SpecimenFormat
+) -> String {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update(family.to_lowercase().as_bytes());
+  for record in records {
+    hasher.update(
+      format!(
+        "\u{0}{}\u{0}{}\u{0}{}",
+        record.object_path,
+        record.face_index,
+        record.metadata.checksum
+      ).as_bytes()
+    );
+  }
+
+  format!(
+    "{}{}.{}",
+    specimen_prefix(user_id, family),
+    &hasher.finalize().to_hex()[..32],
+    format.extension()
+  )
+}
+
+async fn load_faces(
+  state: &AppState,
+  records: Vec<FontRecord>
+) -> Result<Vec<SpecimenFace>, String> {
+  let mut files: HashMap<String, Arc<[u8]>> = HashMap::new();
+  let mut faces = Vec::with_capacity(records.len());
+
+  for record in records {
+    if !files.contains_key(&record.object_path) {
+      let data = fetch_stored_file(state, &record.object_path, &record.metadata.checksum).await
+        .map_err(|e| format!("Unable to retrieve {}: {}", record.object_path, e))?
+        .ok_or_else(|| format!("File '{}' does not exist", record.object_path))?;
+      let (_, sfnt) = decode_font(&data).map_err(|e| {
+        format!("Cannot read {}: {}", record.object_path, e)
+      })?;
+      files.insert(record.object_path.clone(), sfnt.into());
+    }
+
+    let sfnt = Arc::clone(&files[&record.object_path]);
+    faces.push(SpecimenFace { record, sfnt });
+  }
+
+  sort_faces(&mut faces);
+  Ok(faces)
+}
+
+async fn render_specimen(
+  family: &str,
+  faces: Arc<Vec<SpecimenFace>>,
+  format: SpecimenFormat
+) -> Result<Vec<u8>, String> {
+  let family = family.to_string();
+
+  tokio::task
+    ::spawn_blocking(move || {
+      match format {
+        SpecimenFormat::Html => render_html(&family, &faces),
+        SpecimenFormat::Pdf => render_pdf(&family, &faces),
+      }
+    }).await
+    .map_err(|e| format!("Specimen task failed: {}", e))?
+}
+
+/// Deletes cached specimens of a family other than `keep`.
+async fn prune_specimens(state: &AppState, user_id: &Uuid, family: &str, keep: &[String]) {
+  let prefix = specimen_prefix(user_id, family);
+  let mut pages = state.s3_client
+    .list_objects_v2()
+    .bucket(S3_BUCKET)
+    .prefix(&prefix)
+    .into_paginator()
+    .send();
+
+  while let Some(page) = pages.next().await {
+    let page = match page {
+      Ok(page) => page,
+      Err(e) => {
+        warn!("Failed to list cached specimens under {}: {}", prefix, e);
+        return;
+      }
+    };
+    for key in page.contents().iter().filter_map(|object| object.key()) {
+      if keep.iter().any(|kept| kept == key) {
+        continue;
+      }
+      match state.s3_client.delete_object().bucket(S3_BUCKET).key(key).send().await {
+        Ok(_) => info!("Removed stale specimen {}", key),
+        Err(e) => warn!("Failed to remove stale specimen {}: {}", key, e),
+      }
+    }
+  }
+}
+
+/// Renders and caches both specimen formats for a family, then drops the
+/// renderings of earlier sets of styles.
+async fn regenerate_specimens(state: &AppState, user_id: Uuid, family: &str) {
+  let records = match get_metadata_by_family(&state.db_client, &user_id, family).await {
+    Ok(records) => records,
+    Err(e) => {
+      warn!("Failed to look up family {} for specimen: {}", family, e);
+      return;
+    }
+  };
+  if records.is_empty() {
+    prune_specimens(state, &user_id, family, &[]).await;
+    return;
+  }
+
+  let derived_keys = SpecimenFormat::ALL.map(|format| {
+    specimen_key(&user_id, family, &records, format)
+  });
+  let faces = match load_faces(state, records).await {
+    Ok(faces) => Arc::new(faces),
+    Err(e) => {
+      warn!("Failed to load family {} for specimen: {}", family, e);
+      return;
+    }
+  };
+
+  for (format, derived_key) in SpecimenFormat::ALL.into_iter().zip(&derived_keys) {
+    match render_specimen(family, faces.clone(), format).await {
+      Ok(specimen) => {
+        cache_derived_object(state, derived_key, format.content_type(), specimen).await;
+        info!("Regenerated {} specimen for family {}", format.extension(), family);
+      }
+      Err(e) => warn!("Failed to regenerate specimen for family {}: {}", family, e),
+    }
+  }
+
+  prune_specimens(state, &user_id, family, &derived_keys).await;
+}
+
+/// Regenerates a family's specimens after its styles changed. Requests for a
+/// family that is already waiting are folded into that run; changes made
+/// while it renders schedule another one.
+pub async fn schedule_specimens(state: AppState, user_id: Uuid, family: String) {
+  let job = (user_id, family.to_lowercase());
+  if !state.specimen_jobs.lock().await.insert(job.clone()) {
+    return;
+  }
+
+  tokio::time::sleep(REGENERATE_DELAY).await;
+  state.specimen_jobs.lock().await.remove(&job);
+  regenerate_specimens(&state, user_id, &family).await;
+}
+
+/// Serves `GET /specimens/{family}?format=html|pdf` as a download, rendering
+/// it on demand when the current set of styles has no cached specimen yet.
+pub async fn specimen_handler(
+  user: AuthUser,
+  Path(family): Path<String>,
+  Query(params): Query<SpecimenParams>,
+  State(state): State<AppState>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+  let format = match params.format.as_deref() {
+    None | Some("html") => SpecimenFormat::Html,
+    Some("pdf") => SpecimenFormat::Pdf,
+    Some(other) => {
+      return Err((StatusCode::BAD_REQUEST, format!("Unsupported specimen format '{}'", other)));
+    }
+  };
+
+  let records = get_metadata_by_family(&state.db_client, &user.user_id, &family).await.map_err(|e| {
+    error!("Failed to fetch family {}: {}", family, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up font metadata".to_string())
+  })?;
+  if records.is_empty() {
+    return Err((StatusCode::NOT_FOUND, format!("Family '{}' does not exist", family)));
+  }
+
+  let derived_key = specimen_key(&user.user_id, &family, &records, format);
+  let cached = fetch_object(&state, &derived_key).await.map_err(|e| {
+    error!("Failed to read cached specimen {}: {}", derived_key, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Unable to retrieve specimen".to_string())
+  })?;
+  let specimen = match cached {
+    Some(specimen) => specimen,
+    None => {
+      info!("User {} - Client {} rendering specimen of {}", user.email, user.client_id, family);
+      let faces = load_faces(&state, records).await.map_err(|e| {
+        error!("Failed to load family {} for specimen: {}", family, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Unable to retrieve font files".to_string())
+      })?;
+      let specimen = render_specimen(&family, Arc::new(faces), format).await.map_err(|e| {
+        error!("Failed to render specimen for {}: {}", family, e);
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("Cannot render specimen: {}", e))
+      })?;
+
+      cache_derived_object(&state, &derived_key, format.content_type(), specimen.clone()).await;
+      specimen
+    }
+  };
+
+  let file_name: String = family
+    .chars()
+    .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' { ch } else { '_' })
+    .collect();
+  let disposition = format!(
+    "attachment; filename=\"{}-specimen.{}\"",
+    file_name,
+    format.extension()
+  );
+
+  Ok((
+    StatusCode::OK,
+    [
+      (header::CONTENT_TYPE, format.content_type().to_string()),
+      (header::CONTENT_DISPOSITION, disposition),
+    ],
+    specimen,
+  ))
+}
@@ -0,0 +1,74 @@
+//! Spools upload bodies to temporary files so a request only ever holds one
+//! chunk in memory, whatever the size of the font.
+
+use std::{ fmt::Display, io, path::Path, pin::pin };
+
+use axum::{ body::Bytes, extract::multipart::Field, http::StatusCode };
+use futures_util::{ Stream, StreamExt };
+use memmap2::Mmap;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+
+/// An upload written to disk. The file is removed when this is dropped.
+pub struct SpooledFile {
+  file: NamedTempFile,
+  pub len: u64,
+  /// blake3 of the bytes as received, computed while spooling.
+  pub checksum: String,
+}
+
+impl SpooledFile {
+  pub fn path(&self) -> &Path {
+    self.file.path()
+  }
+
+  /// Maps the spooled bytes read-only so the parsers can treat them as a slice
+  /// while the kernel pages them in on demand.
+  pub fn map(&self) -> io::Result<Mmap> {
+    // SAFETY: the temp file is private to this request and is never written
+    // again once spooling has finished.
+    unsafe { Mmap::map(self.file.as_file()) }
+  }
+}
+
+/// Streams a multipart field into a temp file, hashing it on the way.
+pub async fn spool_field(field: Field<'_>) -> Result<SpooledFile, (StatusCode, String)> {
+  spool_stream(field, StatusCode::BAD_REQUEST).await
+}
+
+/// Streams any chunked body into a temp file. Errors from the source itself
+/// are reported with `read_status`.
+pub async fn spool_stream<S, E>(
+  stream: S,
+  read_status: StatusCode
+) -> Result<SpooledFile, (StatusCode, String)>
+  where S: Stream<Item = Result<Bytes, E>>, E: Display
+{
+  let mut stream = pin!(stream);
+  let spool_error = |e: io::Error| {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to spool upload: {}", e))
+  };
+
+  let file = tokio::task
+    ::spawn_blocking(NamedTempFile::new).await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Spool task failed: {}", e)))?
+    .map_err(spool_error)?;
+  let writer = file.reopen().map_err(spool_error)?;
+  let mut writer = tokio::fs::File::from_std(writer);
+  let mut hasher = blake3::Hasher::new();
+  let mut len = 0u64;
+
+  while
+    let Some(chunk) = stream
+      .next().await
+      .transpose()
+      .map_err(|e| (read_status, format!("Failed to read file data: {}", e)))?
+  {
+    hasher.update(&chunk);
+    len += chunk.len() as u64;
+    writer.write_all(&chunk).await.map_err(spool_error)?;
+  }
+  writer.flush().await.map_err(spool_error)?;
+
+  Ok(SpooledFile { file, len, checksum: hasher.finalize().to_string() })
+}
@@ -1,20 +1,71 @@
-use std::{ env, path::PathBuf, str::FromStr };
+use std::{ collections::BTreeSet, env, path::PathBuf, str::FromStr };
 use aws_config::Region;
-use aws_sdk_s3::{ config::Credentials, primitives::ByteStream };
-use axum::{ body::Body, extract::{ Path, State }, http::StatusCode, response::IntoResponse };
+use aws_sdk_s3::{
+  config::{ http::HttpResponse, Credentials },
+  error::SdkError,
+  primitives::{ ByteStream, ByteStreamError, Length },
+  types::{ CompletedMultipartUpload, CompletedPart },
+};
+use axum::{
+  body::Body,
+  extract::{ Path, Query, State },
+  http::{ header, HeaderMap, HeaderValue, Method, StatusCode },
+  response::{ IntoResponse, Response },
+};
 use log::{ error, info, warn };
 use axum::{ extract::Multipart };
+use serde::{ Deserialize, Serialize };
 use tokio_util::io::ReaderStream;
+use uuid::Uuid;
 
 use crate::{
   app_state::AppState,
   auth::AuthUser,
-  database::{ check_duplicate, delete_metadata, get_metadata, insert_metadata, FontRecord },
-  metadata::extract_metadata,
+  blobs::{ blob_key, blob_written, fetch_stored_file, head_stored_file, reserve_blob },
+  conditional::{
+    checksum_etag,
+    http_date,
+    is_not_modified,
+    parse_range,
+    range_applies,
+    RangeRequest,
+  },
+  conflicts::{
+    describe_conflicts,
+    parse_conflict_policy,
+    ConflictPolicy,
+    ConflictReport,
+    FontConflict,
+  },
+  database::{
+    check_duplicate,
+    delete_metadata,
+    find_postscript_conflicts,
+    get_metadata,
+    get_metadata_by_path,
+    insert_metadata,
+    mark_blob_unstored,
+    FontFilter,
+    FontRecord,
+  },
+  container::{ convert_font, FontFormat },
+  metadata::{ extract_decoded_metadata, FontMetadata },
+  specimen::schedule_specimens,
+  spool::{ spool_field, spool_stream, SpooledFile },
   sync_engine::{ SyncMessage, SyncSource },
+  validation::{ validate_font, ValidationReport },
 };
 
-static S3_BUCKET: &str = "fonts";
+pub static S3_BUCKET: &str = "fonts";
+pub static DERIVED_PREFIX: &str = "derived";
+static QUARANTINE_PREFIX: &str = "quarantine";
+/// S3 requires every part but the last to be at least 5 MiB.
+pub const UPLOAD_PART_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct DownloadParams {
+  format: Option<String>,
+}
 
 pub async fn connect_s3() -> Result<aws_sdk_s3::Client, Box<dyn std::error::Error>> {
   info!("Connecting to S3 storage...");
@@ -41,15 +92,20 @@ pub async fn connect_s3() -> Result<aws_sdk_s3::Client, Box<dyn std::error::Erro
   Ok(client)
 }
 
+/// Stores the files of a multipart upload. `path` and `on_conflict` apply to
+/// the file fields after them, so an `on_conflict` sent after a file is
+/// refused rather than silently ignored for that file.
 pub async fn upload_font(
   user: AuthUser,
   State(state): State<AppState>,
   mut multipart: Multipart
-) -> impl IntoResponse {
+) -> Result<Response, UploadError> {
   let mut file_name = String::new();
   let mut font_records = Vec::new();
+  let mut stored = Vec::new();
   let mut relative_path = None;
-  let mut user_key: String = "".to_string();
+  let mut conflict_policy = ConflictPolicy::default();
+  let mut conflicts = Vec::new();
 
   while
     let Some(field) = multipart
@@ -65,6 +121,25 @@ pub async fn upload_font(
       continue;
     }
 
+    if matches!(field.name(), Some("on_conflict")) {
+      if !file_name.is_empty() {
+        return Err(
+          (
+            StatusCode::BAD_REQUEST,
+            "The on_conflict field must come before the file field".to_string(),
+          ).into()
+        );
+      }
+      let policy_value = field
+        .text().await
+        .map_err(|e| {
+          (StatusCode::BAD_REQUEST, format!("Failed to read on_conflict text: {}", e))
+        })?;
+
+      conflict_policy = parse_conflict_policy(&policy_value)?;
+      continue;
+    }
+
     file_name = field
       .file_name()
       .ok_or((StatusCode::BAD_REQUEST, "Missing file name".to_string()))?
@@ -75,105 +150,306 @@ pub async fn upload_font(
       .map(|ct| ct.to_string())
       .unwrap_or_else(|| "application/octet-stream".to_string());
 
-    let data = field
-      .bytes().await
-      .map_err(|e| { (StatusCode::BAD_REQUEST, format!("Failed to read file data: {}", e)) })?;
-
-    user_key = format!("{}/{}", user.user_id, relative_path.as_deref().unwrap_or(&file_name));
+    let spooled = spool_field(field).await?;
+    let user_key = format!("{}/{}", user.user_id, relative_path.as_deref().unwrap_or(&file_name));
     println!("{}", user_key);
 
-    let (family, subfamily, checksum) = extract_metadata(&data)
-      .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid font file: {}", e)))?
-      .ok_or((
-        StatusCode::BAD_REQUEST,
-        "Could not extract font metadata from uploaded file".to_string(),
-      ))?;
-
-    info!("Checking for duplicates for user {} and checksum {}", user.email, checksum);
-    if
-      let Some(existing_path) = check_duplicate(
-        &state.db_client,
-        &user.user_id,
-        &checksum,
-        &user_key
-      ).await.map_err(|e| {
-        error!("Error checking for duplicates: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check for duplicate files".to_string())
-      })?
-    {
-      match state.s3_client.head_object().bucket(S3_BUCKET).key(&existing_path).send().await {
-        Ok(_) => {
-          info!(
-            "Duplicate font detected for user {}: {} (checksum: {})",
-            user.email,
-            file_name,
-            checksum
-          );
-          return Ok((StatusCode::OK, format!("Duplicate file: {}", existing_path)));
-        }
-        Err(e) if e.as_service_error().map(|e| e.is_not_found()) == Some(true) => {
-          warn!(
-            "Database entry found but S3 file missing for user {}: {}",
-            user.email,
-            existing_path
-          );
-          delete_metadata(&state.db_client, &user.user_id, &user_key).await.map_err(|e| {
-            error!("Failed to remove broken metadata: {}", e);
-            (
-              StatusCode::INTERNAL_SERVER_ERROR,
-              "Broken database state and failed to clean it up".to_string(),
-            )
-          })?;
-        }
-        Err(e) => {
-          error!("Failed to verify S3 object existence: {}", e);
-          return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Error checking file in storage".to_string(),
-          ));
-        }
+    let checked = check_upload(
+      &state,
+      &user,
+      &user_key,
+      &file_name,
+      &spooled,
+      conflict_policy
+    ).await?;
+    let faces = match checked {
+      CheckedUpload::Duplicate(existing_path) => {
+        return Ok((StatusCode::OK, format!("Duplicate file: {}", existing_path)).into_response());
+      }
+      CheckedUpload::Accepted { faces, conflicts: found } => {
+        conflicts.extend(found);
+        faces
       }
+    };
+
+    info!("Storing {} for key: {} ({} bytes)", spooled.checksum, user_key, spooled.len);
+    if let Some(blob_key) = reserve_blob(&state, &spooled.checksum).await? {
+      put_spooled_object(&state, &blob_key, &content_type, &spooled, &file_name).await?;
+      blob_written(&state, &spooled.checksum).await?;
     }
 
-    info!("Initiating S3 put_object for key: {}", user_key);
-    let body = ByteStream::from(data);
-    if
-      let Err(e) = state.s3_client
-        .put_object()
-        .bucket(S3_BUCKET)
-        .key(&user_key)
-        .content_type(content_type)
-        .body(body)
-        .send().await
-    {
-      if let aws_sdk_s3::error::SdkError::ServiceError(service_err) = &e {
-        if service_err.raw().status().as_u16() == 403 {
-          return Err((
-            StatusCode::FORBIDDEN,
-            "Access denied: insufficient permissions to upload files".to_string(),
-          ));
-        } else if service_err.raw().status().as_u16() == 404 {
-          return Err((StatusCode::NOT_FOUND, "Bucket not found or does not exist".to_string()));
-        }
+    font_records.extend(font_records_for(&user_key, faces));
+    stored.push(StoredUpload { spooled, content_type });
+
+    info!("User {} - Client {} uploaded file: {}", user.email, user.client_id, file_name);
+  }
+
+  let sync_path = relative_path.unwrap_or_else(|| file_name.clone());
+  let upload = FinishedUpload {
+    file_name: &file_name,
+    sync_path: &sync_path,
+    font_records,
+    stored,
+  };
+  Ok(finish_upload(&state, &user, upload, conflict_policy, &conflicts).await?)
+}
+
+pub fn font_records_for(object_path: &str, faces: Vec<FontMetadata>) -> Vec<FontRecord> {
+  faces
+    .into_iter()
+    .enumerate()
+    .map(|(face_index, metadata)| FontRecord {
+      object_path: object_path.to_string(),
+      face_index: face_index as i32,
+      metadata,
+    })
+    .collect()
+}
+
+/// Failure of the upload pipeline. Rejections that come with a report are
+/// sent as JSON, everything else as plain text.
+pub enum UploadError {
+  Message(StatusCode, String),
+  Report(StatusCode, serde_json::Value),
+}
+
+impl UploadError {
+  pub fn status(&self) -> StatusCode {
+    match self {
+      UploadError::Message(status, _) | UploadError::Report(status, _) => *status,
+    }
+  }
+}
+
+impl From<(StatusCode, String)> for UploadError {
+  fn from((status, message): (StatusCode, String)) -> Self {
+    UploadError::Message(status, message)
+  }
+}
+
+impl IntoResponse for UploadError {
+  fn into_response(self) -> Response {
+    match self {
+      UploadError::Message(status, message) => (status, message).into_response(),
+      UploadError::Report(status, report) => (status, axum::Json(report)).into_response(),
+    }
+  }
+}
+
+pub enum CheckedUpload {
+  /// The same bytes are already stored at this path.
+  Duplicate(String),
+  Accepted {
+    faces: Vec<FontMetadata>,
+    /// PostScript name conflicts the policy let through.
+    conflicts: Vec<FontConflict>,
+  },
+}
+
+enum Inspection {
+  Invalid(ValidationReport),
+  Unreadable(String),
+  Readable(Vec<FontMetadata>),
+}
+
+/// Validates and extracts metadata from one decoding of the upload.
+fn inspect_upload(data: &[u8], checksum: &str) -> Inspection {
+  let (report, decoded) = validate_font(data);
+  match decoded {
+    Some((format, sfnt)) if report.valid => {
+      match extract_decoded_metadata(format, &sfnt, checksum) {
+        Ok(faces) => Inspection::Readable(faces),
+        Err(e) => Inspection::Unreadable(e.to_string()),
       }
+    }
+    _ => Inspection::Invalid(report),
+  }
+}
 
-      error!("Failed to upload file '{}' to S3: {:?}", file_name, e);
-      return Err((
-        StatusCode::INTERNAL_SERVER_ERROR,
-        format!("Failed to upload file '{}': Server error", &file_name),
-      ));
+/// Validates a received font and checks it against the user's library before
+/// it is recorded under `user_key`.
+pub async fn check_upload(
+  state: &AppState,
+  user: &AuthUser,
+  user_key: &str,
+  file_name: &str,
+  spooled: &SpooledFile,
+  conflict_policy: ConflictPolicy
+) -> Result<CheckedUpload, UploadError> {
+  let data = spooled.map().map_err(|e| {
+    error!("Failed to map spooled upload {}: {}", file_name, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read uploaded file".to_string())
+  })?;
+
+  let checksum = spooled.checksum.clone();
+  let inspection = tokio::task
+    ::spawn_blocking({
+      let checksum = checksum.clone();
+      move || inspect_upload(&data, &checksum)
+    }).await
+    .map_err(|e| {
+      error!("Inspection task failed for {}: {}", file_name, e);
+      (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read uploaded file".to_string())
+    })?;
+  let faces = match inspection {
+    Inspection::Readable(faces) => faces,
+    Inspection::Unreadable(e) => {
+      return Err((StatusCode::BAD_REQUEST, format!("Invalid font file: {}", e)).into());
     }
+    Inspection::Invalid(report) => {
+      warn!(
+        "Upload {} from user {} failed validation with {} error(s)",
+        file_name,
+        user.email,
+        report.errors
+      );
+      return Err(reject_upload(state, user_key, spooled, &report).await);
+    }
+  };
 
-    font_records.push(FontRecord {
-      font_family: family,
-      font_subfamily: subfamily,
-      object_path: user_key.clone(),
-      checksum,
-    });
+  info!("Checking for duplicates for user {} and checksum {}", user.email, checksum);
+  if
+    let Some(existing_path) = check_duplicate(
+      &state.db_client,
+      &user.user_id,
+      &checksum,
+      user_key
+    ).await.map_err(|e| {
+      error!("Error checking for duplicates: {}", e);
+      (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check for duplicate files".to_string())
+    })?
+  {
+    let key = blob_key(&checksum);
+    match head_stored_file(state, &existing_path, &checksum).await {
+      Ok(Some(_)) => {
+        info!(
+          "Duplicate font detected for user {}: {} (checksum: {})",
+          user.email,
+          file_name,
+          checksum
+        );
+        return Ok(CheckedUpload::Duplicate(existing_path));
+      }
+      Ok(None) => {
+        warn!(
+          "Database entry found but S3 file missing for user {}: {}",
+          user.email,
+          existing_path
+        );
+        delete_metadata(&state.db_client, &user.user_id, user_key).await.map_err(|e| {
+          error!("Failed to remove broken metadata: {}", e);
+          (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Broken database state and failed to clean it up".to_string(),
+          )
+        })?;
+        mark_blob_unstored(&state.db_client, &checksum).await.map_err(|e| {
+          error!("Failed to reset missing blob {}: {}", key, e);
+          (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Broken database state and failed to clean it up".to_string(),
+          )
+        })?;
+      }
+      Err(e) => {
+        error!("Failed to verify S3 object existence: {}", e);
+        return Err(
+          (StatusCode::INTERNAL_SERVER_ERROR, "Error checking file in storage".to_string()).into()
+        );
+      }
+    }
+  }
 
-    info!("User {} - Client {} uploaded file: {}", user.email, user.client_id, file_name);
+  let postscript_names: Vec<String> = faces
+    .iter()
+    .map(|face| face.postscript_name.clone())
+    .filter(|name| !name.is_empty())
+    .collect();
+  let existing = find_postscript_conflicts(
+    &state.db_client,
+    &user.user_id,
+    &postscript_names,
+    &checksum,
+    user_key
+  ).await.map_err(|e| {
+    error!("Error checking for PostScript name conflicts: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check for name conflicts".to_string())
+  })?;
+
+  let conflicts = describe_conflicts(user_key, &faces, &existing);
+  if !conflicts.is_empty() {
+    match conflict_policy {
+      ConflictPolicy::Reject => {
+        info!(
+          "Rejected upload {} from user {}: {} PostScript name conflict(s)",
+          file_name,
+          user.email,
+          conflicts.len()
+        );
+        let report = serde_json
+          ::to_value(ConflictReport::new(conflict_policy, &conflicts))
+          .unwrap_or_else(|e| serde_json::Value::String(e.to_string()));
+        return Err(UploadError::Report(StatusCode::CONFLICT, report));
+      }
+      ConflictPolicy::Replace => {}
+      ConflictPolicy::KeepBoth => {
+        warn!(
+          "Keeping {} alongside {} file(s) with the same PostScript name(s) for user {}",
+          user_key,
+          existing.len(),
+          user.email
+        );
+      }
+    }
   }
 
+  Ok(CheckedUpload::Accepted { faces, conflicts })
+}
+
+/// A stored upload waiting to be recorded.
+pub struct FinishedUpload<'a> {
+  pub file_name: &'a str,
+  pub sync_path: &'a str,
+  pub font_records: Vec<FontRecord>,
+  /// The bytes that went into the blobs, kept in case one has to be written
+  /// again.
+  pub stored: Vec<StoredUpload>,
+}
+
+pub struct StoredUpload {
+  pub spooled: SpooledFile,
+  pub content_type: String,
+}
+
+#[derive(Serialize)]
+struct UploadedWithConflicts<'a> {
+  message: String,
+  #[serde(flatten)]
+  report: ConflictReport<'a>,
+}
+
+/// Records a stored upload: inserts its metadata, removes the files it replaces
+/// under `on_conflict=replace`, refreshes specimens and tells sync clients about
+/// the new file. When name conflicts were found the answer is the conflict
+/// report, with `status` saying how they were resolved.
+pub async fn finish_upload(
+  state: &AppState,
+  user: &AuthUser,
+  upload: FinishedUpload<'_>,
+  conflict_policy: ConflictPolicy,
+  conflicts: &[FontConflict]
+) -> Result<Response, (StatusCode, String)> {
+  let FinishedUpload { file_name, sync_path, font_records, stored } = upload;
+  let replaced_paths: BTreeSet<&str> = match conflict_policy {
+    ConflictPolicy::Replace => {
+      conflicts
+        .iter()
+        .map(|conflict| conflict.existing.object_path.as_str())
+        .collect()
+    }
+    _ => BTreeSet::new(),
+  };
+
   if let Err(db_err) = insert_metadata(&state.db_client, &user.user_id, &font_records).await {
     error!("Failed to insert font metadata: {}", db_err);
 
@@ -183,9 +459,23 @@ pub async fn upload_font(
     ));
   }
   info!("Successfully inserted {} font records into database", font_records.len());
+  for upload in &stored {
+    restore_blob(state, upload, file_name).await?;
+  }
+
+  let mut families: BTreeSet<String> = font_records
+    .iter()
+    .map(|record| record.metadata.font_family.clone())
+    .collect();
+  for &object_path in &replaced_paths {
+    families.extend(remove_replaced_font(state, user, object_path).await);
+  }
+  for family in families {
+    tokio::spawn(schedule_specimens(state.clone(), user.user_id, family));
+  }
 
   if let Some(_record) = font_records.first() {
-    let path_result = PathBuf::from_str(relative_path.as_deref().unwrap_or(&file_name));
+    let path_result = PathBuf::from_str(sync_path);
     let path = path_result.expect("Failed to convert to PathBuf");
 
     let sync_msg = SyncMessage::ObjectCreated {
@@ -200,25 +490,535 @@ pub async fn upload_font(
     }
   }
 
-  Ok((StatusCode::OK, format!("File {} uploaded successfully", file_name)))
+  let message = if replaced_paths.is_empty() {
+    format!("File {} uploaded successfully", file_name)
+  } else {
+    format!(
+      "File {} uploaded successfully, replaced {} conflicting file(s)",
+      file_name,
+      replaced_paths.len()
+    )
+  };
+  if conflicts.is_empty() {
+    return Ok((StatusCode::OK, message).into_response());
+  }
+
+  let report = ConflictReport::new(conflict_policy, conflicts);
+  Ok((StatusCode::OK, axum::Json(UploadedWithConflicts { message, report })).into_response())
 }
 
+/// Writes an upload's bytes again if the collector deleted its blob between
+/// [`reserve_blob`] and recording the paths, which happens when an upload
+/// stalls for longer than the grace period. The paths now hold references, so
+/// the blob can't be collected again from here on.
+async fn restore_blob(
+  state: &AppState,
+  upload: &StoredUpload,
+  file_name: &str
+) -> Result<(), (StatusCode, String)> {
+  let checksum = &upload.spooled.checksum;
+  if let Some(blob_key) = reserve_blob(state, checksum).await? {
+    warn!("Blob {} was collected before {} was recorded, storing it again", checksum, file_name);
+    put_spooled_object(state, &blob_key, &upload.content_type, &upload.spooled, file_name).await?;
+    blob_written(state, checksum).await?;
+  }
+
+  Ok(())
+}
+
+/// Removes a file superseded by an upload with `on_conflict=replace`. The whole
+/// file goes, including any other faces of a collection; its blob is collected
+/// later if nothing else refers to it. Failures are logged and leave both files
+/// in place. Returns the families the removed faces belonged to.
+async fn remove_replaced_font(
+  state: &AppState,
+  user: &AuthUser,
+  object_path: &str
+) -> Vec<String> {
+  let families = match delete_metadata(&state.db_client, &user.user_id, object_path).await {
+    Ok(families) => {
+      info!("Replaced {} ({} metadata record(s) removed)", object_path, families.len());
+      families
+    }
+    Err(e) => {
+      error!("Metadata deletion failed for replaced file {}: {}", object_path, e);
+      return Vec::new();
+    }
+  };
+
+  let user_prefix = format!("{}/", user.user_id);
+  let sync_msg = SyncMessage::ObjectDeleted {
+    path: object_path.strip_prefix(&user_prefix).unwrap_or(object_path).into(),
+    source: SyncSource::Server,
+    client_id: user.client_id,
+    user_id: user.user_id,
+  };
+  if let Err(e) = state.notify_tx.send(sync_msg).await {
+    error!("Failed to notify about replaced file: {}", e);
+  }
+
+  families
+}
+
+pub fn upload_failure<E: std::fmt::Debug>(
+  file_name: &str,
+  e: SdkError<E, HttpResponse>
+) -> (StatusCode, String) {
+  if let SdkError::ServiceError(service_err) = &e {
+    if service_err.raw().status().as_u16() == 403 {
+      return (
+        StatusCode::FORBIDDEN,
+        "Access denied: insufficient permissions to upload files".to_string(),
+      );
+    } else if service_err.raw().status().as_u16() == 404 {
+      return (StatusCode::NOT_FOUND, "Bucket not found or does not exist".to_string());
+    }
+  }
+
+  error!("Failed to upload file '{}' to S3: {:?}", file_name, e);
+  (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    format!("Failed to upload file '{}': Server error", file_name),
+  )
+}
+
+/// Sends a spooled upload to S3. Anything larger than one part goes through a
+/// multipart upload that reads the temp file back one part at a time.
+async fn put_spooled_object(
+  state: &AppState,
+  key: &str,
+  content_type: &str,
+  spooled: &SpooledFile,
+  file_name: &str
+) -> Result<(), (StatusCode, String)> {
+  let read_failure = |e: ByteStreamError| {
+    error!("Failed to read spooled upload '{}': {}", file_name, e);
+    (
+      StatusCode::INTERNAL_SERVER_ERROR,
+      format!("Failed to upload file '{}': Server error", file_name),
+    )
+  };
+
+  if spooled.len <= UPLOAD_PART_SIZE {
+    let body = ByteStream::from_path(spooled.path()).await.map_err(read_failure)?;
+    state.s3_client
+      .put_object()
+      .bucket(S3_BUCKET)
+      .key(key)
+      .content_type(content_type)
+      .body(body)
+      .send().await
+      .map_err(|e| upload_failure(file_name, e))?;
+    return Ok(());
+  }
+
+  let upload = state.s3_client
+    .create_multipart_upload()
+    .bucket(S3_BUCKET)
+    .key(key)
+    .content_type(content_type)
+    .send().await
+    .map_err(|e| upload_failure(file_name, e))?;
+  let upload_id = upload.upload_id().unwrap_or_default().to_string();
+
+  let result = async {
+    let mut parts = Vec::new();
+    let mut offset = 0;
+    while offset < spooled.len {
+      let part_number = (parts.len() as i32) + 1;
+      let part_len = UPLOAD_PART_SIZE.min(spooled.len - offset);
+      let body = ByteStream::read_from()
+        .path(spooled.path())
+        .offset(offset)
+        .length(Length::Exact(part_len))
+        .build().await
+        .map_err(read_failure)?;
+
+      let part = state.s3_client
+        .upload_part()
+        .bucket(S3_BUCKET)
+        .key(key)
+        .upload_id(&upload_id)
+        .part_number(part_number)
+        .body(body)
+        .send().await
+        .map_err(|e| upload_failure(file_name, e))?;
+
+      parts.push(
+        CompletedPart::builder()
+          .part_number(part_number)
+          .set_e_tag(part.e_tag().map(str::to_string))
+          .build()
+      );
+      offset += part_len;
+    }
+
+    state.s3_client
+      .complete_multipart_upload()
+      .bucket(S3_BUCKET)
+      .key(key)
+      .upload_id(&upload_id)
+      .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+      .send().await
+      .map_err(|e| upload_failure(file_name, e))?;
+
+    Ok(())
+  }.await;
+
+  if
+    result.is_err() &&
+    let Err(e) = state.s3_client
+      .abort_multipart_upload()
+      .bucket(S3_BUCKET)
+      .key(key)
+      .upload_id(&upload_id)
+      .send().await
+  {
+    warn!("Failed to abort multipart upload {} for {}: {}", upload_id, key, e);
+  }
+
+  result
+}
+
+#[derive(Serialize)]
+struct RejectedUpload<'a> {
+  status: &'static str,
+  quarantine_key: Option<String>,
+  report: &'a ValidationReport,
+}
+
+/// Answers an upload that failed validation. With `UPLOAD_VALIDATION=quarantine`
+/// the file and its report are kept under a separate prefix for review, where
+/// they are never listed, served or synced; otherwise the file is dropped.
+async fn reject_upload(
+  state: &AppState,
+  user_key: &str,
+  spooled: &SpooledFile,
+  report: &ValidationReport
+) -> UploadError {
+  let mut rejected = RejectedUpload { status: "rejected", quarantine_key: None, report };
+
+  if env::var("UPLOAD_VALIDATION").as_deref() == Ok("quarantine") {
+    let quarantine_key = format!("{}/{}", QUARANTINE_PREFIX, user_key);
+    let report_json = serde_json::to_vec_pretty(report).unwrap_or_default();
+    let stored = match ByteStream::from_path(spooled.path()).await {
+      Ok(body) => state.s3_client
+        .put_object()
+        .bucket(S3_BUCKET)
+        .key(&quarantine_key)
+        .body(body)
+        .send().await
+        .map_err(|e| e.to_string()),
+      Err(e) => Err(e.to_string()),
+    };
+
+    match stored {
+      Ok(_) => {
+        cache_derived_object(
+          state,
+          &format!("{}.report.json", quarantine_key),
+          "application/json",
+          report_json
+        ).await;
+        info!("Quarantined invalid upload at {}", quarantine_key);
+        rejected.status = "quarantined";
+        rejected.quarantine_key = Some(quarantine_key);
+      }
+      Err(e) => error!("Failed to quarantine {}: {}", quarantine_key, e),
+    }
+  }
+
+  let report = serde_json
+    ::to_value(&rejected)
+    .unwrap_or_else(|e| serde_json::Value::String(e.to_string()));
+  UploadError::Report(StatusCode::UNPROCESSABLE_ENTITY, report)
+}
+
+/// Copies an object that reached S3 without passing through this server into a
+/// temp file, so it can be checked like a regular upload.
+pub async fn spool_object(
+  state: &AppState,
+  object_key: &str
+) -> Result<SpooledFile, (StatusCode, String)> {
+  let object = state.s3_client
+    .get_object()
+    .bucket(S3_BUCKET)
+    .key(object_key)
+    .send().await
+    .map_err(|e| {
+      error!("Failed to read uploaded object {}: {}", object_key, e);
+      (StatusCode::INTERNAL_SERVER_ERROR, "Unable to read the uploaded file".to_string())
+    })?;
+  let stream = ReaderStream::new(object.body.into_async_read());
+
+  spool_stream(stream, StatusCode::INTERNAL_SERVER_ERROR).await
+}
+
+/// Reads a whole object into memory. Returns `None` when the key does not exist.
+pub async fn fetch_object(
+  state: &AppState,
+  object_key: &str
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+  match state.s3_client.get_object().bucket(S3_BUCKET).key(object_key).send().await {
+    Ok(object) => Ok(Some(object.body.collect().await?.into_bytes().to_vec())),
+    Err(e) => {
+      if
+        let aws_sdk_s3::error::SdkError::ServiceError(service_err) = &e &&
+        service_err.err().is_no_such_key()
+      {
+        return Ok(None);
+      }
+      Err(e.into())
+    }
+  }
+}
+
+/// Stores a rendition derived from a stored font. Failures are only logged
+/// since the rendition can always be produced again.
+pub async fn cache_derived_object(
+  state: &AppState,
+  derived_key: &str,
+  content_type: &str,
+  data: Vec<u8>
+) {
+  if
+    let Err(e) = state.s3_client
+      .put_object()
+      .bucket(S3_BUCKET)
+      .key(derived_key)
+      .content_type(content_type)
+      .body(ByteStream::from(data))
+      .send().await
+  {
+    warn!("Failed to cache derived object {}: {}", derived_key, e);
+  }
+}
+
+/// Serves a WOFF/WOFF2 rendition of a stored font, converting it on first
+/// request and caching the result under a checksum-derived key. Returns `None`
+/// when the stored file already is in the requested format.
+async fn get_converted_font(
+  state: &AppState,
+  key: &str,
+  record: &FontRecord,
+  target: FontFormat,
+  request_headers: &HeaderMap
+) -> Result<Option<Response>, (StatusCode, Body)> {
+  if record.metadata.container_format == Some(target) {
+    return Ok(None);
+  }
+
+  let etag = format!("\"{}.{}\"", record.metadata.checksum, target.as_str());
+  if is_not_modified(request_headers, Some(&etag), None) {
+    return Ok(Some((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()));
+  }
+
+  let derived_key = format!("{}/{}.{}", DERIVED_PREFIX, record.metadata.checksum, target.as_str());
+  let cached = fetch_object(state, &derived_key).await.map_err(|e| {
+    error!("Failed to read cached conversion {}: {}", derived_key, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Body::from("Unable to retrieve converted file"))
+  })?;
+
+  let converted = match cached {
+    Some(data) => data,
+    None => {
+      let original = fetch_stored_file(state, &record.object_path, &record.metadata.checksum).await
+        .map_err(|e| {
+          error!("Failed to retrieve file {}: {}", key, e);
+          (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Body::from(format!("Unable to retrieve file '{}': Server error", key)),
+          )
+        })?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, Body::from(format!("File '{}' does not exist", key))))?;
+
+      info!("Converting {} to {}", key, target.as_str());
+      let converted = tokio::task
+        ::spawn_blocking(move || convert_font(&original, target).map_err(|e| e.to_string())).await
+        .map_err(|e| {
+          error!("Conversion task failed for {}: {}", key, e);
+          (StatusCode::INTERNAL_SERVER_ERROR, Body::from("Font conversion failed"))
+        })?
+        .map_err(|e| {
+          (StatusCode::UNPROCESSABLE_ENTITY, Body::from(format!("Cannot convert '{}': {}", key, e)))
+        })?;
+
+      cache_derived_object(state, &derived_key, target.content_type(), converted.clone()).await;
+
+      converted
+    }
+  };
+
+  Ok(
+    Some(
+      (
+        StatusCode::OK,
+        [
+          (header::CONTENT_TYPE, target.content_type().to_string()),
+          (header::ETAG, etag),
+          (header::CACHE_CONTROL, "private, no-cache".to_string()),
+        ],
+        converted,
+      ).into_response()
+    )
+  )
+}
+
+fn header_value(value: &str) -> Option<HeaderValue> {
+  HeaderValue::from_str(value).ok()
+}
+
+/// Streams a stored font. Responses carry an `ETag` derived from the blake3
+/// checksum and `Last-Modified` from S3, so clients can revalidate with
+/// `If-None-Match`/`If-Modified-Since` and resume with `Range`/`If-Range`.
 pub async fn get_font(
   user: AuthUser,
   Path(key): Path<String>,
-  State(state): State<AppState>
-) -> impl IntoResponse {
-  let user_key = format!("{}/{}", user.user_id, key);
-
+  Query(params): Query<DownloadParams>,
+  State(state): State<AppState>,
+  method: Method,
+  request_headers: HeaderMap
+) -> Result<Response, (StatusCode, Body)> {
   info!("User {} - Client {} downloading file: {}", user.email, user.client_id, key);
-  let object = match state.s3_client.get_object().bucket(S3_BUCKET).key(&user_key).send().await {
+  serve_library_file(
+    &state,
+    &user.user_id,
+    &key,
+    params.format.as_deref(),
+    &method,
+    &request_headers
+  ).await
+}
+
+/// Serves `key` from a user's library, converted to `format` when one is given.
+pub async fn serve_library_file(
+  state: &AppState,
+  user_id: &Uuid,
+  key: &str,
+  format: Option<&str>,
+  method: &Method,
+  request_headers: &HeaderMap
+) -> Result<Response, (StatusCode, Body)> {
+  let user_key = format!("{}/{}", user_id, key);
+
+  let records = get_metadata_by_path(&state.db_client, user_id, &user_key).await.map_err(|e| {
+    error!("Failed to fetch metadata for {}: {}", key, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Body::from("Failed to look up font metadata"))
+  })?;
+  let record = records.first().ok_or_else(|| {
+    (StatusCode::NOT_FOUND, Body::from(format!("File '{}' does not exist", key)))
+  })?;
+
+  if let Some(format_name) = format {
+    let target = FontFormat::from_name(format_name)
+      .filter(|format| matches!(format, FontFormat::Woff | FontFormat::Woff2))
+      .ok_or_else(|| {
+        (StatusCode::BAD_REQUEST, Body::from(format!("Unsupported format '{}'", format_name)))
+      })?;
+
+    if
+      let Some(response) = get_converted_font(
+        state,
+        key,
+        record,
+        target,
+        request_headers
+      ).await?
+    {
+      return Ok(response);
+    }
+  }
+
+  let (stored_key, head) = match
+    head_stored_file(state, &record.object_path, &record.metadata.checksum).await
+  {
+    Ok(Some(stored)) => stored,
+    Ok(None) => {
+      error!("No stored object for {} (blob {})", key, record.metadata.checksum);
+      return Err((StatusCode::NOT_FOUND, Body::from(format!("File '{}' does not exist", key))));
+    }
+    Err(e) => {
+      error!("Failed to retrieve file {}: {}", key, e);
+      return Err((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Body::from(format!("Unable to retrieve file '{}': Server error", key)),
+      ));
+    }
+  };
+
+  let length = head.content_length().unwrap_or_default().max(0) as u64;
+  let last_modified = head.last_modified();
+  let etag = checksum_etag(&record.metadata.checksum);
+  let content_type = record.metadata.container_format
+    .map(|format| format.content_type())
+    .or(head.content_type())
+    .unwrap_or("application/octet-stream");
+
+  let mut headers = HeaderMap::new();
+  headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+  headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private, no-cache"));
+  if let Some(value) = header_value(&etag) {
+    headers.insert(header::ETAG, value);
+  }
+  if let Some(value) = last_modified.and_then(http_date).as_deref().and_then(header_value) {
+    headers.insert(header::LAST_MODIFIED, value);
+  }
+
+  if is_not_modified(request_headers, Some(&etag), last_modified) {
+    return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+  }
+  if let Some(value) = header_value(content_type) {
+    headers.insert(header::CONTENT_TYPE, value);
+  }
+
+  let range = if range_applies(request_headers, Some(&etag), last_modified) {
+    parse_range(request_headers, length)
+  } else {
+    RangeRequest::Full
+  };
+  let (status, byte_range) = match range {
+    RangeRequest::Full => {
+      headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+      (StatusCode::OK, None)
+    }
+    RangeRequest::Partial { start, end } => {
+      if let Some(value) = header_value(&format!("bytes {}-{}/{}", start, end, length)) {
+        headers.insert(header::CONTENT_RANGE, value);
+      }
+      headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+      (StatusCode::PARTIAL_CONTENT, Some(format!("bytes={}-{}", start, end)))
+    }
+    RangeRequest::Unsatisfiable => {
+      if let Some(value) = header_value(&format!("bytes */{}", length)) {
+        headers.insert(header::CONTENT_RANGE, value);
+      }
+      return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+    }
+  };
+
+  if method == Method::HEAD {
+    return Ok((status, headers).into_response());
+  }
+
+  // Blobs never change, but pinning the S3 ETag still guards against one
+  // being collected and stored again in between.
+  let object = match
+    state.s3_client
+      .get_object()
+      .bucket(S3_BUCKET)
+      .key(&stored_key)
+      .set_range(byte_range)
+      .set_if_match(head.e_tag().map(str::to_string))
+      .send().await
+  {
     Ok(obj) => obj,
     Err(e) => {
-      if let aws_sdk_s3::error::SdkError::ServiceError(service_err) = &e {
-        if service_err.err().is_no_such_key() {
-          error!("File key does not exist: {}", key);
-          return Err((StatusCode::NOT_FOUND, Body::from(format!("File '{}' does not exist", key))));
-        }
+      if
+        let aws_sdk_s3::error::SdkError::ServiceError(service_err) = &e &&
+        service_err.err().is_no_such_key()
+      {
+        error!("File key does not exist: {}", key);
+        return Err((StatusCode::NOT_FOUND, Body::from(format!("File '{}' does not exist", key))));
       }
 
       error!("Failed to retrieve file {}: {}", key, e);
@@ -233,9 +1033,12 @@ pub async fn get_font(
   let stream = body_stream.into_async_read();
   let reader_stream = ReaderStream::new(stream);
 
-  Ok((StatusCode::OK, Body::from_stream(reader_stream)))
+  Ok((status, headers, Body::from_stream(reader_stream)).into_response())
 }
 
+/// Removes a file from the user's library. Its blob stays until the collector
+/// finds nothing else refers to it; an object left from before the blob store
+/// is removed by the next migration run.
 pub async fn delete_font(
   user: AuthUser,
   Path(key): Path<String>,
@@ -245,90 +1048,77 @@ pub async fn delete_font(
 
   info!("User {} - Client {} deleting file: {}", user.email, user.client_id, key);
 
-  match state.s3_client.head_object().bucket(S3_BUCKET).key(&user_key).send().await {
-    Ok(_) => {
-      match state.s3_client.delete_object().bucket(S3_BUCKET).key(&user_key).send().await {
-        Ok(_) => {
-          // Delete metadata
-          match delete_metadata(&state.db_client, &user.user_id, &user_key).await {
-            Ok(rows_deleted) => {
-              let sync_msg = SyncMessage::ObjectDeleted {
-                path: key.clone().into(),
-                source: SyncSource::Server,
-                client_id: user.client_id,
-                user_id: user.user_id,
-              };
-              tokio::spawn({
-                let notify_tx = state.notify_tx.clone();
-                async move {
-                  if let Err(e) = notify_tx.send(sync_msg).await {
-                    error!("Failed to notify about deleted file: {}", e);
-                  }
-                }
-              });
-              info!("Deleted {} metadata record(s) for {}", rows_deleted, &user_key);
-            }
-            Err(e) => {
-              error!("Metadata deletion failed: {}", e);
-            }
+  match delete_metadata(&state.db_client, &user.user_id, &user_key).await {
+    Ok(families) if families.is_empty() => {
+      let error_msg = format!("File '{}' does not exist", key);
+      error!("Attempted to delete non-existent file: {}", key);
+      (StatusCode::NOT_FOUND, error_msg).into_response()
+    }
+    Ok(families) => {
+      let rows_deleted = families.len();
+      for family in families.into_iter().collect::<BTreeSet<_>>() {
+        tokio::spawn(schedule_specimens(state.clone(), user.user_id, family));
+      }
+      let sync_msg = SyncMessage::ObjectDeleted {
+        path: key.clone().into(),
+        source: SyncSource::Server,
+        client_id: user.client_id,
+        user_id: user.user_id,
+      };
+      tokio::spawn({
+        let notify_tx = state.notify_tx.clone();
+        async move {
+          if let Err(e) = notify_tx.send(sync_msg).await {
+            error!("Failed to notify about deleted file: {}", e);
           }
-          info!("Successfully deleted file: {}", key);
-          (StatusCode::OK, "File deleted successfully").into_response()
-        }
-        Err(e) => {
-          let error_msg = format!("Failed to delete file '{}': {}", key, e);
-          error!("Failed to delete existing file {}: {}", key, e);
-          (StatusCode::INTERNAL_SERVER_ERROR, error_msg).into_response()
         }
-      }
+      });
+      info!("Deleted {} metadata record(s) for {}", rows_deleted, &user_key);
+      info!("Successfully deleted file: {}", key);
+      (StatusCode::OK, "File deleted successfully").into_response()
     }
     Err(e) => {
-      if let aws_sdk_s3::error::SdkError::ServiceError(service_err) = &e {
-        if service_err.err().is_not_found() {
-          let error_msg = format!("File '{}' does not exist", key);
-          error!("Attempted to delete non-existent file: {}", key);
-          return (StatusCode::NOT_FOUND, error_msg).into_response();
-        }
-      }
-
-      let error_msg = format!("Unable to access file '{}': {}", key, e);
-      error!("Failed to check if file {} exists: {}", key, e);
+      let error_msg = format!("Failed to delete file '{}': {}", key, e);
+      error!("Metadata deletion failed for {}: {}", key, e);
       (StatusCode::INTERNAL_SERVER_ERROR, error_msg).into_response()
     }
   }
 }
 
-pub async fn list_fonts(user: AuthUser, State(state): State<AppState>) -> impl IntoResponse {
-  let user_prefix = format!("{}/", user.user_id);
-
-  let s3_result = state.s3_client
-    .list_objects_v2()
-    .bucket(S3_BUCKET)
-    .prefix(&user_prefix)
-    .send().await;
-
-  let db_result = get_metadata(&state.db_client, &user.user_id).await;
+pub async fn list_fonts(
+  user: AuthUser,
+  Query(filter): Query<FontFilter>,
+  State(state): State<AppState>
+) -> impl IntoResponse {
+  if let Err(e) = filter.order_by() {
+    return (StatusCode::BAD_REQUEST, e).into_response();
+  }
 
-  match (s3_result, db_result) {
-    (Ok(s3_res), Ok(fonts)) => {
-      let s3_keys: std::collections::HashSet<_> = s3_res
-        .contents()
-        .iter()
-        .filter_map(|o| o.key())
-        .map(|k| k.to_string())
-        .collect();
+  match get_metadata(&state.db_client, &user.user_id, &filter).await {
+    Ok(fonts) => axum::Json(fonts).into_response(),
+    Err(db_err) => {
+      (StatusCode::INTERNAL_SERVER_ERROR, format!("DB error: {}", db_err)).into_response()
+    }
+  }
+}
 
-      let matched: Vec<FontRecord> = fonts
-        .into_iter()
-        .filter(|f| s3_keys.contains(&f.object_path))
-        .collect();
+/// Returns an array with one record per face, ordered by `face_index`. Plain
+/// font files yield a one-element array; before collections were split into
+/// faces this endpoint returned the single record as an object.
+pub async fn get_font_metadata(
+  user: AuthUser,
+  Path(key): Path<String>,
+  State(state): State<AppState>
+) -> impl IntoResponse {
+  let user_key = format!("{}/{}", user.user_id, key);
 
-      axum::Json(matched).into_response()
-    }
-    (Err(s3_err), _) => {
-      (StatusCode::INTERNAL_SERVER_ERROR, format!("S3 error: {}", s3_err)).into_response()
+  match get_metadata_by_path(&state.db_client, &user.user_id, &user_key).await {
+    Ok(records) if records.is_empty() => {
+      (StatusCode::NOT_FOUND, format!("File '{}' does not exist", key)).into_response()
     }
-    (_, Err(db_err)) => {
+    Ok(records) => axum::Json(records).into_response(),
+    Err(db_err) => {
+      error!("Failed to fetch metadata for {}: {}", key, db_err);
       (StatusCode::INTERNAL_SERVER_ERROR, format!("DB error: {}", db_err)).into_response()
     }
   }
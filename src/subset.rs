@@ -0,0 +1,656 @@
+use std::collections::{ BTreeMap, BTreeSet };
+
+use axum::{
+  extract::{ Path, Query, State },
+  http::{ header, StatusCode },
+  response::IntoResponse,
+};
+use log::{ error, info };
+use serde::Deserialize;
+use ttf_parser::{
+  gsub::{ SingleSubstitution, SubstitutionSubtable },
+  head::IndexToLocationFormat,
+  Face,
+  GlyphId,
+  Tag,
+};
+use uuid::Uuid;
+
+use crate::{
+  app_state::AppState,
+  auth::AuthUser,
+  blobs::fetch_library_file,
+  cff::{ cff_closure, subset_cff },
+  container::{ decode_font, detect_format, encode_woff, encode_woff2, FontFormat },
+  binary::{ read_u16, read_u32 },
+  sfnt::{ build_sfnt, composite_components, glyph_range, read_tables, SfntTable },
+  unicode::requested_codepoints,
+};
+
+const GLYF_TAG: Tag = Tag::from_bytes(b"glyf");
+const LOCA_TAG: Tag = Tag::from_bytes(b"loca");
+const CMAP_TAG: Tag = Tag::from_bytes(b"cmap");
+const HEAD_TAG: Tag = Tag::from_bytes(b"head");
+const DSIG_TAG: Tag = Tag::from_bytes(b"DSIG");
+const MAXP_TAG: Tag = Tag::from_bytes(b"maxp");
+const HHEA_TAG: Tag = Tag::from_bytes(b"hhea");
+const HMTX_TAG: Tag = Tag::from_bytes(b"hmtx");
+const VHEA_TAG: Tag = Tag::from_bytes(b"vhea");
+const VMTX_TAG: Tag = Tag::from_bytes(b"vmtx");
+const POST_TAG: Tag = Tag::from_bytes(b"post");
+const GVAR_TAG: Tag = Tag::from_bytes(b"gvar");
+const HDMX_TAG: Tag = Tag::from_bytes(b"hdmx");
+const LTSH_TAG: Tag = Tag::from_bytes(b"LTSH");
+const CFF_TAG: Tag = Tag::from_bytes(b"CFF ");
+const CFF2_TAG: Tag = Tag::from_bytes(b"CFF2");
+
+/// Tables that either hold no glyph IDs or are rewritten here. Trailing glyphs
+/// are only cut when a font has nothing else, since GSUB, GPOS, COLR and the
+/// like would keep referring to glyphs past the new end.
+const TRUNCATION_SAFE_TABLES: &[&[u8; 4]] = &[
+  b"glyf", b"loca", b"maxp", b"head", b"hhea", b"hmtx", b"vhea", b"vmtx", b"post", b"cmap",
+  b"name", b"OS/2", b"cvt ", b"fpgm", b"prep", b"gasp", b"fvar", b"avar", b"STAT", b"gvar",
+  b"cvar", b"HVAR", b"VVAR", b"MVAR", b"hdmx", b"LTSH", b"DSIG", b"meta", b"PCLT", b"VDMX",
+];
+
+/// Glyph names below this index are the standard Macintosh set.
+const STANDARD_GLYPH_NAMES: u16 = 258;
+
+/// Upper bound on requested codepoints so a single `U+0-10FFFF` cannot make
+/// the closure walk the whole Unicode space for every lookup.
+const MAX_SUBSET_CODEPOINTS: usize = 0x30000;
+
+#[derive(Deserialize, Default)]
+pub struct SubsetParams {
+  pub text: Option<String>,
+  pub unicodes: Option<String>,
+  #[serde(default)]
+  pub face: u32,
+  pub format: Option<String>,
+}
+
+/// Adds every glyph any GSUB lookup can produce from the current set, until
+/// nothing new appears. Contextual lookups only reference other lookups, which
+/// are visited on their own, so the result is a superset of what shaping can
+/// reach.
+fn gsub_closure(face: &Face, glyphs: &mut BTreeSet<u16>) {
+  let Some(gsub) = face.tables().gsub else {
+    return;
+  };
+
+  loop {
+    let before = glyphs.len();
+
+    for lookup in gsub.lookups {
+      for subtable in lookup.subtables.into_iter::<SubstitutionSubtable>() {
+        let mut added = Vec::new();
+        let coverage = subtable.coverage();
+        let covered = glyphs
+          .iter()
+          .filter_map(|&glyph| coverage.get(GlyphId(glyph)).map(|index| (glyph, index)));
+
+        for (glyph, index) in covered {
+          match subtable {
+            SubstitutionSubtable::Single(SingleSubstitution::Format1 { delta, .. }) => {
+              added.push(glyph.wrapping_add(delta as u16));
+            }
+            SubstitutionSubtable::Single(SingleSubstitution::Format2 { substitutes, .. }) => {
+              added.extend(substitutes.get(index).map(|g| g.0));
+            }
+            SubstitutionSubtable::Multiple(multiple) => {
+              if let Some(sequence) = multiple.sequences.get(index) {
+                added.extend(sequence.substitutes.into_iter().map(|g| g.0));
+              }
+            }
+            SubstitutionSubtable::Alternate(alternate) => {
+              if let Some(set) = alternate.alternate_sets.get(index) {
+                added.extend(set.alternates.into_iter().map(|g| g.0));
+              }
+            }
+            SubstitutionSubtable::Ligature(ligature) => {
+              if let Some(set) = ligature.ligature_sets.get(index) {
+                added.extend(
+                  set
+                    .into_iter()
+                    .filter(|lig| lig.components.into_iter().all(|c| glyphs.contains(&c.0)))
+                    .map(|lig| lig.glyph.0)
+                );
+              }
+            }
+            SubstitutionSubtable::ReverseChainSingle(reverse) => {
+              added.extend(reverse.substitutes.get(index).map(|g| g.0));
+            }
+            SubstitutionSubtable::Context(_) | SubstitutionSubtable::ChainContext(_) => {}
+          }
+        }
+
+        glyphs.extend(added);
+      }
+    }
+
+    if glyphs.len() == before {
+      break;
+    }
+  }
+}
+
+fn composite_closure(glyf: &[u8], loca: &[u8], long_offsets: bool, glyphs: &mut BTreeSet<u16>) {
+  let mut pending: Vec<u16> = glyphs.iter().copied().collect();
+
+  while let Some(glyph_id) = pending.pop() {
+    let Some(glyph) = glyph_range(loca, long_offsets, glyph_id).and_then(|range| glyf.get(range))
+    else {
+      continue;
+    };
+
+    for component in composite_components(glyph) {
+      if glyphs.insert(component) {
+        pending.push(component);
+      }
+    }
+  }
+}
+
+/// Groups a codepoint-to-glyph map into runs where both sides increase by one.
+fn mapping_runs(mapping: &BTreeMap<u32, u16>) -> Vec<(u32, u32, u16)> {
+  let mut runs: Vec<(u32, u32, u16)> = Vec::new();
+
+  for (&codepoint, &glyph_id) in mapping {
+    match runs.last_mut() {
+      Some((start, end, start_glyph)) if
+        *end + 1 == codepoint &&
+        u32::from(*start_glyph) + (codepoint - *start) == u32::from(glyph_id)
+      => {
+        *end = codepoint;
+      }
+      _ => runs.push((codepoint, codepoint, glyph_id)),
+    }
+  }
+
+  runs
+}
+
+fn build_cmap_format4(mapping: &BTreeMap<u32, u16>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let bmp: BTreeMap<u32, u16> = mapping
+    .range(..0xffff)
+    .map(|(&codepoint, &glyph_id)| (codepoint, glyph_id))
+    .collect();
+  let mut segments: Vec<(u16, u16, u16)> = mapping_runs(&bmp)
+    .into_iter()
+    .map(|(start, end, glyph_id)| {
+      (start as u16, end as u16, glyph_id.wrapping_sub(start as u16))
+    })
+    .collect();
+  segments.push((0xffff, 0xffff, 1));
+
+  let seg_count = segments.len();
+  let length = 16 + seg_count * 8;
+  if length > usize::from(u16::MAX) {
+    return Err("Too many codepoint ranges for a format 4 cmap subtable".into());
+  }
+
+  let entry_selector = (usize::BITS - 1 - seg_count.leading_zeros()) as u16;
+  let search_range = 2 * (1u16 << entry_selector);
+
+  let mut table = Vec::with_capacity(length);
+  for value in [
+    4,
+    length as u16,
+    0,
+    (seg_count * 2) as u16,
+    search_range,
+    entry_selector,
+    (seg_count * 2) as u16 - search_range,
+  ] {
+    table.extend_from_slice(&value.to_be_bytes());
+  }
+  for (_, end, _) in &segments {
+    table.extend_from_slice(&end.to_be_bytes());
+  }
+  table.extend_from_slice(&0u16.to_be_bytes());
+  for (start, _, _) in &segments {
+    table.extend_from_slice(&start.to_be_bytes());
+  }
+  for (_, _, delta) in &segments {
+    table.extend_from_slice(&delta.to_be_bytes());
+  }
+  table.resize(table.len() + seg_count * 2, 0);
+
+  Ok(table)
+}
+
+fn build_cmap_format12(mapping: &BTreeMap<u32, u16>) -> Vec<u8> {
+  let runs = mapping_runs(mapping);
+
+  let mut table = Vec::with_capacity(16 + runs.len() * 12);
+  table.extend_from_slice(&12u16.to_be_bytes());
+  table.extend_from_slice(&0u16.to_be_bytes());
+  table.extend_from_slice(&((16 + runs.len() * 12) as u32).to_be_bytes());
+  table.extend_from_slice(&0u32.to_be_bytes());
+  table.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+  for (start, end, glyph_id) in runs {
+    table.extend_from_slice(&start.to_be_bytes());
+    table.extend_from_slice(&end.to_be_bytes());
+    table.extend_from_slice(&u32::from(glyph_id).to_be_bytes());
+  }
+
+  table
+}
+
+/// Builds a Windows Unicode cmap: a BMP format 4 subtable plus a format 12
+/// subtable when supplementary-plane codepoints are mapped.
+fn build_cmap(mapping: &BTreeMap<u32, u16>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let mut subtables = vec![(1u16, build_cmap_format4(mapping)?)];
+  if mapping.keys().any(|&codepoint| codepoint > 0xffff) {
+    subtables.push((10, build_cmap_format12(mapping)));
+  }
+
+  let mut cmap = Vec::new();
+  cmap.extend_from_slice(&0u16.to_be_bytes());
+  cmap.extend_from_slice(&(subtables.len() as u16).to_be_bytes());
+  let mut offset = 4 + subtables.len() * 8;
+  for (encoding_id, subtable) in &subtables {
+    cmap.extend_from_slice(&3u16.to_be_bytes());
+    cmap.extend_from_slice(&encoding_id.to_be_bytes());
+    cmap.extend_from_slice(&(offset as u32).to_be_bytes());
+    offset += subtable.len();
+  }
+  for (_, subtable) in subtables {
+    cmap.extend_from_slice(&subtable);
+  }
+
+  Ok(cmap)
+}
+
+/// Rewrites `hmtx`/`vmtx` for the kept glyphs, zeroing the metrics of the rest,
+/// and returns the table with its new long-metric count. Trailing glyphs that
+/// share the last advance only keep their side bearing.
+fn subset_metrics(
+  metrics: &[u8],
+  long_count: u16,
+  glyph_count: u16,
+  glyphs: &BTreeSet<u16>
+) -> Option<(Vec<u8>, u16)> {
+  let long_count = usize::from(long_count.max(1));
+  let mut entries = Vec::with_capacity(usize::from(glyph_count));
+  for glyph_id in 0..usize::from(glyph_count) {
+    if !glyphs.contains(&(glyph_id as u16)) {
+      entries.push((0u16, 0u16));
+      continue;
+    }
+    let advance = read_u16(metrics, 4 * glyph_id.min(long_count - 1))?;
+    let bearing = if glyph_id < long_count {
+      read_u16(metrics, 4 * glyph_id + 2)?
+    } else {
+      read_u16(metrics, 4 * long_count + 2 * (glyph_id - long_count))?
+    };
+    entries.push((advance, bearing));
+  }
+
+  let mut new_long_count = entries.len().max(1);
+  while new_long_count > 1 && entries[new_long_count - 1].0 == entries[new_long_count - 2].0 {
+    new_long_count -= 1;
+  }
+
+  let mut table = Vec::with_capacity(new_long_count * 4 + (entries.len() - new_long_count) * 2);
+  for (index, (advance, bearing)) in entries.iter().enumerate() {
+    if index < new_long_count {
+      table.extend_from_slice(&advance.to_be_bytes());
+    }
+    table.extend_from_slice(&bearing.to_be_bytes());
+  }
+
+  Some((table, new_long_count as u16))
+}
+
+/// Keeps the names of kept glyphs in a format 2 `post` table and drops every
+/// other custom name. Formats 1 and 3 carry no per-glyph data and are left
+/// alone; the deprecated format 2.5 loses its names (format 3).
+fn subset_post(post: &[u8], glyph_count: u16, glyphs: &BTreeSet<u16>) -> Option<Vec<u8>> {
+  let header = post.get(..32)?;
+  match read_u32(post, 0)? {
+    0x0002_0000 => {}
+    0x0001_0000 | 0x0003_0000 => {
+      return None;
+    }
+    _ => {
+      let mut table = header.to_vec();
+      table[..4].copy_from_slice(&0x0003_0000u32.to_be_bytes());
+      return Some(table);
+    }
+  }
+
+  let num_glyphs = read_u16(post, 32)?;
+  let mut names = Vec::new();
+  let mut offset = 34 + 2 * usize::from(num_glyphs);
+  while let Some(&len) = post.get(offset) {
+    names.push(post.get(offset..offset + 1 + usize::from(len))?);
+    offset += 1 + usize::from(len);
+  }
+
+  let mut kept_names: BTreeMap<u16, u16> = BTreeMap::new();
+  let mut indices = Vec::with_capacity(usize::from(glyph_count));
+  for glyph_id in 0..glyph_count {
+    let index = if glyph_id < num_glyphs && glyphs.contains(&glyph_id) {
+      read_u16(post, 34 + 2 * usize::from(glyph_id))?
+    } else {
+      0
+    };
+    if index < STANDARD_GLYPH_NAMES {
+      indices.push(index);
+      continue;
+    }
+    let next = STANDARD_GLYPH_NAMES + (kept_names.len() as u16);
+    indices.push(*kept_names.entry(index).or_insert(next));
+  }
+
+  let mut table = header.to_vec();
+  table.extend_from_slice(&glyph_count.to_be_bytes());
+  for index in indices {
+    table.extend_from_slice(&index.to_be_bytes());
+  }
+  let mut kept: Vec<(u16, u16)> = kept_names.into_iter().collect();
+  kept.sort_by_key(|&(_, new_index)| new_index);
+  for (old_index, _) in kept {
+    table.extend_from_slice(names.get(usize::from(old_index - STANDARD_GLYPH_NAMES))?);
+  }
+
+  Some(table)
+}
+
+/// Drops the variation data of glyphs outside the subset from `gvar`.
+fn subset_gvar(gvar: &[u8], glyph_count: u16, glyphs: &BTreeSet<u16>) -> Option<Vec<u8>> {
+  let axis_count = usize::from(read_u16(gvar, 4)?);
+  let shared_count = usize::from(read_u16(gvar, 6)?);
+  let shared_offset = read_u32(gvar, 8)? as usize;
+  let old_count = read_u16(gvar, 12)?;
+  let long_offsets = read_u16(gvar, 14)? & 1 != 0;
+  let data_offset = read_u32(gvar, 16)? as usize;
+  let variation_data = |glyph_id: u16| -> Option<&[u8]> {
+    let (start, end) = if long_offsets {
+      let index = 20 + 4 * usize::from(glyph_id);
+      (read_u32(gvar, index)? as usize, read_u32(gvar, index + 4)? as usize)
+    } else {
+      let index = 20 + 2 * usize::from(glyph_id);
+      (usize::from(read_u16(gvar, index)?) * 2, usize::from(read_u16(gvar, index + 2)?) * 2)
+    };
+    gvar.get(data_offset + start..data_offset + end)
+  };
+
+  let mut data = Vec::new();
+  let mut offsets = Vec::with_capacity(usize::from(glyph_count) + 1);
+  for glyph_id in 0..glyph_count {
+    offsets.push(data.len() as u32);
+    if glyph_id < old_count && glyphs.contains(&glyph_id) {
+      data.extend_from_slice(variation_data(glyph_id)?);
+      data.resize((data.len() + 1) & !1, 0);
+    }
+  }
+  offsets.push(data.len() as u32);
+
+  let short = data.len() < 0x20000;
+  let offsets_len = offsets.len() * (if short { 2 } else { 4 });
+  let shared_tuples = gvar.get(shared_offset..shared_offset + shared_count * axis_count * 2)?;
+  let new_shared_offset = 20 + offsets_len;
+  let new_data_offset = new_shared_offset + shared_tuples.len();
+
+  let mut table = Vec::with_capacity(new_data_offset + data.len());
+  table.extend_from_slice(gvar.get(..8)?);
+  table.extend_from_slice(&(new_shared_offset as u32).to_be_bytes());
+  table.extend_from_slice(&glyph_count.to_be_bytes());
+  table.extend_from_slice(&((read_u16(gvar, 14)? & !1) | u16::from(!short)).to_be_bytes());
+  table.extend_from_slice(&(new_data_offset as u32).to_be_bytes());
+  for offset in offsets {
+    if short {
+      table.extend_from_slice(&((offset / 2) as u16).to_be_bytes());
+    } else {
+      table.extend_from_slice(&offset.to_be_bytes());
+    }
+  }
+  table.extend_from_slice(shared_tuples);
+  table.extend_from_slice(&data);
+
+  Some(table)
+}
+
+/// Produces a subset that keeps glyph IDs stable: glyphs outside the closure
+/// get empty outlines, metrics, names and variations, the cmap only maps the
+/// requested codepoints and layout tables are kept as-is, so GSUB/GPOS stay
+/// valid without renumbering. Glyphs after the last kept one are cut off when
+/// no remaining table refers to them, which never happens for CFF outlines.
+pub fn subset_font(
+  data: &[u8],
+  face_index: u32,
+  codepoints: &BTreeSet<u32>
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let (_, sfnt) = decode_font(data)?;
+  let face = Face::parse(&sfnt, face_index).map_err(|e|
+    format!("Error parsing font data: {:?}", e)
+  )?;
+
+  let raw_tables = face.tables();
+  let raw_face = face.raw_face();
+  let cff = raw_face.table(CFF_TAG);
+  let truetype = match (raw_face.table(GLYF_TAG), raw_face.table(LOCA_TAG)) {
+    (Some(glyf), Some(loca)) => Some((glyf, loca)),
+    _ if cff.is_some() || raw_face.table(CFF2_TAG).is_some() => None,
+    _ => return Err("Font has no glyph outlines".into()),
+  };
+  let long_offsets = raw_tables.head.index_to_location_format == IndexToLocationFormat::Long;
+
+  let mapping: BTreeMap<u32, u16> = codepoints
+    .iter()
+    .filter_map(|&codepoint| {
+      let glyph_id = face.glyph_index(char::from_u32(codepoint)?)?;
+      Some((codepoint, glyph_id.0))
+    })
+    .collect();
+
+  let mut glyphs: BTreeSet<u16> = mapping.values().copied().collect();
+  glyphs.insert(0);
+  gsub_closure(&face, &mut glyphs);
+  if let Some((glyf, loca)) = truetype {
+    composite_closure(glyf, loca, long_offsets, &mut glyphs);
+  }
+  if let Some(cff) = cff {
+    cff_closure(cff, &mut glyphs)?;
+  }
+
+  let (flavor, tables) = read_tables(&sfnt, face_index)?;
+  let truncate = tables
+    .iter()
+    .all(|table| TRUNCATION_SAFE_TABLES.iter().any(|tag| table.tag == Tag::from_bytes(tag)));
+  let glyph_count = match glyphs.last() {
+    Some(&last) if truncate => (last + 1).min(face.number_of_glyphs()),
+    _ => face.number_of_glyphs(),
+  };
+
+  let mut new_glyf = Vec::new();
+  let mut new_loca = Vec::new();
+  let mut short_loca = None;
+  if let Some((glyf, loca)) = truetype {
+    let mut glyph_offsets = Vec::with_capacity(usize::from(glyph_count) + 1);
+    for glyph_id in 0..glyph_count {
+      glyph_offsets.push(new_glyf.len() as u32);
+      if glyphs.contains(&glyph_id) {
+        let glyph = glyph_range(loca, long_offsets, glyph_id)
+          .and_then(|range| glyf.get(range))
+          .ok_or_else(|| format!("Glyph {} lies outside of the glyf table", glyph_id))?;
+        new_glyf.extend_from_slice(glyph);
+        new_glyf.resize((new_glyf.len() + 3) & !3, 0);
+      }
+    }
+    glyph_offsets.push(new_glyf.len() as u32);
+    // Offsets are multiples of four, so short offsets hold anything below 128 KiB.
+    let short = new_glyf.len() < 0x20000;
+    new_loca.reserve(glyph_offsets.len() * (if short { 2 } else { 4 }));
+    for offset in glyph_offsets {
+      if short {
+        new_loca.extend_from_slice(&((offset / 2) as u16).to_be_bytes());
+      } else {
+        new_loca.extend_from_slice(&offset.to_be_bytes());
+      }
+    }
+    short_loca = Some(short);
+  }
+
+  let metrics_count = |header: Tag| {
+    tables
+      .iter()
+      .find(|table| table.tag == header)
+      .and_then(|table| read_u16(&table.data, 34))
+  };
+  let mut hmtx = match metrics_count(HHEA_TAG) {
+    Some(long_count) => tables
+      .iter()
+      .find(|table| table.tag == HMTX_TAG)
+      .and_then(|table| subset_metrics(&table.data, long_count, glyph_count, &glyphs)),
+    None => None,
+  };
+  let mut vmtx = match metrics_count(VHEA_TAG) {
+    Some(long_count) => tables
+      .iter()
+      .find(|table| table.tag == VMTX_TAG)
+      .and_then(|table| subset_metrics(&table.data, long_count, glyph_count, &glyphs)),
+    None => None,
+  };
+  let hmtx_long_count = hmtx.as_ref().map(|(_, long_count)| *long_count);
+  let vmtx_long_count = vmtx.as_ref().map(|(_, long_count)| *long_count);
+
+  let cmap = build_cmap(&mapping)?;
+  let mut subset_tables: Vec<SfntTable> = Vec::with_capacity(tables.len());
+  for mut table in tables {
+    match table.tag {
+      GLYF_TAG => {
+        table.data = std::mem::take(&mut new_glyf);
+      }
+      LOCA_TAG => {
+        table.data = std::mem::take(&mut new_loca);
+      }
+      CMAP_TAG => {
+        table.data = cmap.clone();
+      }
+      HEAD_TAG if table.data.len() >= 54 => {
+        if let Some(short) = short_loca {
+          table.data[50..52].copy_from_slice(&i16::from(!short).to_be_bytes());
+        }
+      }
+      CFF_TAG | CFF2_TAG => {
+        table.data = subset_cff(&table.data, &glyphs)?;
+      }
+      MAXP_TAG if table.data.len() >= 6 => {
+        table.data[4..6].copy_from_slice(&glyph_count.to_be_bytes());
+      }
+      HHEA_TAG | VHEA_TAG if table.data.len() >= 36 => {
+        let long_count = if table.tag == HHEA_TAG { hmtx_long_count } else { vmtx_long_count };
+        if let Some(long_count) = long_count {
+          table.data[34..36].copy_from_slice(&long_count.to_be_bytes());
+        }
+      }
+      HMTX_TAG | VMTX_TAG => {
+        let metrics = if table.tag == HMTX_TAG { hmtx.take() } else { vmtx.take() };
+        let (data, _) = metrics.ok_or_else(|| format!("Malformed '{}' table", table.tag))?;
+        table.data = data;
+      }
+      POST_TAG => {
+        if let Some(post) = subset_post(&table.data, glyph_count, &glyphs) {
+          table.data = post;
+        }
+      }
+      GVAR_TAG => {
+        table.data = subset_gvar(&table.data, glyph_count, &glyphs).ok_or("Malformed 'gvar' table")?;
+      }
+      // Per-glyph device metrics are only a hinting aid and would have to match
+      // the new glyph count.
+      HDMX_TAG | LTSH_TAG if glyph_count != face.number_of_glyphs() => {
+        continue;
+      }
+      // Any signature is invalidated by rewriting the font.
+      DSIG_TAG => {
+        continue;
+      }
+      _ => {}
+    }
+    subset_tables.push(table);
+  }
+
+  Ok(build_sfnt(flavor, subset_tables))
+}
+
+fn subset_and_encode(
+  data: &[u8],
+  face_index: u32,
+  codepoints: &BTreeSet<u32>,
+  target: Option<FontFormat>
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let subset = subset_font(data, face_index, codepoints)?;
+  match target {
+    Some(FontFormat::Woff) => encode_woff(&subset),
+    Some(FontFormat::Woff2) => encode_woff2(&subset),
+    _ => Ok(subset),
+  }
+}
+
+/// Serves a subset of a stored font for `text` or `unicodes`, optionally as
+/// WOFF or WOFF2.
+pub async fn subset_handler(
+  user: AuthUser,
+  Path(key): Path<String>,
+  Query(params): Query<SubsetParams>,
+  State(state): State<AppState>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+  info!("User {} - Client {} subsetting {}", user.email, user.client_id, key);
+  serve_subset(&state, &user.user_id, &key, params).await
+}
+
+/// Subsets `key` from a user's library as described by `params`.
+pub async fn serve_subset(
+  state: &AppState,
+  user_id: &Uuid,
+  key: &str,
+  params: SubsetParams
+) -> Result<impl IntoResponse + use<>, (StatusCode, String)> {
+  let user_key = format!("{}/{}", user_id, key);
+
+  let codepoints = requested_codepoints(
+    params.text.as_deref(),
+    params.unicodes.as_deref(),
+    MAX_SUBSET_CODEPOINTS
+  ).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+  let target = match params.format.as_deref() {
+    None => None,
+    Some(name) =>
+      Some(
+        FontFormat::from_name(name)
+          .filter(|format| matches!(format, FontFormat::Woff | FontFormat::Woff2))
+          .ok_or((StatusCode::BAD_REQUEST, format!("Unsupported format '{}'", name)))?
+      ),
+  };
+
+  let data = fetch_library_file(state, user_id, &user_key).await
+    .map_err(|e| {
+      error!("Failed to retrieve file {}: {}", key, e);
+      (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Unable to retrieve file '{}': Server error", key),
+      )
+    })?
+    .ok_or((StatusCode::NOT_FOUND, format!("File '{}' does not exist", key)))?;
+
+  info!("Subsetting {} to {} codepoint(s)", key, codepoints.len());
+  let face_index = params.face;
+  let subset = tokio::task
+    ::spawn_blocking(move || {
+      subset_and_encode(&data, face_index, &codepoints, target).map_err(|e| e.to_string())
+    }).await
+    .map_err(|e| {
+      error!("Subset task failed for {}: {}", key, e);
+      (StatusCode::INTERNAL_SERVER_ERROR, "Font subsetting failed".to_string())
+    })?
+    .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("Cannot subset '{}': {}", key, e)))?;
+
+  let format = target.or_else(|| detect_format(&subset)).unwrap_or(FontFormat::Ttf);
+  let content_type = format.content_type();
+  Ok((StatusCode::OK, [(header::CONTENT_TYPE, content_type)], subset))
+}
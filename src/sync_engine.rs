@@ -94,7 +94,7 @@ async fn handle_socket(user: AuthUser, socket: WebSocket, state: AppState) {
     let mut clients_lock = state.sync_clients.lock().await;
     let sync_client = SyncClient {
       client_id,
-      user_id: user.user_id.clone(),
+      user_id: user.user_id,
       sender: tx.clone(),
     };
     clients_lock.push(sync_client);
@@ -0,0 +1,139 @@
+use std::{ collections::BTreeSet, ops::RangeInclusive };
+
+const MAX_CODEPOINT: u32 = 0x10ffff;
+
+fn parse_hex(value: &str) -> Result<u32, String> {
+  u32::from_str_radix(value, 16)
+    .ok()
+    .filter(|&codepoint| codepoint <= MAX_CODEPOINT)
+    .ok_or_else(|| format!("Invalid codepoint '{}'", value))
+}
+
+/// Parses a comma-separated list in CSS `unicode-range` syntax, e.g.
+/// `U+0000-00FF, U+20AC, U+4??`. The `U+` prefix is optional.
+pub fn parse_unicode_ranges(spec: &str) -> Result<Vec<RangeInclusive<u32>>, String> {
+  let mut ranges = Vec::new();
+
+  for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+    let body = part
+      .strip_prefix("U+")
+      .or_else(|| part.strip_prefix("u+"))
+      .unwrap_or(part);
+
+    let range = if body.contains('?') {
+      let start = parse_hex(&body.replace('?', "0"))?;
+      let end = parse_hex(&body.replace('?', "F"))?;
+      start..=end
+    } else if let Some((start, end)) = body.split_once('-') {
+      let start = parse_hex(start)?;
+      let end = parse_hex(end)?;
+      if start > end {
+        return Err(format!("Invalid range '{}'", part));
+      }
+      start..=end
+    } else {
+      let codepoint = parse_hex(body)?;
+      codepoint..=codepoint
+    };
+
+    ranges.push(range);
+  }
+
+  Ok(ranges)
+}
+
+/// Collapses ascending codepoints into contiguous ranges.
+pub fn codepoint_ranges(codepoints: impl IntoIterator<Item = u32>) -> Vec<RangeInclusive<u32>> {
+  let mut ranges: Vec<RangeInclusive<u32>> = Vec::new();
+
+  for codepoint in codepoints {
+    match ranges.last_mut() {
+      Some(range) if *range.end() + 1 == codepoint => {
+        *range = *range.start()..=codepoint;
+      }
+      Some(range) if range.contains(&codepoint) => {}
+      _ => ranges.push(codepoint..=codepoint),
+    }
+  }
+
+  ranges
+}
+
+/// Bridges the smallest gaps between ascending ranges until at most `limit`
+/// remain. The result covers a superset of the input, which is what
+/// `unicode-range` needs: a browser only fetches the font when a page uses one
+/// of its characters, and falls back per glyph either way.
+pub fn merge_nearby_ranges(
+  ranges: &[RangeInclusive<u32>],
+  limit: usize
+) -> Vec<RangeInclusive<u32>> {
+  if ranges.len() <= limit.max(1) {
+    return ranges.to_vec();
+  }
+
+  let mut gaps: Vec<(u32, usize)> = ranges
+    .windows(2)
+    .enumerate()
+    .map(|(index, pair)| (pair[1].start() - pair[0].end(), index))
+    .collect();
+  gaps.sort_unstable();
+  let mut bridged = vec![false; ranges.len()];
+  for &(_, index) in &gaps[..ranges.len() - limit.max(1)] {
+    bridged[index] = true;
+  }
+
+  let mut merged: Vec<RangeInclusive<u32>> = Vec::with_capacity(limit);
+  let mut start = *ranges[0].start();
+  for (index, range) in ranges.iter().enumerate() {
+    if !bridged[index] {
+      merged.push(start..=*range.end());
+      if let Some(next) = ranges.get(index + 1) {
+        start = *next.start();
+      }
+    }
+  }
+  merged
+}
+
+/// Formats ranges as a CSS `unicode-range` descriptor value.
+pub fn format_unicode_ranges(ranges: &[RangeInclusive<u32>]) -> String {
+  ranges
+    .iter()
+    .map(|range| {
+      if range.start() == range.end() {
+        format!("U+{:04X}", range.start())
+      } else {
+        format!("U+{:04X}-{:04X}", range.start(), range.end())
+      }
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// Merges the characters of `text` with the codepoints of a `unicode-range`
+/// style list, refusing lists that expand to more than `limit` codepoints.
+pub fn requested_codepoints(
+  text: Option<&str>,
+  unicodes: Option<&str>,
+  limit: usize
+) -> Result<BTreeSet<u32>, String> {
+  let mut codepoints: BTreeSet<u32> = text.unwrap_or_default().chars().map(u32::from).collect();
+
+  if let Some(unicodes) = unicodes {
+    let ranges = parse_unicode_ranges(unicodes)?;
+    let requested: usize = ranges
+      .iter()
+      .map(|range| (range.end() - range.start() + 1) as usize)
+      .sum();
+    if requested > limit {
+      return Err("Too many codepoints requested".to_string());
+    }
+    codepoints.extend(ranges.into_iter().flatten());
+  }
+
+  if codepoints.is_empty() {
+    return Err("Provide 'text' or 'unicodes' to look up".to_string());
+  }
+
+  Ok(codepoints)
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use ttf_parser::Face;
+
+use crate::{
+  binary::{ read_i16, read_u16, read_u32 },
+  container::{ decode_font, DecodedFont, FontFormat },
+  sfnt::{ table_checksum, CHECKSUM_MAGIC, TTCF_TAG },
+};
+
+const HEAD_MAGIC: u32 = 0x5f0f3cf5;
+const MAX_COLLECTION_FACES: u32 = 256;
+
+static REQUIRED_TABLES: [&[u8; 4]; 7] = [
+  b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"post",
+];
+
+/// Tables from the OpenType and AAT specifications plus the Graphite tables.
+/// Anything else is reported, since browsers sanitizing with OTS drop it.
+static KNOWN_TABLES: &[&[u8; 4]] = &[
+  b"avar", b"BASE", b"CBDT", b"CBLC", b"CFF ", b"CFF2", b"cmap", b"COLR", b"CPAL", b"cvar",
+  b"cvt ", b"DSIG", b"EBDT", b"EBLC", b"EBSC", b"fpgm", b"fvar", b"gasp", b"GDEF", b"glyf",
+  b"GPOS", b"GSUB", b"gvar", b"hdmx", b"head", b"hhea", b"hmtx", b"HVAR", b"JSTF", b"kern",
+  b"loca", b"LTSH", b"MATH", b"maxp", b"MERG", b"meta", b"MVAR", b"name", b"OS/2", b"PCLT",
+  b"post", b"prep", b"sbix", b"STAT", b"SVG ", b"VDMX", b"vhea", b"vmtx", b"VORG", b"VVAR",
+  b"acnt", b"ankr", b"bdat", b"bhed", b"bloc", b"bsln", b"cidg", b"feat", b"fdsc", b"fmtx",
+  b"fond", b"gcid", b"hsty", b"just", b"lcar", b"ltag", b"mort", b"morx", b"opbd", b"prop",
+  b"trak", b"xref", b"Zapf", b"Silf", b"Glat", b"Gloc", b"Feat", b"Sill",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+  /// The font is unsafe or unusable and must not be stored.
+  Error,
+  /// The font is technically malformed but still parses consistently.
+  Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationIssue {
+  pub severity: Severity,
+  /// Stable machine-readable identifier, e.g. `loca_out_of_order`.
+  pub code: &'static str,
+  pub face_index: Option<u32>,
+  pub table: Option<String>,
+  pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+  pub valid: bool,
+  pub container_format: Option<FontFormat>,
+  pub errors: usize,
+  pub warnings: usize,
+  pub issues: Vec<ValidationIssue>,
+}
+
+struct Validator {
+  issues: Vec<ValidationIssue>,
+  face_index: Option<u32>,
+}
+
+impl Validator {
+  fn report(
+    &mut self,
+    severity: Severity,
+    code: &'static str,
+    table: Option<&[u8]>,
+    message: String
+  ) {
+    self.issues.push(ValidationIssue {
+      severity,
+      code,
+      face_index: self.face_index,
+      table: table.map(|tag| String::from_utf8_lossy(tag).trim_end().to_string()),
+      message,
+    });
+  }
+
+  fn error(&mut self, code: &'static str, table: Option<&[u8]>, message: String) {
+    self.report(Severity::Error, code, table, message);
+  }
+
+  fn warning(&mut self, code: &'static str, table: Option<&[u8]>, message: String) {
+    self.report(Severity::Warning, code, table, message);
+  }
+
+  /// Checks the table directory of one face and returns the tables whose
+  /// records point inside the font data.
+  fn check_directory<'a>(&mut self, data: &'a [u8], offset: usize) -> HashMap<[u8; 4], &'a [u8]> {
+    let mut tables = HashMap::new();
+    let Some(num_tables) = read_u16(data, offset + 4) else {
+      self.error("directory_out_of_bounds", None, "Table directory header is truncated".into());
+      return tables;
+    };
+    if num_tables == 0 {
+      self.error("no_tables", None, "Table directory is empty".into());
+      return tables;
+    }
+    let directory_end = offset + 12 + 16 * (num_tables as usize);
+    if directory_end > data.len() {
+      self.error(
+        "directory_out_of_bounds",
+        None,
+        format!("Directory of {} tables runs past the end of the file", num_tables)
+      );
+      return tables;
+    }
+
+    let mut ranges: Vec<(usize, usize, [u8; 4])> = Vec::new();
+    let mut previous_tag: Option<[u8; 4]> = None;
+    for record in data[offset + 12..directory_end].chunks_exact(16) {
+      let tag: [u8; 4] = [record[0], record[1], record[2], record[3]];
+      let checksum = read_u32(record, 4).unwrap_or(0);
+      let table_offset = read_u32(record, 8).unwrap_or(0) as usize;
+      let length = read_u32(record, 12).unwrap_or(0) as usize;
+
+      if previous_tag.is_some_and(|previous| previous >= tag) {
+        self.warning(
+          "directory_unsorted",
+          Some(&tag),
+          "Table records are not sorted by tag".into()
+        );
+      }
+      previous_tag = Some(tag);
+
+      if tables.contains_key(&tag) {
+        self.error("duplicate_table", Some(&tag), "Table appears more than once".into());
+        continue;
+      }
+      let Some(table) = table_offset
+        .checked_add(length)
+        .and_then(|end| data.get(table_offset..end)) else {
+        self.error(
+          "table_out_of_bounds",
+          Some(&tag),
+          format!("Table at offset {} with length {} lies outside the file", table_offset, length)
+        );
+        continue;
+      };
+      if !table_offset.is_multiple_of(4) {
+        self.warning(
+          "table_misaligned",
+          Some(&tag),
+          format!("Table offset {} is not 4-byte aligned", table_offset)
+        );
+      }
+
+      // checkSumAdjustment is excluded from the head table's own checksum.
+      let actual = if &tag == b"head" && table.len() >= 12 {
+        let mut head = table.to_vec();
+        head[8..12].fill(0);
+        table_checksum(&head)
+      } else {
+        table_checksum(table)
+      };
+      if actual != checksum {
+        self.warning(
+          "table_checksum",
+          Some(&tag),
+          format!("Checksum is {:#010x}, directory says {:#010x}", actual, checksum)
+        );
+      }
+
+      if !KNOWN_TABLES.contains(&&tag) {
+        self.warning("unexpected_table", Some(&tag), "Table is not part of OpenType".into());
+      }
+
+      ranges.push((table_offset, table_offset + length, tag));
+      tables.insert(tag, table);
+    }
+
+    ranges.sort();
+    for pair in ranges.windows(2) {
+      let ((_, end, first), (start, _, second)) = (pair[0], pair[1]);
+      if start < end {
+        self.error(
+          "tables_overlap",
+          Some(&second),
+          format!("Table overlaps '{}'", String::from_utf8_lossy(&first))
+        );
+      }
+    }
+
+    tables
+  }
+
+  fn check_required(&mut self, tables: &HashMap<[u8; 4], &[u8]>) {
+    for tag in REQUIRED_TABLES {
+      if !tables.contains_key(tag) {
+        self.error("missing_table", Some(tag), "Required table is missing".into());
+      }
+    }
+
+    let has = |tag: &[u8; 4]| tables.contains_key(tag);
+    if has(b"glyf") != has(b"loca") {
+      self.error(
+        "missing_table",
+        Some(if has(b"glyf") { b"loca" } else { b"glyf" }),
+        "glyf and loca must be present together".into()
+      );
+    }
+    let has_outlines = has(b"glyf") || has(b"CFF ") || has(b"CFF2");
+    let has_bitmaps =
+      (has(b"CBDT") && has(b"CBLC")) || (has(b"EBDT") && has(b"EBLC")) || has(b"sbix");
+    if !has_outlines && !has_bitmaps {
+      self.error("missing_outlines", None, "Font has neither outline nor bitmap glyphs".into());
+    }
+  }
+
+  /// Returns the glyph count from maxp and the loca format from head.
+  fn check_head_and_maxp(&mut self, tables: &HashMap<[u8; 4], &[u8]>) -> Option<(u16, i16)> {
+    let head = tables.get(b"head")?;
+    if head.len() < 54 {
+      self.error("table_truncated", Some(b"head"), format!("Table is {} bytes", head.len()));
+      return None;
+    }
+    if read_u32(head, 12) != Some(HEAD_MAGIC) {
+      self.error("head_magic", Some(b"head"), "magicNumber is not 0x5F0F3CF5".into());
+    }
+    let units_per_em = read_u16(head, 18).unwrap_or(0);
+    if !(16..=16384).contains(&units_per_em) {
+      self.error(
+        "units_per_em",
+        Some(b"head"),
+        format!("unitsPerEm {} is out of range", units_per_em)
+      );
+    }
+    let loca_format = read_i16(head, 50).unwrap_or(-1);
+    if !matches!(loca_format, 0 | 1) {
+      self.error(
+        "loca_format",
+        Some(b"head"),
+        format!("indexToLocFormat {} is invalid", loca_format)
+      );
+    }
+
+    let maxp = tables.get(b"maxp")?;
+    let Some(num_glyphs) = read_u16(maxp, 4) else {
+      self.error("table_truncated", Some(b"maxp"), format!("Table is {} bytes", maxp.len()));
+      return None;
+    };
+    if num_glyphs == 0 {
+      self.error("no_glyphs", Some(b"maxp"), "numGlyphs is 0".into());
+    }
+
+    Some((num_glyphs, loca_format))
+  }
+
+  fn check_loca(&mut self, tables: &HashMap<[u8; 4], &[u8]>, num_glyphs: u16, loca_format: i16) {
+    let (Some(loca), Some(glyf)) = (tables.get(b"loca"), tables.get(b"glyf")) else {
+      return;
+    };
+    let long_offsets = match loca_format {
+      0 => false,
+      1 => true,
+      _ => {
+        return;
+      }
+    };
+
+    let entry_size = if long_offsets { 4 } else { 2 };
+    let expected = (num_glyphs as usize + 1) * entry_size;
+    if loca.len() < expected {
+      self.error(
+        "loca_too_short",
+        Some(b"loca"),
+        format!("{} glyphs need {} bytes, table has {}", num_glyphs, expected, loca.len())
+      );
+      return;
+    }
+    if loca.len() > expected {
+      self.warning(
+        "loca_too_long",
+        Some(b"loca"),
+        format!("{} glyphs need {} bytes, table has {}", num_glyphs, expected, loca.len())
+      );
+    }
+
+    let offset_at = |index: usize| {
+      if long_offsets {
+        read_u32(loca, index * 4).map(|offset| offset as usize)
+      } else {
+        read_u16(loca, index * 2).map(|offset| (offset as usize) * 2)
+      }
+    };
+    let mut previous = 0;
+    for index in 0..=num_glyphs as usize {
+      let offset = offset_at(index).unwrap_or(0);
+      if offset < previous {
+        self.error(
+          "loca_out_of_order",
+          Some(b"loca"),
+          format!("Offset of glyph {} is smaller than the one before it", index)
+        );
+        return;
+      }
+      previous = offset;
+    }
+    if previous > glyf.len() {
+      self.error(
+        "loca_out_of_bounds",
+        Some(b"loca"),
+        format!("Last offset {} is past the end of glyf ({} bytes)", previous, glyf.len())
+      );
+    }
+  }
+
+  fn check_metrics(&mut self, tables: &HashMap<[u8; 4], &[u8]>, num_glyphs: u16) {
+    let (Some(hhea), Some(hmtx)) = (tables.get(b"hhea"), tables.get(b"hmtx")) else {
+      return;
+    };
+    let Some(metrics) = read_u16(hhea, 34) else {
+      self.error("table_truncated", Some(b"hhea"), format!("Table is {} bytes", hhea.len()));
+      return;
+    };
+
+    if metrics == 0 || metrics > num_glyphs {
+      self.error(
+        "hmtx_metrics_count",
+        Some(b"hhea"),
+        format!("numberOfHMetrics {} does not fit {} glyphs", metrics, num_glyphs)
+      );
+      return;
+    }
+    let expected = 4 * (metrics as usize) + 2 * ((num_glyphs - metrics) as usize);
+    if hmtx.len() < expected {
+      self.error(
+        "table_truncated",
+        Some(b"hmtx"),
+        format!("{} glyphs need {} bytes, table has {}", num_glyphs, expected, hmtx.len())
+      );
+    }
+  }
+
+  fn check_face(&mut self, data: &[u8], face_index: u32, offset: usize) {
+    let tables = self.check_directory(data, offset);
+    if tables.is_empty() {
+      return;
+    }
+
+    self.check_required(&tables);
+    if let Some((num_glyphs, loca_format)) = self.check_head_and_maxp(&tables) {
+      self.check_loca(&tables, num_glyphs, loca_format);
+      self.check_metrics(&tables, num_glyphs);
+    }
+
+    if let Err(e) = Face::parse(data, face_index) {
+      self.error("parse_failed", None, format!("Font cannot be parsed: {:?}", e));
+    }
+  }
+}
+
+/// Structural checks in the spirit of OTS, run on untrusted uploads before
+/// they are stored or synced. Web font containers are checked after decoding,
+/// and the decoded font is handed back so it need not be decoded again.
+pub fn validate_font(data: &[u8]) -> (ValidationReport, Option<DecodedFont<'_>>) {
+  let mut validator = Validator { issues: Vec::new(), face_index: None };
+  let mut container_format = None;
+
+  let decoded = match decode_font(data) {
+    Err(e) => {
+      validator.error("container", None, e.to_string());
+      None
+    }
+    Ok((format, sfnt)) => {
+      container_format = Some(format);
+
+      let offsets = if read_u32(&sfnt, 0) == Some(TTCF_TAG) {
+        let num_fonts = read_u32(&sfnt, 8).unwrap_or(0);
+        if num_fonts == 0 || num_fonts > MAX_COLLECTION_FACES {
+          validator.error("collection_header", None, format!("Collection has {} faces", num_fonts));
+        }
+        (0..num_fonts.min(MAX_COLLECTION_FACES))
+          .map(|index| read_u32(&sfnt, 12 + 4 * (index as usize)).map(|offset| offset as usize))
+          .collect::<Vec<_>>()
+      } else {
+        vec![Some(0)]
+      };
+
+      for (index, offset) in offsets.into_iter().enumerate() {
+        validator.face_index = Some(index as u32);
+        match offset {
+          Some(offset) => validator.check_face(&sfnt, index as u32, offset),
+          None => validator.error("collection_header", None, "Face offset is truncated".into()),
+        }
+      }
+      validator.face_index = None;
+
+      // Decoded WOFF data is rebuilt, so only plain sfnt files carry the
+      // original whole-file checksum.
+      if format == FontFormat::Ttf || format == FontFormat::Otf {
+        let sum = table_checksum(&sfnt);
+        if sum != CHECKSUM_MAGIC {
+          validator.warning(
+            "head_checksum_adjustment",
+            Some(b"head"),
+            format!("Whole-font checksum is {:#010x}, expected {:#010x}", sum, CHECKSUM_MAGIC)
+          );
+        }
+      }
+      Some((format, sfnt))
+    }
+  };
+
+  let errors = validator.issues
+    .iter()
+    .filter(|issue| issue.severity == Severity::Error)
+    .count();
+
+  let report = ValidationReport {
+    valid: errors == 0,
+    container_format,
+    errors,
+    warnings: validator.issues.len() - errors,
+    issues: validator.issues,
+  };
+  (report, decoded)
+}
@@ -0,0 +1,187 @@
+use serde::{ Deserialize, Serialize };
+use ttf_parser::{ stat::AxisValueSubtable, Face, Tag };
+
+use crate::{ binary::{ read_fixed, read_u16 }, metadata::get_name_string };
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VariationAxis {
+  pub tag: String,
+  pub name: String,
+  pub min_value: f32,
+  pub default_value: f32,
+  pub max_value: f32,
+  pub hidden: bool,
+  /// `avar` segment map as (default normalized, remapped normalized) pairs.
+  pub avar_mapping: Vec<(f32, f32)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedInstance {
+  pub subfamily: String,
+  pub postscript_name: Option<String>,
+  /// One coordinate per axis, in the same order as `axes`.
+  pub coordinates: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatAxisValue {
+  pub axis_tag: String,
+  pub name: String,
+  pub value: Option<f32>,
+  pub range: Option<(f32, f32)>,
+  pub elidable: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VariationInfo {
+  pub axes: Vec<VariationAxis>,
+  pub instances: Vec<NamedInstance>,
+  pub stat_values: Vec<StatAxisValue>,
+}
+
+fn get_avar_mappings(face: &Face) -> Vec<Vec<(f32, f32)>> {
+  let Some(avar) = face.tables().avar else {
+    return Vec::new();
+  };
+
+  avar.segment_maps
+    .into_iter()
+    .map(|map| {
+      map
+        .into_iter()
+        .map(|m| ((m.from_coordinate as f32) / 16384.0, (m.to_coordinate as f32) / 16384.0))
+        .collect()
+    })
+    .collect()
+}
+
+/// ttf-parser only exposes the `fvar` axis array, so the instance records are
+/// read straight from the raw table.
+fn get_named_instances(face: &Face) -> Vec<NamedInstance> {
+  let Some(data) = face.raw_face().table(Tag::from_bytes(b"fvar")) else {
+    return Vec::new();
+  };
+
+  let parse = || -> Option<Vec<NamedInstance>> {
+    let axes_offset = read_u16(data, 4)? as usize;
+    let axis_count = read_u16(data, 8)? as usize;
+    let axis_size = read_u16(data, 10)? as usize;
+    let instance_count = read_u16(data, 12)? as usize;
+    let instance_size = read_u16(data, 14)? as usize;
+    let has_postscript_name = instance_size >= axis_count * 4 + 6;
+
+    let mut instances = Vec::with_capacity(instance_count);
+    let mut offset = axes_offset + axis_count * axis_size;
+    for _ in 0..instance_count {
+      let subfamily_name_id = read_u16(data, offset)?;
+      let coordinates = (0..axis_count)
+        .map(|i| read_fixed(data, offset + 4 + i * 4))
+        .collect::<Option<Vec<f32>>>()?;
+      let postscript_name = if has_postscript_name {
+        let name_id = read_u16(data, offset + 4 + axis_count * 4)?;
+        Some(get_name_string(face, name_id)).filter(|name| name_id != 0xffff && !name.is_empty())
+      } else {
+        None
+      };
+
+      instances.push(NamedInstance {
+        subfamily: get_name_string(face, subfamily_name_id),
+        postscript_name,
+        coordinates,
+      });
+      offset += instance_size;
+    }
+
+    Some(instances)
+  };
+
+  parse().unwrap_or_default()
+}
+
+fn get_stat_values(face: &Face) -> Vec<StatAxisValue> {
+  let Some(stat) = face.tables().stat else {
+    return Vec::new();
+  };
+
+  let axis_tag = |index: u16| {
+    stat.axes
+      .get(index)
+      .map(|axis| axis.tag.to_string())
+      .unwrap_or_default()
+  };
+
+  let mut values = Vec::new();
+  for subtable in stat.subtables() {
+    let name = get_name_string(face, subtable.name_id());
+    let elidable = subtable.is_elidable();
+
+    match subtable {
+      AxisValueSubtable::Format1(v) =>
+        values.push(StatAxisValue {
+          axis_tag: axis_tag(v.axis_index),
+          name,
+          value: Some(v.value.0),
+          range: None,
+          elidable,
+        }),
+      AxisValueSubtable::Format2(v) =>
+        values.push(StatAxisValue {
+          axis_tag: axis_tag(v.axis_index),
+          name,
+          value: Some(v.nominal_value.0),
+          range: Some((v.range_min_value.0, v.range_max_value.0)),
+          elidable,
+        }),
+      AxisValueSubtable::Format3(v) =>
+        values.push(StatAxisValue {
+          axis_tag: axis_tag(v.axis_index),
+          name,
+          value: Some(v.value.0),
+          range: None,
+          elidable,
+        }),
+      AxisValueSubtable::Format4(v) => {
+        for axis_value in v.values {
+          values.push(StatAxisValue {
+            axis_tag: axis_tag(axis_value.axis_index),
+            name: name.clone(),
+            value: Some(axis_value.value.0),
+            range: None,
+            elidable,
+          });
+        }
+      }
+    }
+  }
+
+  values
+}
+
+/// Returns `None` for static faces so they keep a NULL `variations` column.
+pub fn extract_variations(face: &Face) -> Option<VariationInfo> {
+  if !face.is_variable() {
+    return None;
+  }
+
+  let avar_mappings = get_avar_mappings(face);
+  let axes = face
+    .variation_axes()
+    .into_iter()
+    .enumerate()
+    .map(|(index, axis)| VariationAxis {
+      tag: axis.tag.to_string(),
+      name: get_name_string(face, axis.name_id),
+      min_value: axis.min_value,
+      default_value: axis.def_value,
+      max_value: axis.max_value,
+      hidden: axis.hidden,
+      avar_mapping: avar_mappings.get(index).cloned().unwrap_or_default(),
+    })
+    .collect();
+
+  Some(VariationInfo {
+    axes,
+    instances: get_named_instances(face),
+    stat_values: get_stat_values(face),
+  })
+}